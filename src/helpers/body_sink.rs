@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::helpers::multipart_upload::MultipartUpload;
+
+/// Where a request body ended up after reading.
+#[derive(Debug)]
+pub enum BodyStorage {
+    Memory(Vec<u8>),
+    File(SpooledFile),
+    /// Location of a body a `BodySink` stored elsewhere, as an object key, like the
+    /// `S3MultipartSink` of the `s3` feature.
+    External(String),
+    /// Parts of a `multipart/form-data` body split while reading.
+    Multipart(MultipartUpload),
+}
+
+/// Temp file holding a spilled body, removed on drop unless persisted.
+#[derive(Debug)]
+pub struct SpooledFile {
+    path: PathBuf,
+    len: usize,
+    keep: bool,
+}
+
+impl SpooledFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub async fn persist<P: AsRef<Path>>(mut self, to: P) -> Result<PathBuf, Box<dyn Error>> {
+        tokio::fs::rename(&self.path, to.as_ref()).await?;
+        self.keep = true;
+        Ok(to.as_ref().to_path_buf())
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[async_trait]
+pub trait BodySink: Send {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn finish(self: Box<Self>) -> Result<BodyStorage, Box<dyn Error + Send + Sync>>;
+}
+
+#[derive(Default)]
+pub struct MemorySink {
+    bytes: Vec<u8>,
+}
+
+#[async_trait]
+impl BodySink for MemorySink {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.bytes.extend_from_slice(bytes);
+        Ok(())
+    }
+    async fn finish(self: Box<Self>) -> Result<BodyStorage, Box<dyn Error + Send + Sync>> {
+        Ok(BodyStorage::Memory(self.bytes))
+    }
+}
+
+pub struct FileSink {
+    file: File,
+    spooled: SpooledFile,
+}
+
+static SPOOL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl FileSink {
+    pub async fn new<P: AsRef<Path>>(dir: P) -> Result<FileSink, Box<dyn Error + Send + Sync>> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or(0);
+        let path = dir.as_ref().join(format!(
+            "atomic_http-{}-{}-{}.body",
+            std::process::id(),
+            nanos,
+            SPOOL_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::create(&path).await?;
+        Ok(FileSink {
+            file,
+            spooled: SpooledFile {
+                path,
+                len: 0,
+                keep: false,
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl BodySink for FileSink {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.file.write_all(bytes).await?;
+        self.spooled.len += bytes.len();
+        Ok(())
+    }
+    async fn finish(mut self: Box<Self>) -> Result<BodyStorage, Box<dyn Error + Send + Sync>> {
+        self.file.flush().await?;
+        Ok(BodyStorage::File(self.spooled))
+    }
+}
+
+type SinkFn = dyn Fn(usize) -> Box<dyn BodySink> + Send + Sync;
+
+/// Creates the sink for bodies above `Options.body_spool_threshold`, gets the content length.
+#[derive(Clone)]
+pub struct BodySinkFactory(Arc<SinkFn>);
+
+impl BodySinkFactory {
+    pub fn new<F>(factory: F) -> BodySinkFactory
+    where
+        F: Fn(usize) -> Box<dyn BodySink> + Send + Sync + 'static,
+    {
+        BodySinkFactory(Arc::new(factory))
+    }
+
+    pub fn create(&self, content_length: usize) -> Box<dyn BodySink> {
+        (self.0)(content_length)
+    }
+}
+
+impl fmt::Debug for BodySinkFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodySinkFactory")
+    }
+}
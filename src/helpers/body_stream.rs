@@ -0,0 +1,188 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::Sleep;
+
+use crate::helpers::chunked::ChunkedDecoder;
+use crate::helpers::throttle::Throttle;
+use crate::{Body, Writer};
+
+/// Socket reads of a streamed body are at most this large.
+const READ_SIZE: usize = 8192;
+
+enum Framing {
+    /// Bytes still on the socket.
+    Length(usize),
+    Chunked(ChunkedDecoder),
+}
+
+/// Request body left on the socket by `Options::stream_request_body`, read with `Body::reader`.
+pub struct PendingBody {
+    /// Payload read but not handed out yet, decoded for chunked bodies.
+    buffered: Vec<u8>,
+    position: usize,
+    framing: Framing,
+    throttle: Option<Throttle>,
+    /// Wait of `throttle` before the next socket read.
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl PendingBody {
+    /// `buffered` is the part of a Content-Length body read with the head.
+    pub(crate) fn length(buffered: Vec<u8>, content_length: usize) -> PendingBody {
+        PendingBody {
+            framing: Framing::Length(content_length - buffered.len()),
+            buffered,
+            position: 0,
+            throttle: None,
+            sleep: None,
+        }
+    }
+
+    /// `buffered` is what `decoder` decoded from the bytes read with the head.
+    pub(crate) fn chunked(buffered: Vec<u8>, decoder: ChunkedDecoder) -> PendingBody {
+        PendingBody {
+            buffered,
+            position: 0,
+            framing: Framing::Chunked(decoder),
+            throttle: None,
+            sleep: None,
+        }
+    }
+
+    /// Socket reads are limited by `throttle`, `read_rate_limit` of the request.
+    pub(crate) fn throttled(mut self, throttle: Option<Throttle>) -> PendingBody {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Nothing of the body is left on the socket.
+    pub(crate) fn is_received(&self) -> bool {
+        match &self.framing {
+            Framing::Length(remaining) => *remaining == 0,
+            Framing::Chunked(decoder) => decoder.is_done(),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.position == self.buffered.len() && self.is_received()
+    }
+}
+
+impl std::fmt::Debug for PendingBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingBody")
+            .field("buffered", &(self.buffered.len() - self.position))
+            .finish()
+    }
+}
+
+/// `AsyncRead` over a request body, reading a streamed body from the connection of `writer`.
+pub struct BodyReader<'a> {
+    body: &'a mut Body,
+    writer: &'a mut Writer,
+    /// Position in `Body.bytes` when the body was read before `parse_request` returned.
+    offset: usize,
+}
+
+impl Body {
+    /// Read the body as it arrives when it was left on the socket, or the bytes already read.
+    /// The response can be sent after the reader hits EOF, a body left unread closes the
+    /// connection after the response.
+    pub fn reader<'a>(&'a mut self, writer: &'a mut Writer) -> BodyReader<'a> {
+        BodyReader {
+            body: self,
+            writer,
+            offset: 0,
+        }
+    }
+}
+
+impl AsyncRead for BodyReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let Some(pending) = this.body.pending.as_mut() else {
+            let rest = &this.body.bytes[this.offset.min(this.body.bytes.len())..];
+            let n = rest.len().min(buf.remaining());
+            buf.put_slice(&rest[..n]);
+            this.offset += n;
+            return Poll::Ready(Ok(()));
+        };
+        loop {
+            if pending.position < pending.buffered.len() {
+                let rest = &pending.buffered[pending.position..];
+                let n = rest.len().min(buf.remaining());
+                buf.put_slice(&rest[..n]);
+                pending.position += n;
+                break;
+            }
+            if pending.is_done() {
+                break;
+            }
+            if let Some(sleep) = pending.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                pending.sleep = None;
+            }
+            pending.buffered.clear();
+            pending.position = 0;
+            let size = match pending.framing {
+                Framing::Length(remaining) => remaining.min(READ_SIZE),
+                Framing::Chunked(_) => READ_SIZE,
+            };
+            let mut chunk = vec![0; size];
+            let mut read_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.writer.stream).poll_read(cx, &mut read_buf))?;
+            let n = read_buf.filled().len();
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            chunk.truncate(n);
+            if let Some(delay) = pending.throttle.as_mut().and_then(|t| t.consume(n)) {
+                pending.sleep = Some(Box::pin(tokio::time::sleep(delay)));
+            }
+            match &mut pending.framing {
+                Framing::Length(remaining) => {
+                    *remaining -= n;
+                    pending.buffered = chunk;
+                }
+                Framing::Chunked(decoder) => {
+                    let consumed = decoder
+                        .feed(&chunk, &mut pending.buffered)
+                        .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))?;
+                    let options = &this.writer.options;
+                    let limit = [options.max_chunked_size, options.max_body_size]
+                        .into_iter()
+                        .filter(|&limit| limit > 0)
+                        .min();
+                    if let Some(limit) = limit.filter(|&limit| decoder.declared() > limit) {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("chunked body exceeds {} bytes", limit),
+                        )));
+                    }
+                    if decoder.is_done() {
+                        // The start of a pipelined request.
+                        let rest = &chunk[consumed..];
+                        this.writer.options.pipelined.extend_from_slice(rest);
+                    }
+                }
+            }
+        }
+        if pending.is_done() {
+            if let Framing::Chunked(decoder) = &mut pending.framing {
+                this.body.trailers = decoder.take_trailers();
+            }
+            this.body.pending = None;
+            this.offset = this.body.bytes.len();
+            this.writer.unread_body = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,27 @@
+use http::header::CACHE_CONTROL;
+use http::{HeaderMap, HeaderValue};
+
+/// Replace or add one `Cache-Control` directive, keeping the others.
+pub(crate) fn set_directive(headers: &mut HeaderMap, name: &str, value: Option<String>) {
+    let mut directives: Vec<String> = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim())
+        .filter(|directive| {
+            let directive_name = directive.split('=').next().unwrap_or("").trim();
+            !directive.is_empty()
+                && !directive_name.eq_ignore_ascii_case(name)
+                && !directive_name.eq_ignore_ascii_case("no-store")
+        })
+        .map(String::from)
+        .collect();
+    directives.push(match value {
+        Some(value) => format!("{}={}", name, value),
+        None => name.to_string(),
+    });
+    if let Ok(value) = HeaderValue::from_str(&directives.join(", ")) {
+        headers.insert(CACHE_CONTROL, value);
+    }
+}
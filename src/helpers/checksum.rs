@@ -0,0 +1,81 @@
+use std::error::Error;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+/// Field name of the checksum, header on buffered bodies and trailer on chunked streams.
+pub const CONTENT_DIGEST: &str = "content-digest";
+
+/// Algorithm for `Content-Digest`, the successor of the `Digest` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Crc32c,
+}
+
+impl ChecksumAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha-256",
+            ChecksumAlgorithm::Crc32c => "crc32c",
+        }
+    }
+
+    pub fn checksum(&self) -> Checksum {
+        Checksum {
+            state: match self {
+                ChecksumAlgorithm::Sha256 => State::Sha256(Sha256::new()),
+                ChecksumAlgorithm::Crc32c => State::Crc32c(0),
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for ChecksumAlgorithm {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "sha-256" | "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "crc32c" => Ok(ChecksumAlgorithm::Crc32c),
+            _ => Err(format!("unknown checksum algorithm: {}", s).into()),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum State {
+    Sha256(Sha256),
+    Crc32c(u32),
+}
+
+/// Running checksum of a body sent in pieces.
+#[derive(Clone)]
+pub struct Checksum {
+    state: State,
+}
+
+impl Checksum {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match &mut self.state {
+            State::Sha256(hasher) => hasher.update(bytes),
+            State::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, bytes),
+        }
+    }
+
+    /// `Content-Digest` value, as `sha-256=:<base64>:`.
+    pub fn finish(self) -> String {
+        let (name, digest) = match self.state {
+            State::Sha256(hasher) => ("sha-256", hasher.finalize().to_vec()),
+            State::Crc32c(crc) => ("crc32c", crc.to_be_bytes().to_vec()),
+        };
+        format!("{}=:{}:", name, STANDARD.encode(digest))
+    }
+}
+
+/// `Content-Digest` value of a whole body.
+pub fn content_digest(algorithm: ChecksumAlgorithm, bytes: &[u8]) -> String {
+    let mut checksum = algorithm.checksum();
+    checksum.update(bytes);
+    checksum.finish()
+}
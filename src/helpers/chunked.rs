@@ -0,0 +1,335 @@
+use std::error::Error;
+
+use http::header::{CONTENT_LENGTH, TRAILER, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Response};
+use tokio::io::AsyncWriteExt;
+
+#[cfg(feature = "checksum")]
+use crate::helpers::checksum::{Checksum, CONTENT_DIGEST};
+use crate::helpers::traits::bytes::ByteSlice;
+use crate::helpers::traits::http_response::{get_head, send_bytes};
+use crate::{Options, Writer};
+
+const MAX_LINE_LENGTH: usize = 4096;
+
+enum State {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+/// Incremental `Transfer-Encoding: chunked` decoder, fed as bytes arrive.
+pub(crate) struct ChunkedDecoder {
+    state: State,
+    line: Vec<u8>,
+    trailers: HeaderMap,
+    decoded: usize,
+    chunks: usize,
+    trailer_size: usize,
+    max_extension_size: usize,
+    max_trailer_size: usize,
+    max_chunk_count: usize,
+}
+
+impl ChunkedDecoder {
+    pub(crate) fn new(options: &Options) -> ChunkedDecoder {
+        ChunkedDecoder {
+            state: State::Size,
+            line: Vec::new(),
+            trailers: HeaderMap::new(),
+            decoded: 0,
+            chunks: 0,
+            trailer_size: 0,
+            max_extension_size: options.max_chunk_extension_size,
+            max_trailer_size: options.max_trailer_size,
+            max_chunk_count: options.max_chunk_count,
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    pub(crate) fn decoded(&self) -> usize {
+        self.decoded
+    }
+
+    /// Decoded bytes plus the rest of the current chunk, for early size checks.
+    pub(crate) fn declared(&self) -> usize {
+        match self.state {
+            State::Data(remaining) => self.decoded.saturating_add(remaining),
+            _ => self.decoded,
+        }
+    }
+
+    pub(crate) fn take_trailers(&mut self) -> HeaderMap {
+        std::mem::take(&mut self.trailers)
+    }
+
+    /// Append the payload in `input` to `output` and return how many bytes were consumed,
+    /// bytes after the last chunk are left for the next request.
+    pub(crate) fn feed(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> Result<usize, String> {
+        let total = input.len();
+        while !input.is_empty() {
+            if let State::Data(remaining) = self.state {
+                let n = remaining.min(input.len());
+                output.extend_from_slice(&input[..n]);
+                self.decoded += n;
+                input = &input[n..];
+                self.state = match remaining - n {
+                    0 => State::DataEnd,
+                    remaining => State::Data(remaining),
+                };
+                continue;
+            }
+            if let State::Done = self.state {
+                break;
+            }
+
+            let Some(end) = input.iter().position(|&byte| byte == b'\n') else {
+                self.push_line(input)?;
+                break;
+            };
+            self.push_line(&input[..end])?;
+            input = &input[end + 1..];
+            let line = std::mem::take(&mut self.line);
+            let line = line.strip_suffix(b"\r").unwrap_or(&line);
+            self.on_line(line)?;
+        }
+        Ok(total - input.len())
+    }
+
+    fn push_line(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.line.len() + bytes.len() > MAX_LINE_LENGTH {
+            return Err("chunk size or trailer line too long".into());
+        }
+        self.line.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn on_line(&mut self, line: &[u8]) -> Result<(), String> {
+        match self.state {
+            State::Size => {
+                // Chunk extensions after `;` are ignored, but still bounded.
+                let size = line.split(|&byte| byte == b';').next().unwrap_or(line);
+                let extension_size = line.len() - size.len();
+                if self.max_extension_size > 0 && extension_size > self.max_extension_size {
+                    return Err(format!(
+                        "chunk extension of {} bytes is over {}",
+                        extension_size, self.max_extension_size
+                    ));
+                }
+                self.chunks += 1;
+                if self.max_chunk_count > 0 && self.chunks > self.max_chunk_count {
+                    return Err(format!("more than {} chunks", self.max_chunk_count));
+                }
+                let size = std::str::from_utf8(size.trim_whitespace())
+                    .ok()
+                    .and_then(|size| usize::from_str_radix(size, 16).ok())
+                    .ok_or_else(|| {
+                        format!("invalid chunk size `{}`", String::from_utf8_lossy(line))
+                    })?;
+                self.state = match size {
+                    0 => State::Trailers,
+                    size => State::Data(size),
+                };
+            }
+            State::DataEnd => {
+                if !line.is_empty() {
+                    return Err("missing CRLF after chunk data".into());
+                }
+                self.state = State::Size;
+            }
+            State::Trailers => {
+                if line.is_empty() {
+                    self.state = State::Done;
+                    return Ok(());
+                }
+                self.trailer_size += line.len();
+                if self.max_trailer_size > 0 && self.trailer_size > self.max_trailer_size {
+                    return Err(format!(
+                        "trailer fields are over {} bytes",
+                        self.max_trailer_size
+                    ));
+                }
+                let Some(colon) = line.iter().position(|&byte| byte == b':') else {
+                    return Err("trailer line without `:` separator".into());
+                };
+                if let (Ok(name), Ok(value)) = (
+                    HeaderName::from_bytes(line[..colon].trim_whitespace()),
+                    HeaderValue::from_bytes(line[colon + 1..].trim_whitespace()),
+                ) {
+                    self.trailers.append(name, value);
+                }
+            }
+            State::Data(_) | State::Done => {}
+        }
+        Ok(())
+    }
+}
+
+/// `Transfer-Encoding` with `chunked` as the final coding.
+pub(crate) fn is_chunked(value: &[u8]) -> bool {
+    value
+        .split(|&byte| byte == b',')
+        .next_back()
+        .map(|coding| coding.trim_whitespace().eq_ignore_ascii_case(b"chunked"))
+        .unwrap_or(false)
+}
+
+/// Chunked response streamed as data is produced, trailer fields go after the last chunk.
+pub struct ChunkedStream {
+    writer: Writer,
+    head_only: bool,
+    #[cfg(feature = "checksum")]
+    checksum: Option<Checksum>,
+}
+
+impl ChunkedStream {
+    pub async fn new(response: Response<Writer>) -> Result<ChunkedStream, Box<dyn Error>> {
+        ChunkedStream::with_trailers(response, &[]).await
+    }
+
+    /// Announce the trailer field names in a `Trailer` header.
+    pub async fn with_trailers(
+        mut response: Response<Writer>,
+        trailers: &[&str],
+    ) -> Result<ChunkedStream, Box<dyn Error>> {
+        response.headers_mut().remove(CONTENT_LENGTH);
+        response
+            .headers_mut()
+            .insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        #[cfg(feature = "checksum")]
+        let checksum = response
+            .body()
+            .options
+            .response_checksum
+            .map(|algorithm| algorithm.checksum());
+        #[cfg(feature = "checksum")]
+        let mut trailers = trailers.to_vec();
+        #[cfg(feature = "checksum")]
+        if checksum.is_some() && !trailers.contains(&CONTENT_DIGEST) {
+            trailers.push(CONTENT_DIGEST);
+        }
+        if !trailers.is_empty() {
+            response
+                .headers_mut()
+                .insert(TRAILER, trailers.join(", ").parse()?);
+        }
+        let head = get_head(&response)?;
+        let mut writer = response.into_body();
+        send_bytes(&mut writer.stream, head.as_bytes()).await?;
+        writer.stream.flush().await?;
+        Ok(ChunkedStream {
+            head_only: writer.request_method == Method::HEAD,
+            writer,
+            #[cfg(feature = "checksum")]
+            checksum,
+        })
+    }
+
+    pub async fn send(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        if bytes.is_empty() || self.head_only {
+            return Ok(());
+        }
+        #[cfg(feature = "checksum")]
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(bytes);
+        }
+        let writer = &mut self.writer;
+        writer
+            .send(format!("{:x}\r\n", bytes.len()).as_bytes())
+            .await?;
+        writer.send(bytes).await?;
+        writer.send(b"\r\n").await?;
+        writer.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn finish(self) -> Result<(), Box<dyn Error>> {
+        self.finish_with_trailers(&HeaderMap::new()).await
+    }
+
+    pub async fn finish_with_trailers(
+        mut self,
+        trailers: &HeaderMap,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.head_only {
+            return Ok(());
+        }
+        let mut end = String::from("0\r\n");
+        for (key, value) in trailers.iter() {
+            end.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+        }
+        #[cfg(feature = "checksum")]
+        if let Some(checksum) = self.checksum.take() {
+            if !trailers.contains_key(CONTENT_DIGEST) {
+                end.push_str(&format!("{}: {}\r\n", CONTENT_DIGEST, checksum.finish()));
+            }
+        }
+        end.push_str("\r\n");
+        send_bytes(&mut self.writer.stream, end.as_bytes()).await?;
+        self.writer.stream.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChunkedDecoder;
+    use crate::Options;
+
+    fn decode(options: &Options, input: &[u8]) -> Result<Vec<u8>, String> {
+        let mut decoder = ChunkedDecoder::new(options);
+        let mut output = Vec::new();
+        decoder.feed(input, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn decodes_across_feeds() {
+        let options = Options::new();
+        let mut decoder = ChunkedDecoder::new(&options);
+        let mut output = Vec::new();
+        let input = b"5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nx-sum: 11\r\n\r\nGET / HTTP/1.1";
+        for part in input[..42].chunks(3) {
+            decoder.feed(part, &mut output).unwrap();
+            assert!(!decoder.is_done());
+        }
+        // Only the final CRLF is consumed, the pipelined request is left.
+        assert_eq!(decoder.feed(&input[42..], &mut output), Ok(1));
+        assert!(decoder.is_done());
+        assert_eq!(output, b"hello world");
+        assert_eq!(decoder.decoded(), 11);
+        assert_eq!(decoder.take_trailers()["x-sum"], "11");
+    }
+
+    #[test]
+    fn refuses_malformed_chunks() {
+        let options = Options::new();
+        assert!(decode(&options, b"zz\r\n").is_err());
+        assert!(decode(&options, b"1\r\nab\r\n").is_err());
+        assert!(decode(&options, b"0\r\nno separator\r\n").is_err());
+        assert!(decode(&options, &[b'1'; 5000]).is_err());
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let mut options = Options::new();
+        options.max_chunk_count = 2;
+        assert!(decode(&options, b"1\r\na\r\n1\r\nb\r\n").is_ok());
+        assert!(decode(&options, b"1\r\na\r\n1\r\nb\r\n0\r\n\r\n").is_err());
+
+        let mut options = Options::new();
+        options.max_chunk_extension_size = 4;
+        assert!(decode(&options, b"1;abc\r\na\r\n").is_ok());
+        assert!(decode(&options, b"1;abcdefgh\r\na\r\n").is_err());
+
+        let mut options = Options::new();
+        options.max_trailer_size = 8;
+        assert!(decode(&options, b"0\r\nx-a: 1\r\n\r\n").is_ok());
+        assert!(decode(&options, b"0\r\nx-long: 123456789\r\n\r\n").is_err());
+    }
+}
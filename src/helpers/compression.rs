@@ -0,0 +1,264 @@
+use std::io::{self, Write};
+#[cfg(feature = "response_file")]
+use std::path::{Component, Path, PathBuf};
+#[cfg(feature = "response_file")]
+use std::time::UNIX_EPOCH;
+
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use http::{Response, StatusCode};
+
+#[cfg(feature = "response_file")]
+use crate::helpers::file_cache::FileInfo;
+use crate::helpers::precompressed::coding_quality;
+#[cfg(feature = "response_file")]
+use crate::helpers::random::random_hex;
+#[cfg(feature = "response_file")]
+use crate::helpers::traits::http_response::before_deadline;
+use crate::Writer;
+
+/// Files above this size are sent as is, they would have to be compressed in memory.
+#[cfg(feature = "response_file")]
+pub(crate) const MAX_FILE_SIZE: u64 = 16 * 1048576;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "zstd")]
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+/// Server preference, used when the client gives several codings the same q-value.
+const PREFERENCE: &[Encoding] = &[
+    #[cfg(feature = "brotli")]
+    Encoding::Brotli,
+    #[cfg(feature = "zstd")]
+    Encoding::Zstd,
+    Encoding::Gzip,
+    Encoding::Deflate,
+];
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(bytes)?;
+                Ok(encoder.into_inner())
+            }
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => zstd::bulk::compress(bytes, 3),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Best supported coding in `Accept-Encoding` by q-value, `*` covers codings not listed.
+pub(crate) fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in PREFERENCE {
+        let Some(quality) = coding_quality(accept_encoding, encoding.as_str()) else {
+            continue;
+        };
+        match best {
+            Some((_, best_quality)) if best_quality >= quality => {}
+            _ => best = Some((*encoding, quality)),
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Media types that are already compressed gain nothing.
+pub(crate) fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    if content_type.starts_with("image/svg") {
+        return true;
+    }
+    !["image/", "video/", "audio/", "font/woff"]
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix))
+        && ![
+            "application/zip",
+            "application/gzip",
+            "application/x-gzip",
+            "application/zstd",
+            "application/octet-stream",
+        ]
+        .iter()
+        .any(|media_type| content_type.starts_with(media_type))
+}
+
+/// Coding to apply to a `len` byte body, `None` when compression is off or would not help.
+pub(crate) fn get_compression(
+    response: &Response<Writer>,
+    len: usize,
+    content_type: Option<&str>,
+) -> Option<Encoding> {
+    let writer = response.body();
+    let options = &writer.options;
+    if !options.compress_responses
+        || len == 0
+        || len < options.compression_min_size
+        || response.headers().contains_key(CONTENT_ENCODING)
+        || response.status() == StatusCode::PARTIAL_CONTENT
+        || response.status() == StatusCode::NO_CONTENT
+        || response.status() == StatusCode::NOT_MODIFIED
+        || response.status().is_informational()
+    {
+        return None;
+    }
+    let content_type = content_type.or_else(|| {
+        response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+    });
+    let allowed = |content_type: &str| match options.compression_types.is_empty() {
+        true => is_compressible(content_type),
+        false => options.compression_types.iter().any(|allowed| {
+            content_type
+                .to_ascii_lowercase()
+                .starts_with(&allowed.to_ascii_lowercase())
+        }),
+    };
+    if !content_type.map(allowed).unwrap_or(true) {
+        return None;
+    }
+    writer
+        .request_headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate)
+}
+
+/// Read and compress `path`, through `cache_dir` when it is set. Cache errors only cost the
+/// compression, `Elapsed` when the deadline passed first.
+#[cfg(feature = "response_file")]
+pub(crate) async fn encode_file(
+    path: &str,
+    info: FileInfo,
+    encoding: Encoding,
+    deadline: Option<std::time::Instant>,
+    cache_dir: Option<&Path>,
+) -> Result<io::Result<Vec<u8>>, tokio::time::error::Elapsed> {
+    let cache_path = cache_dir.and_then(|dir| disk_cache_path(dir, path, info, encoding));
+    if let Some(cache_path) = &cache_path {
+        if let Ok(bytes) = before_deadline(deadline, tokio::fs::read(cache_path)).await? {
+            return Ok(Ok(bytes));
+        }
+    }
+    let bytes = match before_deadline(deadline, tokio::fs::read(path)).await? {
+        Ok(bytes) => bytes,
+        Err(e) => return Ok(Err(e)),
+    };
+    let encoded = match encoding.encode(&bytes) {
+        Ok(encoded) => encoded,
+        Err(e) => return Ok(Err(e)),
+    };
+    if let Some(cache_path) = &cache_path {
+        if let Err(e) = write_disk_cache(cache_path, &encoded).await {
+            dev_print!("compression cache {}: {}", cache_path.display(), e);
+        }
+    }
+    Ok(Ok(encoded))
+}
+
+/// `<cache_dir>/<dirs of path>/<name>.<mtime>-<len>.<coding>`, `None` without a modification
+/// time. Only the normal components of `path` are kept, so entries stay below `cache_dir`.
+#[cfg(feature = "response_file")]
+fn disk_cache_path(
+    cache_dir: &Path,
+    path: &str,
+    info: FileInfo,
+    encoding: Encoding,
+) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let name = path.file_name()?.to_str()?;
+    let modified = info.modified?.duration_since(UNIX_EPOCH).ok()?;
+    let file_name = format!(
+        "{}.{}-{:x}.{}",
+        name,
+        modified.as_nanos(),
+        info.len,
+        encoding.as_str()
+    );
+    let dirs = path
+        .parent()?
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        });
+    let mut cache_path = cache_dir.to_path_buf();
+    cache_path.extend(dirs);
+    Some(cache_path.join(file_name))
+}
+
+/// Write through a temporary file so readers never see half an entry, then drop the entries of
+/// older versions of the same file and coding.
+#[cfg(feature = "response_file")]
+async fn write_disk_cache(cache_path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let (Some(dir), Some(file_name)) = (
+        cache_path.parent(),
+        cache_path.file_name().and_then(|name| name.to_str()),
+    ) else {
+        return Ok(());
+    };
+    tokio::fs::create_dir_all(dir).await?;
+    let temporary = dir.join(format!(".{}.{}", file_name, random_hex(8)));
+    tokio::fs::write(&temporary, bytes).await?;
+    if let Err(e) = tokio::fs::rename(&temporary, cache_path).await {
+        let _ = tokio::fs::remove_file(&temporary).await;
+        return Err(e);
+    }
+
+    // `name.<mtime>-<len>.<coding>`, split from the right as the name may hold dots.
+    let version_of = |entry: &str| -> Option<(String, String)> {
+        let (rest, coding) = entry.rsplit_once('.')?;
+        let (name, version) = rest.rsplit_once('.')?;
+        let (modified, len) = version.split_once('-')?;
+        let is_version = !modified.is_empty()
+            && modified.bytes().all(|byte| byte.is_ascii_digit())
+            && !len.is_empty()
+            && len.bytes().all(|byte| byte.is_ascii_hexdigit());
+        is_version.then(|| (name.to_string(), coding.to_string()))
+    };
+    let Some(current) = version_of(file_name) else {
+        return Ok(());
+    };
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(entry_name) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        if entry_name != file_name && version_of(&entry_name).as_ref() == Some(&current) {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::time::{Duration, SystemTime};
+
+/// Current validators of the target resource, supplied by the handler.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    pub fn new() -> Validators {
+        Validators::default()
+    }
+
+    pub fn etag(mut self, etag: &str) -> Validators {
+        self.etag = Some(etag.into());
+        self
+    }
+
+    pub fn last_modified(mut self, last_modified: SystemTime) -> Validators {
+        self.last_modified = Some(last_modified);
+        self
+    }
+}
+
+pub(crate) fn etag_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+}
+
+fn opaque(tag: &str) -> (bool, &str) {
+    match tag.strip_prefix("W/") {
+        Some(tag) => (true, tag),
+        None => (false, tag),
+    }
+}
+
+pub(crate) fn strong_match(a: &str, b: &str) -> bool {
+    let (a_weak, a) = opaque(a);
+    let (b_weak, b) = opaque(b);
+    !a_weak && !b_weak && a == b
+}
+
+pub(crate) fn weak_match(a: &str, b: &str) -> bool {
+    opaque(a).1 == opaque(b).1
+}
+
+/// Http dates have second precision, drop the rest before comparing.
+pub(crate) fn truncate_secs(time: SystemTime) -> SystemTime {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => SystemTime::UNIX_EPOCH + Duration::from_secs(duration.as_secs()),
+        Err(_) => time,
+    }
+}
+
+/// `If-Match` then `If-Unmodified-Since`, RFC 9110 13.2.2 steps 1 and 2.
+pub(crate) fn is_write_precondition_ok(
+    if_match: Option<&str>,
+    if_unmodified_since: Option<&str>,
+    validators: &Validators,
+) -> bool {
+    if let Some(if_match) = if_match {
+        if if_match.trim() == "*" {
+            return validators.etag.is_some() || validators.last_modified.is_some();
+        }
+        return match &validators.etag {
+            Some(etag) => etag_list(if_match).any(|tag| strong_match(tag, etag)),
+            None => false,
+        };
+    }
+    if let (Some(since), Some(last_modified)) = (if_unmodified_since, validators.last_modified) {
+        if let Ok(since) = httpdate::parse_http_date(since) {
+            return truncate_secs(last_modified) <= since;
+        }
+    }
+    true
+}
+
+/// `If-Range` still names the current representation, RFC 9110 13.1.5. Dates must match exactly.
+pub(crate) fn is_if_range_fresh(if_range: &str, validators: &Validators) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return match &validators.etag {
+            Some(etag) => strong_match(if_range, etag),
+            None => false,
+        };
+    }
+    match (
+        httpdate::parse_http_date(if_range),
+        validators.last_modified,
+    ) {
+        (Ok(date), Some(last_modified)) => truncate_secs(last_modified) == date,
+        _ => false,
+    }
+}
+
+/// `If-None-Match` then `If-Modified-Since` for GET and HEAD, RFC 9110 13.2.2 steps 3 and 4.
+pub(crate) fn is_not_modified(
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    validators: &Validators,
+) -> bool {
+    if let Some(if_none_match) = if_none_match {
+        if if_none_match.trim() == "*" {
+            return true;
+        }
+        return match &validators.etag {
+            Some(etag) => etag_list(if_none_match).any(|tag| weak_match(tag, etag)),
+            None => false,
+        };
+    }
+    if let (Some(since), Some(last_modified)) = (if_modified_since, validators.last_modified) {
+        if let Ok(since) = httpdate::parse_http_date(since) {
+            return truncate_secs(last_modified) <= since;
+        }
+    }
+    false
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// `name=value` pairs of a `Cookie` header in order. Double quotes around a value are dropped,
+/// pairs without `=` or with an empty name are skipped.
+pub fn parse_cookies(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            let value = value.trim();
+            let value = match value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                true => &value[1..value.len() - 1],
+                false => value,
+            };
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Cookies of every `Cookie` header by name. Browsers send the most specific path first, so
+/// the first of duplicated names is kept.
+pub(crate) fn cookie_map<'a>(headers: impl Iterator<Item = &'a str>) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for (name, value) in headers.flat_map(parse_cookies) {
+        cookies.entry(name).or_insert(value);
+    }
+    cookies
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// `Set-Cookie` value, added with `ResponseUtil::set_cookie`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetCookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> SetCookie {
+        SetCookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Expires `name` on the client, path and domain have to match the original cookie.
+    pub fn removal(name: &str) -> SetCookie {
+        SetCookie::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn path(mut self, path: &str) -> SetCookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> SetCookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> SetCookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> SetCookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> SetCookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> SetCookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Header value, `Err` when the name or value has characters a cookie can not carry.
+    pub fn to_header(&self) -> Result<String, Box<dyn Error>> {
+        if self.name.is_empty() || !self.name.bytes().all(is_token_byte) {
+            return Err(format!("invalid cookie name: {}", self.name).into());
+        }
+        if !self.value.bytes().all(is_cookie_octet) {
+            return Err(format!("invalid value for cookie {}", self.name).into());
+        }
+        let mut header = format!("{}={}", self.name, self.value);
+        let attributes = [&self.path, &self.domain];
+        for (name, value) in ["Path", "Domain"].iter().zip(attributes) {
+            if let Some(value) = value {
+                if value
+                    .bytes()
+                    .any(|byte| byte == b';' || byte.is_ascii_control())
+                {
+                    return Err(format!("invalid {} for cookie {}", name, self.name).into());
+                }
+                header.push_str(&format!("; {}={}", name, value));
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            header.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            header.push_str("; Secure");
+        }
+        if self.http_only {
+            header.push_str("; HttpOnly");
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => header.push_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => header.push_str("; SameSite=Lax"),
+            Some(SameSite::None) => header.push_str("; SameSite=None"),
+            None => {}
+        }
+        Ok(header)
+    }
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// RFC 6265 cookie-octet, no whitespace, `"`, `,`, `;` or `\`.
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cookie_pairs() {
+        let cookies = parse_cookies(r#"sid=abc; theme="dark" ; flag; =orphan; empty=; q="""#);
+        let expected = [("sid", "abc"), ("theme", "dark"), ("empty", ""), ("q", "")];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(cookies, expected);
+        assert_eq!(
+            parse_cookies(r#"lone=""#),
+            [("lone".to_string(), "\"".to_string())]
+        );
+        assert!(parse_cookies("").is_empty());
+    }
+
+    #[test]
+    fn keeps_the_first_duplicated_cookie() {
+        let cookies = cookie_map(["sid=narrow; a=1", "sid=wide; b=2"].into_iter());
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies["sid"], "narrow");
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(cookies["b"], "2");
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+#[cfg(feature = "private_cookies")]
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+#[cfg(feature = "private_cookies")]
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Server key for signed and private cookies. Give it 32 or more random bytes, the signing and
+/// encryption keys are derived from it.
+#[derive(Clone)]
+pub struct CookieKey {
+    signing: Arc<[u8; 32]>,
+    #[cfg(feature = "private_cookies")]
+    encryption: Arc<[u8; 32]>,
+}
+
+impl std::fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CookieKey").finish_non_exhaustive()
+    }
+}
+
+impl CookieKey {
+    pub fn new(master: impl AsRef<[u8]>) -> CookieKey {
+        let derive = |purpose: &str| -> [u8; 32] {
+            let mut mac = hmac_with(master.as_ref());
+            mac.update(purpose.as_bytes());
+            mac.finalize().into_bytes().into()
+        };
+        CookieKey {
+            signing: Arc::new(derive("atomic_http signed cookie")),
+            #[cfg(feature = "private_cookies")]
+            encryption: Arc::new(derive("atomic_http private cookie")),
+        }
+    }
+
+    /// `value.signature`, the signature covers the cookie name too.
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let signature = self.mac(name, value).finalize().into_bytes();
+        format!("{}.{}", value, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Value of a cookie made by `sign`, `None` when it was changed or moved to another name.
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, signature) = signed.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(name, value).verify_slice(&signature).ok()?;
+        Some(value.to_string())
+    }
+
+    /// AES-256-GCM with a random nonce, readable only with this key.
+    #[cfg(feature = "private_cookies")]
+    pub fn encrypt(&self, name: &str, value: &str) -> String {
+        let cipher = Aes256Gcm::new(self.encryption.as_slice().into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: name.as_bytes(),
+        };
+        // Encrypting into a Vec only fails for messages over 64GB.
+        let sealed = cipher.encrypt(&nonce, payload).unwrap_or_default();
+        let mut bytes = nonce.to_vec();
+        bytes.extend(sealed);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Value of a cookie made by `encrypt`, `None` when it was changed or moved to another name.
+    #[cfg(feature = "private_cookies")]
+    pub fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+        let bytes = URL_SAFE_NO_PAD.decode(encrypted).ok()?;
+        if bytes.len() < 12 {
+            return None;
+        }
+        let (nonce, sealed) = bytes.split_at(12);
+        let cipher = Aes256Gcm::new(self.encryption.as_slice().into());
+        let payload = Payload {
+            msg: sealed,
+            aad: name.as_bytes(),
+        };
+        let value = cipher.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+        String::from_utf8(value).ok()
+    }
+
+    fn mac(&self, name: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac = hmac_with(self.signing.as_slice());
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+}
+
+fn hmac_with(key: &[u8]) -> Hmac<Sha256> {
+    <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CookieKey;
+
+    #[test]
+    fn verifies_signed_values() {
+        let key = CookieKey::new("a master key of 32 or more bytes!");
+        let signed = key.sign("session", "user=7");
+        assert_eq!(key.verify("session", &signed).as_deref(), Some("user=7"));
+    }
+
+    #[test]
+    fn refuses_tampered_signatures() {
+        let key = CookieKey::new("a master key of 32 or more bytes!");
+        let signed = key.sign("session", "user=7");
+        let changed = signed.replacen("user=7", "user=8", 1);
+        assert_eq!(key.verify("session", &changed), None);
+        assert_eq!(key.verify("other", &signed), None);
+        assert_eq!(key.verify("session", "user=7"), None);
+        assert_eq!(key.verify("session", "user=7.!!"), None);
+        let other = CookieKey::new("another master key of 32 or more");
+        assert_eq!(other.verify("session", &signed), None);
+    }
+
+    #[cfg(feature = "private_cookies")]
+    #[test]
+    fn decrypts_encrypted_values() {
+        let key = CookieKey::new("a master key of 32 or more bytes!");
+        let encrypted = key.encrypt("session", "user=7");
+        assert!(!encrypted.contains("user=7"));
+        assert_ne!(encrypted, key.encrypt("session", "user=7"));
+        assert_eq!(
+            key.decrypt("session", &encrypted).as_deref(),
+            Some("user=7")
+        );
+    }
+
+    #[cfg(feature = "private_cookies")]
+    #[test]
+    fn refuses_tampered_ciphertexts() {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+        let key = CookieKey::new("a master key of 32 or more bytes!");
+        let encrypted = key.encrypt("session", "user=7");
+        let mut bytes = URL_SAFE_NO_PAD.decode(&encrypted).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let flipped = URL_SAFE_NO_PAD.encode(&bytes);
+        assert_eq!(key.decrypt("session", &flipped), None);
+        assert_eq!(key.decrypt("other", &encrypted), None);
+        assert_eq!(key.decrypt("session", "c2hvcnQ"), None);
+        let other = CookieKey::new("another master key of 32 or more");
+        assert_eq!(other.decrypt("session", &encrypted), None);
+    }
+}
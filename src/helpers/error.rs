@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::fmt;
+
+use http::{HeaderMap, StatusCode};
+
+#[derive(Debug, Clone)]
+pub struct ParseIssue {
+    pub offset: usize,
+    pub header: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ParseIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at byte {}", self.offset)?;
+        if let Some(header) = &self.header {
+            write!(f, " (header `{}`)", header)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Every problem found while parsing a request, with the status code answered to the client.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub status: StatusCode,
+    pub issues: Vec<ParseIssue>,
+    /// Extra headers sent with the error response.
+    pub headers: HeaderMap,
+}
+
+impl ParseError {
+    pub fn new(status: StatusCode) -> ParseError {
+        ParseError {
+            status,
+            issues: Vec::new(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, offset: usize, header: Option<&str>, message: impl Into<String>) {
+        self.issues.push(ParseIssue {
+            offset,
+            header: header.map(|header| header.into()),
+            message: message.into(),
+        });
+    }
+
+    /// Push an issue answered with a more specific status than the default.
+    pub fn push_status(
+        &mut self,
+        status: StatusCode,
+        offset: usize,
+        header: Option<&str>,
+        message: impl Into<String>,
+    ) {
+        if self.status == StatusCode::BAD_REQUEST {
+            self.status = status;
+        }
+        self.push(offset, header, message);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} with {} issue(s)", self.status, self.issues.len())?;
+        for issue in self.issues.iter() {
+            write!(f, "\n  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::time::error::Elapsed;
+
+use crate::helpers::memory::MemoryLease;
+use crate::helpers::traits::http_response::before_deadline;
+
+/// Above this many cached stats the expired ones are dropped, all of them if none expired.
+const MAX_STATS: usize = 16384;
+
+/// Length and modification time of a file to serve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileInfo {
+    pub(crate) len: u64,
+    pub(crate) modified: Option<SystemTime>,
+}
+
+impl From<&Metadata> for FileInfo {
+    fn from(metadata: &Metadata) -> FileInfo {
+        FileInfo {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+struct CachedBody {
+    info: FileInfo,
+    bytes: Arc<Vec<u8>>,
+    inserted: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    stats: HashMap<String, (Option<FileInfo>, Instant)>,
+    bodies: HashMap<String, CachedBody>,
+    used: usize,
+    tick: u64,
+    /// Cached bytes count against `memory_budget` like request buffers do.
+    lease: MemoryLease,
+}
+
+/// Stats and bytes of hot `response_file` files, least recently used bodies go first once
+/// `max_bytes` is reached. Nothing is older than `ttl`, so file changes show up after it.
+#[derive(Clone)]
+pub struct FileCache {
+    max_bytes: usize,
+    ttl: Duration,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl std::fmt::Debug for FileCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileCache")
+            .field("max_bytes", &self.max_bytes)
+            .field("ttl", &self.ttl)
+            .field("used_bytes", &self.used_bytes())
+            .finish()
+    }
+}
+
+impl FileCache {
+    pub fn new(max_bytes: usize, ttl: Duration) -> FileCache {
+        FileCache {
+            max_bytes,
+            ttl,
+            entries: Arc::new(Mutex::new(Entries::default())),
+        }
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.entries.lock().map(|entries| entries.used).unwrap_or(0)
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            *entries = Entries::default();
+        }
+    }
+
+    /// Files up to a quarter of `max_bytes` are kept, so one file can not flush the rest.
+    pub(crate) fn fits(&self, len: u64) -> bool {
+        len <= (self.max_bytes / 4) as u64
+    }
+
+    /// `Some(None)` is a cached miss.
+    fn stat(&self, path: &str) -> Option<Option<FileInfo>> {
+        let mut entries = self.entries.lock().ok()?;
+        match entries.stats.get(path) {
+            Some((info, inserted)) if inserted.elapsed() < self.ttl => Some(*info),
+            Some(_) => {
+                entries.stats.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put_stat(&self, path: &str, info: Option<FileInfo>) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.stats.len() >= MAX_STATS {
+            let ttl = self.ttl;
+            entries
+                .stats
+                .retain(|_, (_, inserted)| inserted.elapsed() < ttl);
+            if entries.stats.len() >= MAX_STATS {
+                entries.stats.clear();
+            }
+        }
+        entries
+            .stats
+            .insert(path.to_string(), (info, Instant::now()));
+    }
+
+    fn forget_stat(&self, path: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.stats.remove(path);
+        }
+    }
+
+    /// Bytes stored under `key` for the same version of the file.
+    pub(crate) fn body(&self, key: &str, info: FileInfo) -> Option<Arc<Vec<u8>>> {
+        let mut entries = self.entries.lock().ok()?;
+        entries.tick += 1;
+        let tick = entries.tick;
+        match entries.bodies.get_mut(key) {
+            Some(body) if body.info == info && body.inserted.elapsed() < self.ttl => {
+                body.last_used = tick;
+                return Some(body.bytes.clone());
+            }
+            Some(_) => {}
+            None => return None,
+        }
+        // Expired, or the file changed since it was cached.
+        if let Some(body) = entries.bodies.remove(key) {
+            entries.used -= body.bytes.len();
+            let used = entries.used;
+            entries.lease.resize(used);
+        }
+        None
+    }
+
+    pub(crate) fn put_body(&self, key: &str, info: FileInfo, bytes: Arc<Vec<u8>>) {
+        if !self.fits(bytes.len() as u64) {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if let Some(old) = entries.bodies.remove(key) {
+            entries.used -= old.bytes.len();
+        }
+        while entries.used + bytes.len() > self.max_bytes {
+            let Some(oldest) = entries
+                .bodies
+                .iter()
+                .min_by_key(|(_, body)| body.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(body) = entries.bodies.remove(&oldest) {
+                entries.used -= body.bytes.len();
+            }
+        }
+        entries.tick += 1;
+        let last_used = entries.tick;
+        entries.used += bytes.len();
+        let used = entries.used;
+        entries.lease.resize(used);
+        entries.bodies.insert(
+            key.to_string(),
+            CachedBody {
+                info,
+                bytes,
+                inserted: Instant::now(),
+                last_used,
+            },
+        );
+    }
+}
+
+/// Stat a regular file through `cache`, `Elapsed` when the deadline passed first.
+pub(crate) async fn stat_file(
+    path: &str,
+    deadline: Option<Instant>,
+    cache: Option<&FileCache>,
+) -> Result<io::Result<FileInfo>, Elapsed> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "not a file");
+    if let Some(cached) = cache.and_then(|cache| cache.stat(path)) {
+        return Ok(cached.ok_or_else(not_found));
+    }
+    let info = match before_deadline(deadline, tokio::fs::metadata(path)).await? {
+        Ok(metadata) if metadata.is_file() => Ok(FileInfo::from(&metadata)),
+        Ok(_) => Err(not_found()),
+        Err(e) => Err(e),
+    };
+    if let Some(cache) = cache {
+        cache.put_stat(path, info.as_ref().ok().copied());
+    }
+    Ok(info)
+}
+
+/// Whole file through `cache`, read from disk when missing or stale.
+pub(crate) async fn read_file(
+    path: &str,
+    info: FileInfo,
+    deadline: Option<Instant>,
+    cache: &FileCache,
+) -> Result<io::Result<Arc<Vec<u8>>>, Elapsed> {
+    if let Some(bytes) = cache.body(path, info) {
+        return Ok(Ok(bytes));
+    }
+    let bytes = match before_deadline(deadline, tokio::fs::read(path)).await? {
+        Ok(bytes) => Arc::new(bytes),
+        Err(e) => return Ok(Err(e)),
+    };
+    match bytes.len() as u64 == info.len {
+        true => cache.put_body(path, info, bytes.clone()),
+        // Changed since the cached stat, the next request stats it again.
+        false => cache.forget_stat(path),
+    }
+    Ok(Ok(bytes))
+}
@@ -0,0 +1,303 @@
+use std::path::{Component, Path, PathBuf};
+
+use http::StatusCode;
+
+use crate::{DotfilePolicy, Options, StaticMount, SymlinkPolicy};
+
+/// `root_path` with the global file policies, as a mount of `/`.
+pub(crate) fn root_mount(options: &Options) -> StaticMount {
+    StaticMount {
+        prefix: "/".into(),
+        root: options.root_path.clone(),
+        symlink_policy: options.symlink_policy,
+        dotfile_policy: options.dotfile_policy,
+        file_denylist: options.file_denylist.clone(),
+    }
+}
+
+/// The `static_mounts` entry with the longest prefix of `path` and the rest of `path`.
+pub(crate) fn find_mount<'a, 'b>(
+    options: &'a Options,
+    path: &'b str,
+) -> Option<(&'a StaticMount, &'b str)> {
+    options
+        .static_mounts
+        .iter()
+        .filter_map(|mount| {
+            let rest = path.strip_prefix(mount.prefix.trim_end_matches('/'))?;
+            (rest.is_empty() || rest.starts_with('/')).then_some((mount, rest))
+        })
+        .max_by_key(|(mount, _)| mount.prefix.trim_end_matches('/').len())
+}
+
+/// `find_mount`, or `root_mount` and all of `path` when no mount matches.
+pub(crate) fn resolve_mount<'a>(options: &Options, path: &'a str) -> (StaticMount, &'a str) {
+    match find_mount(options, path) {
+        Some((mount, rest)) => (mount.clone(), rest),
+        None => (root_mount(options), path),
+    }
+}
+
+/// Status refusing `path` under the dotfile, denylist and symlink policies of `mount`, `None`
+/// to serve it.
+pub(crate) async fn check_file_access(path: &Path, mount: &StaticMount) -> Option<StatusCode> {
+    if let Some(status) = check_hidden(path, mount) {
+        return Some(status);
+    }
+    match is_symlink_allowed(path, &mount.root, mount.symlink_policy).await {
+        true => None,
+        false => Some(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Dotfiles and `file_denylist` matches among the components below the mount root, or the
+/// file name alone for a path outside it.
+fn check_hidden(path: &Path, mount: &StaticMount) -> Option<StatusCode> {
+    let names: Vec<&str> = match path.strip_prefix(&mount.root) {
+        Ok(relative) => relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect(),
+        Err(_) => path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .into_iter()
+            .collect(),
+    };
+    let refused = match mount.dotfile_policy {
+        DotfilePolicy::Deny => StatusCode::FORBIDDEN,
+        _ => StatusCode::NOT_FOUND,
+    };
+    let is_dotfile = names.iter().any(|name| name.starts_with('.'));
+    if is_dotfile && mount.dotfile_policy != DotfilePolicy::Allow {
+        return Some(refused);
+    }
+    let denied = names.iter().any(|name| {
+        mount
+            .file_denylist
+            .iter()
+            .any(|pattern| matches_pattern(name, pattern))
+    });
+    denied.then_some(refused)
+}
+
+/// `*.bak` suffix, `backup*` prefix or exact name.
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    if let Some(suffix) = pattern.strip_prefix('*') {
+        return name.ends_with(suffix);
+    }
+    if let Some(prefix) = pattern.strip_suffix('*') {
+        return name.starts_with(prefix);
+    }
+    name == pattern
+}
+
+/// Whether `path` may be served or written under `policy`. `Deny` looks at every component
+/// below `root` (all of them for a path outside it), `WithinRoot` needs the resolved path
+/// inside the resolved root, a missing path is resolved through its closest existing ancestor.
+async fn is_symlink_allowed(path: &Path, root: &Path, policy: SymlinkPolicy) -> bool {
+    match policy {
+        SymlinkPolicy::Allow => true,
+        SymlinkPolicy::WithinRoot => {
+            let Ok(root) = tokio::fs::canonicalize(root).await else {
+                return false;
+            };
+            // A file to be created lands where its existing ancestor resolves to.
+            let mut current = path;
+            loop {
+                match tokio::fs::canonicalize(current).await {
+                    Ok(resolved) => return resolved.starts_with(&root),
+                    Err(_) => match current.parent() {
+                        Some(parent) => current = parent,
+                        None => return false,
+                    },
+                }
+            }
+        }
+        SymlinkPolicy::Deny => {
+            let (mut current, relative) = match path.strip_prefix(root) {
+                Ok(relative) => (root.to_path_buf(), relative),
+                Err(_) => (PathBuf::new(), path),
+            };
+            for component in relative.components() {
+                current.push(component);
+                if !matches!(component, Component::Normal(_)) {
+                    continue;
+                }
+                match tokio::fs::symlink_metadata(&current).await {
+                    Ok(metadata) if metadata.file_type().is_symlink() => return false,
+                    Ok(_) => {}
+                    Err(_) => return true,
+                }
+            }
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory per test under the system temp dir.
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "atomic_http_file_policy_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn mount(root: &Path, dotfile_policy: DotfilePolicy) -> StaticMount {
+        let mut mount = StaticMount::new("/", root);
+        mount.dotfile_policy = dotfile_policy;
+        mount.file_denylist = vec!["*.bak".into(), "backup*".into(), "secrets.toml".into()];
+        mount
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches_pattern("db.bak", "*.bak"));
+        assert!(!matches_pattern("db.bak.txt", "*.bak"));
+        assert!(matches_pattern("backup-1.tar", "backup*"));
+        assert!(!matches_pattern("my-backup", "backup*"));
+        assert!(matches_pattern("secrets.toml", "secrets.toml"));
+        assert!(!matches_pattern("secrets.toml.txt", "secrets.toml"));
+    }
+
+    #[tokio::test]
+    async fn refuses_hidden_entries() {
+        let root = temp_root("hidden");
+        let deny = mount(&root, DotfilePolicy::Deny);
+        let ignore = mount(&root, DotfilePolicy::Ignore);
+        let allow = mount(&root, DotfilePolicy::Allow);
+
+        let env = root.join(".env");
+        assert_eq!(
+            check_file_access(&env, &deny).await,
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check_file_access(&env, &ignore).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(check_file_access(&env, &allow).await, None);
+
+        let nested = root.join(".git/config");
+        assert_eq!(
+            check_file_access(&nested, &deny).await,
+            Some(StatusCode::FORBIDDEN)
+        );
+
+        let denied = root.join("db/dump.bak");
+        assert_eq!(
+            check_file_access(&denied, &deny).await,
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check_file_access(&denied, &allow).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        let denied_dir = root.join("backup-1/index.html");
+        assert_eq!(
+            check_file_access(&denied_dir, &ignore).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+
+        assert_eq!(
+            check_file_access(&root.join("index.html"), &deny).await,
+            None
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn follows_symlinks_by_policy() {
+        use std::os::unix::fs::symlink;
+
+        let base = temp_root("symlinks");
+        let root = base.join("root");
+        let outside = base.join("outside");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("docs/a.txt"), "a").unwrap();
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(root.join("docs"), root.join("inside")).unwrap();
+
+        let mut mount = mount(&root, DotfilePolicy::Deny);
+        let escaped = root.join("escape/secret.txt");
+        let created = root.join("escape/new.txt");
+        let linked = root.join("inside/a.txt");
+        let missing = root.join("docs/new/b.txt");
+
+        mount.symlink_policy = SymlinkPolicy::WithinRoot;
+        assert_eq!(
+            check_file_access(&escaped, &mount).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            check_file_access(&created, &mount).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(check_file_access(&linked, &mount).await, None);
+        assert_eq!(check_file_access(&missing, &mount).await, None);
+
+        mount.symlink_policy = SymlinkPolicy::Deny;
+        assert_eq!(
+            check_file_access(&escaped, &mount).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            check_file_access(&linked, &mount).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(check_file_access(&missing, &mount).await, None);
+
+        mount.symlink_policy = SymlinkPolicy::Allow;
+        assert_eq!(check_file_access(&escaped, &mount).await, None);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn resolves_missing_paths_through_their_ancestor() {
+        use std::os::unix::fs::symlink;
+
+        let base = temp_root("missing");
+        let root = base.join("root");
+        let outside = base.join("outside");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(root.join("docs"), root.join("inside")).unwrap();
+
+        let mut mount = mount(&root, DotfilePolicy::Deny);
+        mount.symlink_policy = SymlinkPolicy::WithinRoot;
+        let escaped = root.join("escape/new/dir/file.txt");
+        assert_eq!(
+            check_file_access(&escaped, &mount).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        let inside = root.join("inside/new/dir/file.txt");
+        assert_eq!(check_file_access(&inside, &mount).await, None);
+        let missing_root = base.join("missing_root");
+        let mount = StaticMount {
+            root: missing_root.clone(),
+            ..mount
+        };
+        let file = missing_root.join("file.txt");
+        assert_eq!(
+            check_file_access(&file, &mount).await,
+            Some(StatusCode::NOT_FOUND)
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
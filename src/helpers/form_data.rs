@@ -0,0 +1,392 @@
+use serde::de::value::{SeqDeserializer, StringDeserializer};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, IntoDeserializer, MapAccess,
+    Visitor,
+};
+use serde::Deserialize;
+
+use crate::helpers::query::{QueryError, QueryValue};
+use crate::helpers::traits::http_stream::{Form, Part};
+
+/// File of a multipart form, the field type for uploads in `Form::into_struct`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilePart {
+    pub file_name: String,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl From<Part> for FilePart {
+    fn from(part: Part) -> FilePart {
+        FilePart {
+            content_type: part
+                .headers
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from),
+            file_name: part.file_name,
+            body: part.body,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FilePart {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FilePart, D::Error> {
+        deserializer.deserialize_struct("FilePart", FILE_PART_FIELDS, FilePartVisitor)
+    }
+}
+
+const FILE_PART_FIELDS: &[&str] = &["file_name", "content_type", "body"];
+
+struct FilePartVisitor;
+
+impl<'de> Visitor<'de> for FilePartVisitor {
+    type Value = FilePart;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a file part")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FilePart, A::Error> {
+        let mut part = FilePart::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "file_name" => part.file_name = map.next_value()?,
+                "content_type" => part.content_type = map.next_value()?,
+                "body" => part.body = map.next_value::<ByteBuf>()?.0,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(part)
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ByteBuf, D::Error> {
+        struct ByteBufVisitor;
+
+        impl<'de> Visitor<'de> for ByteBufVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(bytes.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(bytes))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+impl Form {
+    /// Text fields and file parts by name into `T`. Text fields parse like `query_as`, file parts
+    /// fill `FilePart` fields, and repeated names fill `Vec` fields.
+    pub fn into_struct<T: DeserializeOwned>(self) -> Result<T, QueryError> {
+        let mut entries: Vec<(String, Vec<FormValue>)> = Vec::new();
+        let values = self
+            .fields
+            .into_iter()
+            .map(|(name, value)| (name, FormValue::Text(value)))
+            .chain(
+                self.parts
+                    .into_iter()
+                    .map(|part| (part.name.clone(), FormValue::File(part.into()))),
+            );
+        for (name, value) in values {
+            match entries.iter_mut().find(|(entry, _)| *entry == name) {
+                Some((_, entry_values)) => entry_values.push(value),
+                None => entries.push((name, vec![value])),
+            }
+        }
+        T::deserialize(FormDeserializer {
+            entries: entries.into_iter(),
+            current: None,
+        })
+    }
+}
+
+enum FormValue {
+    Text(String),
+    File(FilePart),
+}
+
+struct FormDeserializer {
+    entries: std::vec::IntoIter<(String, Vec<FormValue>)>,
+    current: Option<(String, Vec<FormValue>)>,
+}
+
+impl<'de> Deserializer<'de> for FormDeserializer {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_map(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for FormDeserializer {
+    type Error = QueryError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, QueryError> {
+        let Some((name, values)) = self.entries.next() else {
+            return Ok(None);
+        };
+        let key = seed.deserialize(name.clone().into_deserializer())?;
+        self.current = Some((name, values));
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, QueryError> {
+        let (name, values) = self.current.take().unwrap_or_default();
+        seed.deserialize(FormValues(values))
+            .map_err(|error| QueryError {
+                key: error.key.or(Some(name)),
+                message: error.message,
+            })
+    }
+}
+
+/// All values of one name.
+struct FormValues(Vec<FormValue>);
+
+impl FormValues {
+    fn first(self) -> FormValue {
+        self.0
+            .into_iter()
+            .next()
+            .unwrap_or(FormValue::Text(String::new()))
+    }
+}
+
+macro_rules! first_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                self.first().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for FormValues {
+    type Error = QueryError;
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        let is_empty = self.0.iter().all(|value| match value {
+            FormValue::Text(text) => text.is_empty(),
+            FormValue::File(_) => false,
+        });
+        match is_empty {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_seq(SeqDeserializer::new(self.0.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.first().deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.first().deserialize_enum(name, variants, visitor)
+    }
+
+    first_value! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32
+        deserialize_u64 deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+    }
+
+    serde::forward_to_deserialize_any! {
+        unit unit_struct tuple_struct map identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, QueryError> for FormValue {
+    type Deserializer = FormValue;
+
+    fn into_deserializer(self) -> FormValue {
+        self
+    }
+}
+
+macro_rules! text_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                match self {
+                    FormValue::Text(text) => QueryValue(text).$method(visitor),
+                    FormValue::File(_) => Err(de::Error::custom("expected a text field, got a file")),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for FormValue {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self {
+            FormValue::Text(text) => visitor.visit_string(text),
+            FormValue::File(file) => visitor.visit_map(FileAccess::new(file)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self {
+            FormValue::Text(text) => QueryValue(text).deserialize_option(visitor),
+            file => visitor.visit_some(file),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self {
+            FormValue::Text(text) => visitor.visit_byte_buf(text.into_bytes()),
+            FormValue::File(file) => visitor.visit_byte_buf(file.body),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        match self {
+            FormValue::Text(_) => Err(de::Error::custom("expected a file, got a text field")),
+            file => file.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        match self {
+            FormValue::Text(text) => QueryValue(text).deserialize_enum(name, variants, visitor),
+            FormValue::File(_) => Err(de::Error::custom("expected a text field, got a file")),
+        }
+    }
+
+    text_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string
+    }
+
+    serde::forward_to_deserialize_any! {
+        unit unit_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+/// `FilePart` fields of one file, read back by its `Deserialize`.
+struct FileAccess {
+    file: FilePart,
+    field: usize,
+}
+
+impl FileAccess {
+    fn new(file: FilePart) -> FileAccess {
+        FileAccess { file, field: 0 }
+    }
+}
+
+impl<'de> MapAccess<'de> for FileAccess {
+    type Error = QueryError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, QueryError> {
+        let Some(field) = FILE_PART_FIELDS.get(self.field) else {
+            return Ok(None);
+        };
+        let key: StringDeserializer<QueryError> = field.to_string().into_deserializer();
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, QueryError> {
+        let field = self.field;
+        self.field += 1;
+        let file = &mut self.file;
+        match field {
+            0 => seed.deserialize(QueryValue(std::mem::take(&mut file.file_name))),
+            1 => seed.deserialize(QueryValue(file.content_type.take().unwrap_or_default())),
+            _ => seed.deserialize(FormValue::File(FilePart {
+                body: std::mem::take(&mut file.body),
+                ..FilePart::default()
+            })),
+        }
+    }
+}
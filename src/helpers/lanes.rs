@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use http::Request;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone)]
+pub enum LaneMatcher {
+    PathPrefix(String),
+    Header(String, Option<String>),
+}
+
+impl LaneMatcher {
+    fn is_match<T>(&self, request: &Request<T>) -> bool {
+        match self {
+            LaneMatcher::PathPrefix(prefix) => request.uri().path().starts_with(prefix.as_str()),
+            LaneMatcher::Header(name, value) => match request.headers().get(name.as_str()) {
+                Some(header) => match value {
+                    Some(value) => header.to_str().map(|v| v == value).unwrap_or(false),
+                    None => true,
+                },
+                None => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Lane {
+    name: String,
+    matchers: Vec<LaneMatcher>,
+    semaphore: Arc<Semaphore>,
+}
+
+/// Classify requests into lanes with their own concurrency budget.
+/// Lanes are checked in the order they were added, unmatched requests go to the default lane.
+#[derive(Debug, Clone)]
+pub struct PriorityLanes {
+    lanes: Vec<Arc<Lane>>,
+    default_lane: Arc<Lane>,
+}
+
+pub struct LanePermit {
+    pub lane: String,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl std::fmt::Debug for LanePermit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LanePermit")
+            .field("lane", &self.lane)
+            .finish()
+    }
+}
+
+impl PriorityLanes {
+    pub fn new(default_concurrency: usize) -> PriorityLanes {
+        PriorityLanes {
+            lanes: Vec::new(),
+            default_lane: Arc::new(Lane {
+                name: "default".into(),
+                matchers: Vec::new(),
+                semaphore: Arc::new(Semaphore::new(default_concurrency)),
+            }),
+        }
+    }
+
+    pub fn add_lane(
+        mut self,
+        name: &str,
+        concurrency: usize,
+        matchers: Vec<LaneMatcher>,
+    ) -> PriorityLanes {
+        self.lanes.push(Arc::new(Lane {
+            name: name.into(),
+            matchers,
+            semaphore: Arc::new(Semaphore::new(concurrency)),
+        }));
+        self
+    }
+
+    fn get_lane<T>(&self, request: &Request<T>) -> &Lane {
+        self.lanes
+            .iter()
+            .find(|lane| {
+                lane.matchers
+                    .iter()
+                    .any(|matcher| matcher.is_match(request))
+            })
+            .unwrap_or(&self.default_lane)
+            .as_ref()
+    }
+
+    pub fn classify<T>(&self, request: &Request<T>) -> &str {
+        self.get_lane(request).name.as_str()
+    }
+
+    pub fn available(&self, name: &str) -> Option<usize> {
+        self.lanes
+            .iter()
+            .chain(std::iter::once(&self.default_lane))
+            .find(|lane| lane.name == name)
+            .map(|lane| lane.semaphore.available_permits())
+    }
+
+    pub async fn acquire<T: Sync>(
+        &self,
+        request: &Request<T>,
+    ) -> Result<LanePermit, Box<dyn Error>> {
+        let lane = self.get_lane(request);
+        dev_print!("priority lane: {}", &lane.name);
+        let permit = lane.semaphore.clone().acquire_owned().await?;
+        Ok(LanePermit {
+            lane: lane.name.clone(),
+            _permit: permit,
+        })
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+/// Process wide bytes held in request and response buffers.
+pub struct MemoryUsage;
+
+impl MemoryUsage {
+    pub fn in_use() -> usize {
+        IN_USE.load(Ordering::Relaxed)
+    }
+
+    pub fn is_over(budget: usize) -> bool {
+        budget > 0 && MemoryUsage::in_use() >= budget
+    }
+}
+
+/// Counts `len` bytes as in use until dropped.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryLease {
+    len: usize,
+}
+
+impl MemoryLease {
+    pub(crate) fn new(len: usize) -> MemoryLease {
+        IN_USE.fetch_add(len, Ordering::Relaxed);
+        MemoryLease { len }
+    }
+
+    pub(crate) fn resize(&mut self, len: usize) {
+        if len > self.len {
+            IN_USE.fetch_add(len - self.len, Ordering::Relaxed);
+        } else {
+            IN_USE.fetch_sub(self.len - len, Ordering::Relaxed);
+        }
+        self.len = len;
+    }
+}
+
+impl Drop for MemoryLease {
+    fn drop(&mut self) {
+        IN_USE.fetch_sub(self.len, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,157 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+static HEADERS: AtomicU64 = AtomicU64::new(0);
+static HEADER_BYTES: AtomicU64 = AtomicU64::new(0);
+static PARSE_NANOS: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Process wide counters for the request parser.
+pub struct ParserMetrics;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParserMetricsSnapshot {
+    pub requests: u64,
+    pub headers: u64,
+    pub header_bytes: u64,
+    pub parse_time: Duration,
+    /// Heads the parser refused, also counted in `requests` and `parse_time`.
+    pub failed: u64,
+    /// Allocations made while parsing heads. Counted in debug builds with `CountingAllocator`
+    /// as the global allocator, 0 otherwise.
+    pub allocations: u64,
+    pub elapsed: Duration,
+}
+
+impl ParserMetricsSnapshot {
+    pub fn requests_per_sec(&self) -> f64 {
+        per_sec(self.requests, self.elapsed)
+    }
+
+    pub fn headers_per_sec(&self) -> f64 {
+        per_sec(self.headers, self.elapsed)
+    }
+
+    /// Headers parsed per second of time spent inside the parser.
+    pub fn parser_headers_per_sec(&self) -> f64 {
+        per_sec(self.headers, self.parse_time)
+    }
+}
+
+fn per_sec(count: u64, duration: Duration) -> f64 {
+    match duration.as_secs_f64() {
+        secs if secs > 0.0 => count as f64 / secs,
+        _ => 0.0,
+    }
+}
+
+impl ParserMetrics {
+    pub fn snapshot() -> ParserMetricsSnapshot {
+        ParserMetricsSnapshot {
+            requests: REQUESTS.load(Ordering::Relaxed),
+            headers: HEADERS.load(Ordering::Relaxed),
+            header_bytes: HEADER_BYTES.load(Ordering::Relaxed),
+            parse_time: Duration::from_nanos(PARSE_NANOS.load(Ordering::Relaxed)),
+            failed: FAILED.load(Ordering::Relaxed),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            elapsed: STARTED.get_or_init(Instant::now).elapsed(),
+        }
+    }
+
+    pub fn reset() {
+        REQUESTS.store(0, Ordering::Relaxed);
+        HEADERS.store(0, Ordering::Relaxed);
+        HEADER_BYTES.store(0, Ordering::Relaxed);
+        PARSE_NANOS.store(0, Ordering::Relaxed);
+        FAILED.store(0, Ordering::Relaxed);
+        ALLOCATIONS.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record(
+        headers: usize,
+        header_bytes: usize,
+        parse_time: Duration,
+        failed: bool,
+        allocations: u64,
+    ) {
+        STARTED.get_or_init(Instant::now);
+        REQUESTS.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            FAILED.fetch_add(1, Ordering::Relaxed);
+        }
+        ALLOCATIONS.fetch_add(allocations, Ordering::Relaxed);
+        HEADERS.fetch_add(headers as u64, Ordering::Relaxed);
+        HEADER_BYTES.fetch_add(header_bytes as u64, Ordering::Relaxed);
+        PARSE_NANOS.fetch_add(parse_time.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    static THREAD_ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The system allocator, counting allocations per thread in debug builds for
+/// `ParserMetricsSnapshot::allocations`. Install it in the application:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: atomic_http::CountingAllocator = atomic_http::CountingAllocator;
+/// ```
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count_allocation() {
+    if cfg!(debug_assertions) {
+        // Not counted while the thread local is torn down.
+        let _ = THREAD_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    }
+}
+
+/// Allocations of this thread so far, 0 without `CountingAllocator` or in release builds.
+pub(crate) fn thread_allocations() -> u64 {
+    THREAD_ALLOCATIONS.try_with(Cell::get).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn counts_allocations_of_the_thread() {
+        let before = thread_allocations();
+        let bytes = std::hint::black_box(vec![0u8; 16]);
+        let mut text = String::with_capacity(1);
+        text.push_str("more than one byte");
+        // The vec, the string and its growth, frees are not counted.
+        assert_eq!(thread_allocations() - before, 3);
+        drop((bytes, text));
+        assert_eq!(thread_allocations() - before, 3);
+    }
+}
@@ -1 +1,40 @@
+pub mod body_sink;
+pub mod body_stream;
+pub(crate) mod cache_control;
+#[cfg(feature = "checksum")]
+pub mod checksum;
+pub mod chunked;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod conditional;
+pub mod cookie;
+#[cfg(feature = "signing")]
+pub mod cookie_key;
+pub mod error;
+pub mod file_cache;
+pub(crate) mod file_policy;
+pub mod form_data;
+pub mod lanes;
+pub mod memory;
+pub mod metrics;
+pub mod multipart;
+pub mod multipart_upload;
+pub mod path;
+pub(crate) mod precompressed;
+pub mod query;
+pub(crate) mod random;
+pub mod range;
+pub mod redirect;
+#[cfg(feature = "s3")]
+pub mod s3_sink;
+#[cfg(feature = "signing")]
+pub mod signed_url;
+pub mod sse;
+pub(crate) mod throttle;
 pub mod traits;
+#[cfg(feature = "tus")]
+pub mod tus;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+#[cfg(feature = "websocket")]
+pub mod websocket;
@@ -0,0 +1,193 @@
+use std::error::Error;
+
+use http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue, Response};
+use tokio::io::AsyncWriteExt;
+
+use crate::helpers::random::random_hex;
+use crate::helpers::traits::http_response::{get_head, send_bytes};
+use crate::Writer;
+
+/// Assemble `multipart/*` response bodies.
+#[derive(Debug, Clone)]
+pub struct MultipartBuilder {
+    subtype: String,
+    boundary: String,
+    body: Vec<u8>,
+}
+
+impl MultipartBuilder {
+    pub fn new(subtype: &str) -> MultipartBuilder {
+        MultipartBuilder {
+            subtype: subtype.into(),
+            boundary: format!("atomic_http_{}", random_hex(24)),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn mixed() -> MultipartBuilder {
+        MultipartBuilder::new("mixed")
+    }
+
+    pub fn form_data() -> MultipartBuilder {
+        MultipartBuilder::new("form-data")
+    }
+
+    pub fn boundary(mut self, boundary: &str) -> MultipartBuilder {
+        self.boundary = boundary.into();
+        self
+    }
+
+    pub fn get_boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    pub fn part(mut self, headers: &HeaderMap, body: &[u8]) -> MultipartBuilder {
+        self.body
+            .extend_from_slice(format!("--{}\r\n", self.boundary).as_bytes());
+        for (key, value) in headers.iter() {
+            self.body.extend_from_slice(key.as_str().as_bytes());
+            self.body.extend_from_slice(b": ");
+            self.body.extend_from_slice(value.as_bytes());
+            self.body.extend_from_slice(b"\r\n");
+        }
+        self.body.extend_from_slice(b"\r\n");
+        self.body.extend_from_slice(body);
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    fn disposition(&self, default: &'static str) -> &'static str {
+        match self.subtype.as_str() {
+            "form-data" => "form-data",
+            _ => default,
+        }
+    }
+
+    pub fn text(self, name: &str, value: &str) -> MultipartBuilder {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_DISPOSITION,
+            format!(
+                "{}; name=\"{}\"",
+                self.disposition("inline"),
+                escape_quoted(name)
+            )
+            .parse()
+            .unwrap(),
+        );
+        self.part(&headers, value.as_bytes())
+    }
+
+    pub fn file(
+        self,
+        name: &str,
+        file_name: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> MultipartBuilder {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_DISPOSITION,
+            format!(
+                "{}; name=\"{}\"; filename=\"{}\"",
+                self.disposition("attachment"),
+                escape_quoted(name),
+                escape_quoted(file_name)
+            )
+            .parse()
+            .unwrap(),
+        );
+        if let Ok(content_type) = content_type.parse() {
+            headers.insert(http::header::CONTENT_TYPE, content_type);
+        }
+        self.part(&headers, body)
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/{}; boundary={}", self.subtype, self.boundary)
+    }
+
+    pub fn build(mut self) -> Vec<u8> {
+        self.body
+            .extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        self.body
+    }
+}
+
+fn escape_quoted(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+/// Long lived `multipart/x-mixed-replace` response(MJPEG, previews), every part is flushed.
+pub struct MultipartStream {
+    writer: Writer,
+    boundary: String,
+}
+
+impl MultipartStream {
+    pub async fn new(response: Response<Writer>) -> Result<MultipartStream, Box<dyn Error>> {
+        MultipartStream::with_subtype(response, "x-mixed-replace").await
+    }
+
+    pub async fn with_subtype(
+        mut response: Response<Writer>,
+        subtype: &str,
+    ) -> Result<MultipartStream, Box<dyn Error>> {
+        let boundary = format!("atomic_http_{}", random_hex(24));
+        *response.status_mut() = http::StatusCode::OK;
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            format!("multipart/{}; boundary={}", subtype, boundary).parse()?,
+        );
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response.headers_mut().remove(CONTENT_LENGTH);
+        let head = get_head(&response)?;
+        let mut writer = response.into_body();
+        send_bytes(&mut writer.stream, head.as_bytes()).await?;
+        writer.stream.flush().await?;
+        Ok(MultipartStream { writer, boundary })
+    }
+
+    pub async fn push_part(
+        &mut self,
+        bytes: &[u8],
+        content_type: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.parse()?);
+        self.push_part_with_headers(&headers, bytes).await
+    }
+
+    pub async fn push_part_with_headers(
+        &mut self,
+        headers: &HeaderMap,
+        bytes: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut part = format!("--{}\r\n", self.boundary);
+        for (key, value) in headers.iter() {
+            part.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+        }
+        part.push_str(&format!("content-length: {}\r\n\r\n", bytes.len()));
+        let writer = &mut self.writer;
+        writer.send(part.as_bytes()).await?;
+        writer.send(bytes).await?;
+        writer.send(b"\r\n").await?;
+        writer.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        let end = format!("--{}--\r\n", self.boundary);
+        send_bytes(&mut self.writer.stream, end.as_bytes()).await?;
+        self.writer.stream.flush().await?;
+        Ok(())
+    }
+}
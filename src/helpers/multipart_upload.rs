@@ -0,0 +1,557 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+
+use crate::helpers::body_sink::{BodySink, BodyStorage, FileSink};
+use crate::helpers::error::ParseError;
+use crate::helpers::traits::GetHeaderChild;
+use crate::Options;
+
+/// Part headers larger than this are refused, they are kept in memory until complete.
+const MAX_PART_HEAD: usize = 16384;
+
+/// `multipart/form-data` body parsed while it was read, see `Options::multipart_spool_threshold`.
+#[derive(Debug, Default)]
+pub struct MultipartUpload {
+    /// Text fields in order.
+    pub fields: Vec<(String, String)>,
+    /// File parts, and text fields too large to keep in memory.
+    pub files: Vec<UploadedFile>,
+}
+
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub name: String,
+    pub file_name: String,
+    pub headers: HeaderMap,
+    /// `Memory` up to the threshold, `File` beyond it.
+    pub data: BodyStorage,
+}
+
+/// `max_multipart_*` options, checked by `get_multi_part` and `MultipartSink`. Offsets are in
+/// the body.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MultipartLimits {
+    parts: usize,
+    part_size: usize,
+    size: usize,
+    name_length: usize,
+}
+
+impl MultipartLimits {
+    pub(crate) fn new(options: &Options) -> MultipartLimits {
+        MultipartLimits {
+            parts: options.max_multipart_parts,
+            part_size: options.max_multipart_part_size,
+            size: options.max_multipart_size,
+            name_length: options.max_multipart_name_length,
+        }
+    }
+
+    pub(crate) fn check_size(&self, size: usize) -> Option<ParseError> {
+        over(self.size, size)
+            .then(|| too_large(size, format!("multipart body exceeds {} bytes", self.size)))
+    }
+
+    pub(crate) fn check_parts(&self, parts: usize, offset: usize) -> Option<ParseError> {
+        over(self.parts, parts).then(|| {
+            too_large(
+                offset,
+                format!("{} multipart parts, limit is {}", parts, self.parts),
+            )
+        })
+    }
+
+    pub(crate) fn check_part_size(&self, len: usize, offset: usize) -> Option<ParseError> {
+        over(self.part_size, len).then(|| {
+            too_large(
+                offset,
+                format!("multipart part exceeds {} bytes", self.part_size),
+            )
+        })
+    }
+
+    pub(crate) fn check_names(
+        &self,
+        name: &str,
+        file_name: Option<&str>,
+        offset: usize,
+    ) -> Option<ParseError> {
+        let longest = name.len().max(file_name.map_or(0, str::len));
+        if !over(self.name_length, longest) {
+            return None;
+        }
+        Some(malformed(
+            offset,
+            format!("multipart part name over {} bytes", self.name_length),
+        ))
+    }
+
+    /// Limits over an in memory body, malformed bodies are left to `get_multi_part`.
+    pub(crate) fn check_body(&self, body: &[u8], boundary: &str) -> Option<ParseError> {
+        if let Some(error) = self.check_size(body.len()) {
+            return Some(error);
+        }
+        let first = format!("--{}", boundary);
+        let delimiter = format!("\r\n--{}", boundary);
+        let mut index = find(body, first.as_bytes())? + first.len();
+        let mut parts = 0;
+        while !body[index..].starts_with(b"--") {
+            let head_start = index + 2;
+            let head_len = find(body.get(head_start..)?, b"\r\n\r\n")?;
+            parts += 1;
+            if let Some(error) = self.check_parts(parts, head_start) {
+                return Some(error);
+            }
+            let (name, file_name, _) = part_head(&body[head_start..head_start + head_len]);
+            if let Some(error) = self.check_names(&name, file_name.as_deref(), head_start) {
+                return Some(error);
+            }
+            let body_start = head_start + head_len + 4;
+            let len = find(&body[body_start..], delimiter.as_bytes())?;
+            if let Some(error) = self.check_part_size(len, body_start) {
+                return Some(error);
+            }
+            index = body_start + len + delimiter.len();
+        }
+        None
+    }
+}
+
+fn over(limit: usize, value: usize) -> bool {
+    limit > 0 && value > limit
+}
+
+fn too_large(offset: usize, message: String) -> ParseError {
+    let mut error = ParseError::new(StatusCode::PAYLOAD_TOO_LARGE);
+    error.push(offset, Some("content-type"), message);
+    error
+}
+
+fn malformed(offset: usize, message: impl Into<String>) -> ParseError {
+    let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+    error.push(offset, Some("content-disposition"), message);
+    error
+}
+
+enum State {
+    Preamble,
+    /// Right after a delimiter, `--` ends the body and CRLF starts a part.
+    Boundary,
+    Head,
+    Body,
+    Done,
+}
+
+struct CurrentPart {
+    offset: usize,
+    len: usize,
+    name: String,
+    file_name: Option<String>,
+    headers: HeaderMap,
+    memory: Vec<u8>,
+    file: Option<Box<FileSink>>,
+}
+
+/// Body sink splitting a multipart body into parts as it arrives, parts above `threshold` bytes
+/// are written to temp files in `spool_dir`.
+pub struct MultipartSink {
+    delimiter: Vec<u8>,
+    threshold: usize,
+    spool_dir: PathBuf,
+    limits: MultipartLimits,
+    /// Body bytes written so far.
+    received: usize,
+    parts: usize,
+    buffer: Vec<u8>,
+    state: State,
+    part: Option<CurrentPart>,
+    upload: MultipartUpload,
+}
+
+impl MultipartSink {
+    pub fn new(boundary: &str, options: &Options) -> MultipartSink {
+        MultipartSink {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            threshold: options.multipart_spool_threshold,
+            spool_dir: options.body_spool_dir.clone(),
+            limits: MultipartLimits::new(options),
+            received: 0,
+            parts: 0,
+            // The first delimiter has no CRLF in front.
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+            part: None,
+            upload: MultipartUpload::default(),
+        }
+    }
+
+    /// Body offset of the first buffered byte, the seeded CRLF is not part of the body.
+    fn offset(&self) -> usize {
+        self.received.saturating_sub(self.buffer.len())
+    }
+
+    /// Bytes of the buffer that can not be the start of a delimiter.
+    fn safe_len(&self) -> usize {
+        self.buffer
+            .len()
+            .saturating_sub(self.delimiter.len().saturating_sub(1))
+    }
+
+    async fn part_write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(part) = &mut self.part else {
+            return Ok(());
+        };
+        part.len += bytes.len();
+        if let Some(error) = self.limits.check_part_size(part.len, part.offset) {
+            return Err(Box::new(error));
+        }
+        if let Some(file) = &mut part.file {
+            return file.write(bytes).await;
+        }
+        if part.memory.len() + bytes.len() <= self.threshold {
+            part.memory.extend_from_slice(bytes);
+            return Ok(());
+        }
+        let mut file = Box::new(FileSink::new(&self.spool_dir).await?);
+        file.write(&part.memory).await?;
+        file.write(bytes).await?;
+        part.memory = Vec::new();
+        part.file = Some(file);
+        Ok(())
+    }
+
+    async fn part_finish(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(part) = self.part.take() else {
+            return Ok(());
+        };
+        let data = match part.file {
+            Some(file) => file.finish().await?,
+            None if part.file_name.is_none() => {
+                let value = String::from_utf8_lossy(&part.memory).into_owned();
+                self.upload.fields.push((part.name, value));
+                return Ok(());
+            }
+            None => BodyStorage::Memory(part.memory),
+        };
+        self.upload.files.push(UploadedFile {
+            name: part.name,
+            file_name: part.file_name.unwrap_or_default(),
+            headers: part.headers,
+            data,
+        });
+        Ok(())
+    }
+
+    /// `offset` is where the head starts in the body.
+    fn part_start(
+        &mut self,
+        head: &[u8],
+        offset: usize,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.parts += 1;
+        if let Some(error) = self.limits.check_parts(self.parts, offset) {
+            return Err(Box::new(error));
+        }
+        let (name, file_name, headers) = part_head(head);
+        if let Some(error) = self.limits.check_names(&name, file_name.as_deref(), offset) {
+            return Err(Box::new(error));
+        }
+        self.part = Some(CurrentPart {
+            offset: self.offset(),
+            len: 0,
+            name,
+            file_name,
+            headers,
+            memory: Vec::new(),
+            file: None,
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BodySink for MultipartSink {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.received += bytes.len();
+        if let Some(error) = self.limits.check_size(self.received) {
+            return Err(Box::new(error));
+        }
+        self.buffer.extend_from_slice(bytes);
+        loop {
+            match self.state {
+                State::Preamble => match find(&self.buffer, &self.delimiter) {
+                    Some(index) => {
+                        self.buffer.drain(..index + self.delimiter.len());
+                        self.state = State::Boundary;
+                    }
+                    None => {
+                        let safe_len = self.safe_len();
+                        self.buffer.drain(..safe_len);
+                        return Ok(());
+                    }
+                },
+                State::Boundary => {
+                    if self.buffer.len() < 2 {
+                        return Ok(());
+                    }
+                    match &self.buffer[..2] {
+                        b"--" => self.state = State::Done,
+                        b"\r\n" => self.state = State::Head,
+                        _ => {
+                            let error = malformed(self.offset(), "malformed multipart boundary");
+                            return Err(Box::new(error));
+                        }
+                    }
+                    self.buffer.drain(..2);
+                }
+                State::Head => match find(&self.buffer, b"\r\n\r\n") {
+                    Some(index) => {
+                        let offset = self.offset();
+                        let head: Vec<u8> = self.buffer.drain(..index + 4).collect();
+                        self.part_start(&head[..index], offset)?;
+                        self.state = State::Body;
+                    }
+                    None if self.buffer.len() > MAX_PART_HEAD => {
+                        let error = malformed(self.offset(), "multipart part headers too large");
+                        return Err(Box::new(error));
+                    }
+                    None => return Ok(()),
+                },
+                State::Body => match find(&self.buffer, &self.delimiter) {
+                    Some(index) => {
+                        let body: Vec<u8> = self.buffer.drain(..index).collect();
+                        self.part_write(&body).await?;
+                        self.part_finish().await?;
+                        self.buffer.drain(..self.delimiter.len());
+                        self.state = State::Boundary;
+                    }
+                    None => {
+                        let safe_len = self.safe_len();
+                        let body: Vec<u8> = self.buffer.drain(..safe_len).collect();
+                        self.part_write(&body).await?;
+                        return Ok(());
+                    }
+                },
+                // Epilogue after the closing delimiter is ignored.
+                State::Done => {
+                    self.buffer.clear();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn finish(self: Box<Self>) -> Result<BodyStorage, Box<dyn Error + Send + Sync>> {
+        match self.state {
+            State::Done => Ok(BodyStorage::Multipart(self.upload)),
+            _ => {
+                let error = malformed(
+                    self.received,
+                    "multipart body ended before the closing boundary",
+                );
+                Err(Box::new(error))
+            }
+        }
+    }
+}
+
+/// `name`, `filename` and headers of a part head without its blank line.
+fn part_head(head: &[u8]) -> (String, Option<String>, HeaderMap) {
+    let mut name = String::new();
+    let mut file_name = None;
+    let mut headers = HeaderMap::new();
+    for line in String::from_utf8_lossy(head).split("\r\n") {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if key.trim().eq_ignore_ascii_case("content-disposition") {
+            let children = value.get_header_child();
+            name = children.get("name").cloned().unwrap_or_default();
+            file_name = children.get("filename").cloned();
+        }
+        if let (Ok(key), Ok(value)) = (
+            HeaderName::from_str(key.trim()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.append(key, value);
+        }
+    }
+    (name, file_name, headers)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `boundary` parameter of a `multipart/form-data` Content-Type.
+pub(crate) fn form_data_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        hello\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"doc\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        0123456789abcdef\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+        a text field over the threshold\r\n--XyZ--\r\nepilogue";
+
+    fn spool_options(threshold: usize) -> Options {
+        let mut options = Options::new();
+        options.multipart_spool_threshold = threshold;
+        options
+    }
+
+    async fn upload(
+        body: &[u8],
+        chunk: usize,
+        options: &Options,
+    ) -> Result<MultipartUpload, Box<dyn Error + Send + Sync>> {
+        let mut sink: Box<dyn BodySink> = Box::new(MultipartSink::new("XyZ", options));
+        for bytes in body.chunks(chunk) {
+            sink.write(bytes).await?;
+        }
+        match sink.finish().await? {
+            BodyStorage::Multipart(upload) => Ok(upload),
+            storage => panic!("unexpected storage {:?}", storage),
+        }
+    }
+
+    fn status(error: Box<dyn Error + Send + Sync>) -> StatusCode {
+        error.downcast_ref::<ParseError>().unwrap().status
+    }
+
+    #[tokio::test]
+    async fn splits_parts_in_any_chunk_size() {
+        for chunk in [1, 3, 7, BODY.len()] {
+            let upload = upload(BODY, chunk, &spool_options(64)).await.unwrap();
+            let expected = [
+                ("title".to_string(), "hello".to_string()),
+                (
+                    "note".to_string(),
+                    "a text field over the threshold".to_string(),
+                ),
+            ];
+            assert_eq!(upload.fields, expected);
+            assert_eq!(upload.files.len(), 1);
+            let file = &upload.files[0];
+            assert_eq!(
+                (file.name.as_str(), file.file_name.as_str()),
+                ("doc", "a.txt")
+            );
+            assert_eq!(file.headers["content-type"], "text/plain");
+            match &file.data {
+                BodyStorage::Memory(bytes) => assert_eq!(bytes, b"0123456789abcdef"),
+                data => panic!("unexpected data {:?}", data),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn spools_parts_over_the_threshold() {
+        let upload = upload(BODY, 5, &spool_options(8)).await.unwrap();
+        assert_eq!(upload.fields, [("title".to_string(), "hello".to_string())]);
+        assert_eq!(upload.files.len(), 2);
+        let contents: Vec<_> = upload
+            .files
+            .iter()
+            .map(|file| match &file.data {
+                BodyStorage::File(spooled) => std::fs::read(spooled.path()).unwrap(),
+                data => panic!("unexpected data {:?}", data),
+            })
+            .collect();
+        assert_eq!(contents[0], b"0123456789abcdef");
+        assert_eq!(contents[1], b"a text field over the threshold");
+        assert_eq!(upload.files[1].file_name, "");
+        let path = match &upload.files[0].data {
+            BodyStorage::File(spooled) => spooled.path().to_path_buf(),
+            _ => unreachable!(),
+        };
+        drop(upload);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn refuses_bodies_without_the_closing_boundary() {
+        let body = &BODY[..BODY.len() - b"--\r\nepilogue".len()];
+        let error = upload(body, 4, &spool_options(64)).await.unwrap_err();
+        assert_eq!(status(error), StatusCode::BAD_REQUEST);
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--XyZxx";
+        let error = upload(body, 4, &spool_options(64)).await.unwrap_err();
+        assert_eq!(status(error), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn reads_the_form_data_boundary() {
+        let boundary = form_data_boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"");
+        assert_eq!(boundary.as_deref(), Some("a b"));
+        assert_eq!(form_data_boundary("multipart/mixed; boundary=x"), None);
+        assert_eq!(form_data_boundary("multipart/form-data; boundary="), None);
+        let long = format!("multipart/form-data; boundary={}", "x".repeat(71));
+        assert_eq!(form_data_boundary(&long), None);
+    }
+
+    fn limited(parts: usize, part_size: usize, size: usize, name_length: usize) -> Options {
+        let mut options = spool_options(64);
+        options.max_multipart_parts = parts;
+        options.max_multipart_part_size = part_size;
+        options.max_multipart_size = size;
+        options.max_multipart_name_length = name_length;
+        options
+    }
+
+    #[tokio::test]
+    async fn enforces_limits_while_streaming() {
+        let cases = [
+            (limited(2, 0, 0, 0), StatusCode::PAYLOAD_TOO_LARGE),
+            (limited(0, 16, 0, 0), StatusCode::PAYLOAD_TOO_LARGE),
+            (limited(0, 0, 100, 0), StatusCode::PAYLOAD_TOO_LARGE),
+            (limited(0, 0, 0, 4), StatusCode::BAD_REQUEST),
+        ];
+        for (options, expected) in cases {
+            let error = upload(BODY, 7, &options).await.unwrap_err();
+            assert_eq!(status(error), expected);
+        }
+        assert!(upload(BODY, 7, &limited(3, 31, 0, 5)).await.is_ok());
+    }
+
+    #[test]
+    fn enforces_limits_on_buffered_bodies() {
+        let check = |options: &Options| MultipartLimits::new(options).check_body(BODY, "XyZ");
+        assert!(check(&limited(3, 31, BODY.len(), 5)).is_none());
+        let error = check(&limited(2, 0, 0, 0)).unwrap();
+        assert_eq!(error.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error.issues[0].message, "3 multipart parts, limit is 2");
+        let error = check(&limited(0, 16, 0, 0)).unwrap();
+        assert_eq!(error.issues[0].message, "multipart part exceeds 16 bytes");
+        let error = check(&limited(0, 0, BODY.len() - 1, 0)).unwrap();
+        assert_eq!(error.issues[0].offset, BODY.len());
+        let error = check(&limited(0, 0, 0, 4)).unwrap();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error.issues[0].header.as_deref(),
+            Some("content-disposition")
+        );
+    }
+}
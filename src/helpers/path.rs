@@ -0,0 +1,63 @@
+use crate::helpers::query::percent_decode_bytes;
+
+/// Percent-decoded request path with `.` and `..` segments and duplicate slashes collapsed,
+/// `..` stops at the root and a trailing slash is kept. `None` for a NUL byte or invalid UTF-8.
+/// Encoded slashes are decoded first, so `..%2F` can not climb out either.
+pub fn decode_path(path: &str) -> Option<String> {
+    let decoded = String::from_utf8(percent_decode_bytes(path, false)).ok()?;
+    if decoded.contains('\0') {
+        return None;
+    }
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    let last = decoded.rsplit('/').next().unwrap_or("");
+    let is_directory = matches!(last, "" | "." | "..");
+    if is_directory && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_path;
+
+    #[test]
+    fn collapses_dot_segments() {
+        assert_eq!(decode_path("/a/./b/../c").as_deref(), Some("/a/c"));
+        assert_eq!(decode_path("//a//b").as_deref(), Some("/a/b"));
+        assert_eq!(decode_path("/a/b/").as_deref(), Some("/a/b/"));
+        assert_eq!(decode_path("/a/b/..").as_deref(), Some("/a/"));
+        assert_eq!(decode_path("/..").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn stops_at_the_root() {
+        assert_eq!(
+            decode_path("/../../etc/passwd").as_deref(),
+            Some("/etc/passwd")
+        );
+        assert_eq!(decode_path("/%2e%2e/%2E%2E/etc").as_deref(), Some("/etc"));
+    }
+
+    #[test]
+    fn decodes_slashes_before_collapsing() {
+        assert_eq!(decode_path("/a/..%2F..%2Fetc").as_deref(), Some("/etc"));
+        assert_eq!(decode_path("/a%2Fb").as_deref(), Some("/a/b"));
+    }
+
+    #[test]
+    fn refuses_nul_and_invalid_utf8() {
+        assert_eq!(decode_path("/a%00.txt"), None);
+        assert_eq!(decode_path("/%ff"), None);
+    }
+}
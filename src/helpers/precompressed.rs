@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::time::Instant;
+
+use crate::helpers::file_cache::{stat_file, FileCache, FileInfo};
+use crate::helpers::file_policy::check_file_access;
+use crate::StaticMount;
+
+/// Sibling suffix of each pre-compressed coding, in order of preference on a q-value tie.
+const SIBLINGS: &[(&str, &str)] = &[("br", ".br"), ("zstd", ".zst"), ("gzip", ".gz")];
+
+/// q-value `Accept-Encoding` gives `coding`, `*` covers codings not listed. `None` when it
+/// is not acceptable.
+pub(crate) fn coding_quality(accept_encoding: &str, coding: &str) -> Option<f32> {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let listed = params.next().unwrap_or("").trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let listed = match listed.eq_ignore_ascii_case("x-gzip") {
+            true => "gzip",
+            false => listed,
+        };
+        if listed.eq_ignore_ascii_case(coding) {
+            return Some(quality).filter(|&quality| quality > 0.0);
+        }
+        if listed == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    wildcard.filter(|&quality| quality > 0.0)
+}
+
+/// `path.br`, `path.zst` or `path.gz` with the best q-value the client accepts, as
+/// (coding, sibling path, sibling info). Stats past `deadline` and siblings the policies of
+/// `mount` refuse count as missing.
+pub(crate) async fn find_precompressed(
+    path: &str,
+    accept_encoding: &str,
+    mount: &StaticMount,
+    deadline: Option<Instant>,
+    cache: Option<&FileCache>,
+) -> Option<(&'static str, String, FileInfo)> {
+    let mut best: Option<(&'static str, String, FileInfo, f32)> = None;
+    for (coding, suffix) in SIBLINGS {
+        let Some(quality) = coding_quality(accept_encoding, coding) else {
+            continue;
+        };
+        if best.as_ref().is_some_and(|best| best.3 >= quality) {
+            continue;
+        }
+        let sibling = format!("{}{}", path, suffix);
+        if check_file_access(Path::new(&sibling), mount)
+            .await
+            .is_some()
+        {
+            continue;
+        }
+        if let Ok(Ok(info)) = stat_file(&sibling, deadline, cache).await {
+            best = Some((coding, sibling, info, quality));
+        }
+    }
+    best.map(|(coding, sibling, info, _)| (coding, sibling, info))
+}
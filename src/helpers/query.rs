@@ -0,0 +1,375 @@
+use serde::de::value::SeqDeserializer;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor,
+};
+
+/// Percent-decoded `key=value` pairs of a query string in order, repeated keys included.
+/// `+` is a space and a pair without `=` has an empty value.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Invalid `%` escapes are kept as is, invalid UTF-8 becomes U+FFFD.
+pub(crate) fn percent_decode(value: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(value, true)).into_owned()
+}
+
+/// Invalid `%` escapes are kept as is, `+` is a space only in query strings.
+pub(crate) fn percent_decode_bytes(value: &str, plus_as_space: bool) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(index + 1..index + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    decoded
+}
+
+/// Why a query string does not fit the requested type, with the offending key when known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "query `{}`: {}", key, self.message),
+            None => write!(f, "query: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl de::Error for QueryError {
+    fn custom<T: std::fmt::Display>(message: T) -> QueryError {
+        QueryError {
+            key: None,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Deserialize a query string into `T`. Repeated keys fill `Vec` fields, other fields take the
+/// first value, and an empty value is `None` for `Option` fields.
+pub fn from_query<T: DeserializeOwned>(query: &str) -> Result<T, QueryError> {
+    let mut entries: Vec<(String, Vec<String>)> = Vec::new();
+    for (key, value) in parse_query(query) {
+        match entries.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, values)) => values.push(value),
+            None => entries.push((key, vec![value])),
+        }
+    }
+    T::deserialize(QueryDeserializer {
+        entries: entries.into_iter(),
+        key: None,
+    })
+}
+
+struct QueryDeserializer {
+    entries: std::vec::IntoIter<(String, Vec<String>)>,
+    key: Option<(String, Vec<String>)>,
+}
+
+impl<'de> Deserializer<'de> for QueryDeserializer {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_map(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for QueryDeserializer {
+    type Error = QueryError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, QueryError> {
+        let Some((key, values)) = self.entries.next() else {
+            return Ok(None);
+        };
+        let name = seed.deserialize(key.clone().into_deserializer())?;
+        self.key = Some((key, values));
+        Ok(Some(name))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, QueryError> {
+        let (key, values) = self.key.take().unwrap_or_default();
+        seed.deserialize(QueryValues(values))
+            .map_err(|error| QueryError {
+                key: error.key.or(Some(key)),
+                message: error.message,
+            })
+    }
+}
+
+/// All values of one key.
+struct QueryValues(Vec<String>);
+
+macro_rules! first_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                self.first().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for QueryValues {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        QueryValue(self.0.into_iter().next().unwrap_or_default()).deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self.0.iter().all(|value| value.is_empty()) {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_seq(SeqDeserializer::new(self.0.into_iter().map(QueryValue)))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    first_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.first().deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl QueryValues {
+    fn first(self) -> QueryValue {
+        QueryValue(self.0.into_iter().next().unwrap_or_default())
+    }
+}
+
+/// One decoded value, parsed into whatever the field asks for.
+pub(crate) struct QueryValue(pub(crate) String);
+
+impl<'de> IntoDeserializer<'de, QueryError> for QueryValue {
+    type Deserializer = QueryValue;
+
+    fn into_deserializer(self) -> QueryValue {
+        self
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                match self.0.trim().parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::custom(format!("invalid value `{}`", self.0))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for QueryValue {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self.0.is_empty() {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    fn owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_pairs_in_order() {
+        assert_eq!(
+            parse_query("b=2&a=1&b=3"),
+            owned(&[("b", "2"), ("a", "1"), ("b", "3")])
+        );
+        assert_eq!(
+            parse_query("flag&&=x&k="),
+            owned(&[("flag", ""), ("", "x"), ("k", "")])
+        );
+        assert!(parse_query("").is_empty());
+    }
+
+    #[test]
+    fn decodes_escapes_and_plus() {
+        assert_eq!(
+            parse_query("q=a+b%20c&%ED%95%9C=%EA%B8%80&eq=a%3Db"),
+            owned(&[("q", "a b c"), ("한", "글"), ("eq", "a=b")])
+        );
+        assert_eq!(percent_decode("100%&%zz%4"), "100%&%zz%4");
+        assert_eq!(percent_decode("%FF"), "\u{FFFD}");
+        assert_eq!(percent_decode_bytes("a+b", false), b"a+b");
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Order {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Search {
+        q: String,
+        page: u32,
+        tag: Vec<String>,
+        order: Option<Order>,
+        limit: Option<u8>,
+        exact: Option<bool>,
+    }
+
+    #[test]
+    fn deserializes_typed_queries() {
+        let search: Search = from_query("q=a+b&page=2&tag=x&tag=y&order=desc&limit=").unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "a b".into(),
+                page: 2,
+                tag: vec!["x".into(), "y".into()],
+                order: Some(Order::Desc),
+                limit: None,
+                exact: None,
+            }
+        );
+        let first: Search = from_query("q=x&page=1&page=9&tag=z&exact=true").unwrap();
+        assert_eq!((first.page, first.exact), (1, Some(true)));
+    }
+
+    #[test]
+    fn names_the_key_that_failed() {
+        let error = from_query::<Search>("q=x&page=two&tag=a").unwrap_err();
+        assert_eq!(error.key.as_deref(), Some("page"));
+        assert_eq!(error.to_string(), "query `page`: invalid value `two`");
+        let error = from_query::<Search>("q=x&page=1&tag=a&order=up").unwrap_err();
+        assert_eq!(error.key.as_deref(), Some("order"));
+        let error = from_query::<Search>("q=x&tag=a").unwrap_err();
+        assert!(error.message.contains("page"), "{}", error);
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Unpredictable enough for boundaries and ids, not for secrets.
+pub(crate) fn random_hex(len: usize) -> String {
+    let mut result = String::with_capacity(len);
+    while result.len() < len {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_nanos())
+                .unwrap_or(0),
+        );
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        result.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    result.truncate(len);
+    result
+}
+
+/// `len` hex characters from the OS CSPRNG, for ids that must not be guessed.
+#[cfg(feature = "tus")]
+pub(crate) fn secure_random_hex(len: usize) -> Result<String, getrandom::Error> {
+    let mut bytes = vec![0; len.div_ceil(2)];
+    getrandom::getrandom(&mut bytes)?;
+    let mut result: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    result.truncate(len);
+    Ok(result)
+}
@@ -0,0 +1,165 @@
+use tokio::io::{AsyncRead, AsyncSeek};
+
+/// Body source for range responses, anything readable and seekable.
+pub trait ByteSource: AsyncRead + AsyncSeek + Unpin + Send {}
+
+impl<T> ByteSource for T where T: AsyncRead + AsyncSeek + Unpin + Send {}
+
+pub(crate) struct SourceBody {
+    pub(crate) reader: Box<dyn ByteSource>,
+    pub(crate) len: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    /// Inclusive start and end.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse a single `Range: bytes=...` value against the representation length.
+pub fn parse_range(value: &str, len: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    parse_spec(spec, len)
+}
+
+/// Parse a `Range: bytes=...` value with any number of ranges, at most `max_ranges` of them.
+/// Unsatisfiable ranges are dropped and overlapping ones merged, a header to ignore gives
+/// `[Full]` and one without any satisfiable range `[Unsatisfiable]`.
+pub fn parse_ranges(value: &str, len: u64, max_ranges: usize) -> Vec<ByteRange> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return vec![ByteRange::Full];
+    };
+    let specs: Vec<&str> = spec
+        .split(',')
+        .filter(|spec| !spec.trim().is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > max_ranges.max(1) {
+        return vec![ByteRange::Full];
+    }
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for spec in specs {
+        match parse_spec(spec, len) {
+            ByteRange::Full => return vec![ByteRange::Full],
+            ByteRange::Partial(start, end) => ranges.push((start, end)),
+            ByteRange::Unsatisfiable => {}
+        }
+    }
+    if ranges.is_empty() {
+        return vec![ByteRange::Unsatisfiable];
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(start, end)| ByteRange::Partial(start, end))
+        .collect()
+}
+
+fn parse_spec(spec: &str, len: u64) -> ByteRange {
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    match end {
+        "" => ByteRange::Partial(start, len - 1),
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => ByteRange::Partial(start, end.min(len - 1)),
+            _ => ByteRange::Full,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ranges, ByteRange};
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=0-9", 100, 4),
+            [ByteRange::Partial(0, 9)]
+        );
+        assert_eq!(
+            parse_ranges("bytes=90-", 100, 4),
+            [ByteRange::Partial(90, 99)]
+        );
+        assert_eq!(
+            parse_ranges("bytes=-10", 100, 4),
+            [ByteRange::Partial(90, 99)]
+        );
+        assert_eq!(
+            parse_ranges("bytes=-500", 100, 4),
+            [ByteRange::Partial(0, 99)]
+        );
+        assert_eq!(
+            parse_ranges("bytes=50-500", 100, 4),
+            [ByteRange::Partial(50, 99)]
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=20-29, 0-4, 3-9", 100, 4),
+            [ByteRange::Partial(0, 9), ByteRange::Partial(20, 29)]
+        );
+        assert_eq!(
+            parse_ranges("bytes=0-4,5-9", 100, 4),
+            [ByteRange::Partial(0, 9)]
+        );
+    }
+
+    #[test]
+    fn drops_unsatisfiable_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=200-300,0-1", 100, 4),
+            [ByteRange::Partial(0, 1)]
+        );
+        assert_eq!(
+            parse_ranges("bytes=200-300", 100, 4),
+            [ByteRange::Unsatisfiable]
+        );
+        assert_eq!(parse_ranges("bytes=-0", 100, 4), [ByteRange::Unsatisfiable]);
+        assert_eq!(parse_ranges("bytes=-5", 0, 4), [ByteRange::Unsatisfiable]);
+    }
+
+    #[test]
+    fn ignores_invalid_headers() {
+        assert_eq!(parse_ranges("items=0-1", 100, 4), [ByteRange::Full]);
+        assert_eq!(parse_ranges("bytes=5-2", 100, 4), [ByteRange::Full]);
+        assert_eq!(parse_ranges("bytes=a-b", 100, 4), [ByteRange::Full]);
+        assert_eq!(parse_ranges("bytes=", 100, 4), [ByteRange::Full]);
+        assert_eq!(
+            parse_ranges("bytes=0-1,3-4,6-7,9-10,12-13", 100, 4),
+            [ByteRange::Full]
+        );
+    }
+}
@@ -0,0 +1,37 @@
+/// `Location` value that stays on this server, or an absolute http(s) URL to one of
+/// `allowed_hosts` (`*.example.com` matches subdomains). `None` for anything else.
+pub fn safe_redirect_target(location: &str, allowed_hosts: &[String]) -> Option<String> {
+    let location = location.trim();
+    if location.is_empty() || location.contains('\\') || location.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    // Scheme relative `//host/...` leaves the server like an absolute URL does.
+    if location.starts_with("//") {
+        return None;
+    }
+    let scheme_end = location.find(':');
+    let reference_end = location.find(['/', '?', '#']).unwrap_or(location.len());
+    let Some(scheme_end) = scheme_end.filter(|&colon| colon < reference_end) else {
+        return Some(location.to_string());
+    };
+
+    let scheme = &location[..scheme_end];
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let uri = location.parse::<http::Uri>().ok()?;
+    let authority = uri.authority()?;
+    if authority.as_str().contains('@') {
+        return None;
+    }
+    let host = authority.host();
+    allowed_hosts
+        .iter()
+        .any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", domain.to_ascii_lowercase())),
+            None => allowed.eq_ignore_ascii_case(host),
+        })
+        .then(|| uri.to_string())
+}
@@ -0,0 +1,670 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use http::header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, ETAG, HOST};
+use http::{Method, Request, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::helpers::body_sink::{BodySink, BodySinkFactory, BodyStorage};
+use crate::helpers::random::random_hex;
+
+type S3Error = Box<dyn Error + Send + Sync>;
+
+const X_AMZ_CONTENT_SHA256: HeaderName = HeaderName::from_static("x-amz-content-sha256");
+const X_AMZ_DATE: HeaderName = HeaderName::from_static("x-amz-date");
+
+/// Smallest part S3 accepts, except for the last one.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Sends the signed requests of `S3MultipartSink`. `HttpTransport` speaks plain HTTP, a TLS
+/// endpoint needs a transport over the application's HTTP client.
+#[async_trait]
+pub trait S3Transport: Send + Sync {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, S3Error>;
+}
+
+/// One connection per request to an `http://` endpoint, like MinIO or a gateway on the host.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HttpTransport;
+
+#[async_trait]
+impl S3Transport for HttpTransport {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, S3Error> {
+        let uri = request.uri();
+        if uri.scheme_str() != Some("http") {
+            return Err(format!("HttpTransport only speaks http://, not {}", uri).into());
+        }
+        let host = uri.host().ok_or("S3 endpoint without host")?;
+        let mut stream = TcpStream::connect((host, uri.port_u16().unwrap_or(80))).await?;
+        let target = uri.path_and_query().map_or("/", |target| target.as_str());
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method(), target);
+        for (name, value) in request.headers() {
+            head.push_str(&format!("{}: {}\r\n", name, value.to_str()?));
+        }
+        head.push_str(&format!(
+            "content-length: {}\r\nconnection: close\r\n\r\n",
+            request.body().len()
+        ));
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(request.body()).await?;
+        stream.flush().await?;
+
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output).await?;
+        parse_response(&output).ok_or_else(|| "malformed S3 response".into())
+    }
+}
+
+/// Bucket, credentials and transport of an S3 compatible store. Objects are addressed path
+/// style, `<endpoint>/<bucket>/<key>`.
+#[derive(Clone)]
+pub struct S3Config {
+    /// Base url, `http://127.0.0.1:9000`.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prefix of the keys `sink_factory` generates.
+    pub key_prefix: String,
+    /// Bytes per uploaded part, `MIN_PART_SIZE` by default. S3 refuses smaller parts but the
+    /// last, other stores may allow them.
+    pub part_size: usize,
+    transport: Arc<dyn S3Transport>,
+}
+
+impl fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("bucket", &self.bucket)
+            .field("key_prefix", &self.key_prefix)
+            .field("part_size", &self.part_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3Config {
+    pub fn new(
+        endpoint: &str,
+        region: &str,
+        bucket: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> S3Config {
+        S3Config {
+            endpoint: endpoint.trim_end_matches('/').into(),
+            region: region.into(),
+            bucket: bucket.into(),
+            access_key: access_key.into(),
+            secret_key: secret_key.into(),
+            key_prefix: String::new(),
+            part_size: MIN_PART_SIZE,
+            transport: Arc::new(HttpTransport),
+        }
+    }
+
+    pub fn transport<T: S3Transport + 'static>(mut self, transport: T) -> S3Config {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    pub fn key_prefix(mut self, key_prefix: &str) -> S3Config {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    pub fn part_size(mut self, part_size: usize) -> S3Config {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// `body_sink` factory uploading each spooled body to a new key under `key_prefix`, the
+    /// body is then `BodyStorage::External` with that key.
+    pub fn sink_factory(self) -> BodySinkFactory {
+        BodySinkFactory::new(move |_| {
+            let key = format!("{}{}", self.key_prefix, random_hex(32));
+            Box::new(S3MultipartSink::new(self.clone(), &key))
+        })
+    }
+
+    /// Signed request for `key` with a canonical `query`, failing on a non 2xx status.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &str,
+        body: Vec<u8>,
+    ) -> Result<Response<Vec<u8>>, S3Error> {
+        let path = format!("/{}/{}", encode(&self.bucket, false), encode(key, true));
+        let uri = match query.is_empty() {
+            true => format!("{}{}", self.endpoint, path),
+            false => format!("{}{}?{}", self.endpoint, path, query),
+        };
+        let mut request = Request::builder().method(method).uri(uri).body(body)?;
+        self.sign(&mut request, SystemTime::now())?;
+        let response = self.transport.send(request).await?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "S3 answered {}: {}",
+                response.status(),
+                String::from_utf8_lossy(response.body())
+            )
+            .into());
+        }
+        Ok(response)
+    }
+
+    /// AWS Signature Version 4 over the host, payload hash and date headers.
+    fn sign(&self, request: &mut Request<Vec<u8>>, now: SystemTime) -> Result<(), S3Error> {
+        let host = request
+            .uri()
+            .authority()
+            .ok_or("S3 endpoint without host")?
+            .to_string();
+        let payload_hash = hex(&Sha256::digest(request.body()));
+        let date = amz_date(now);
+        let headers = [
+            ("host", host.as_str()),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", date.as_str()),
+        ];
+        let authorization = authorization(
+            &Credentials {
+                access_key: &self.access_key,
+                secret_key: &self.secret_key,
+                region: &self.region,
+                service: "s3",
+            },
+            request.method(),
+            request.uri().path(),
+            request.uri().query().unwrap_or(""),
+            &headers,
+            &payload_hash,
+        );
+        let request_headers = request.headers_mut();
+        request_headers.insert(HOST, host.parse()?);
+        request_headers.insert(X_AMZ_CONTENT_SHA256, payload_hash.parse()?);
+        request_headers.insert(X_AMZ_DATE, date.parse()?);
+        request_headers.insert(AUTHORIZATION, authorization.parse()?);
+        Ok(())
+    }
+}
+
+/// Streams a body to one object with a multipart upload, `part_size` bytes per part. A failed
+/// part aborts the upload. A sink dropped before `finish`, when the client goes away, leaves
+/// the upload to the bucket's `AbortIncompleteMultipartUpload` lifecycle rule.
+pub struct S3MultipartSink {
+    config: S3Config,
+    key: String,
+    upload_id: Option<String>,
+    buffer: Vec<u8>,
+    /// ETag of part `n` at `n - 1`.
+    etags: Vec<String>,
+}
+
+impl S3MultipartSink {
+    pub fn new(config: S3Config, key: &str) -> S3MultipartSink {
+        S3MultipartSink {
+            config,
+            key: key.into(),
+            upload_id: None,
+            buffer: Vec::new(),
+            etags: Vec::new(),
+        }
+    }
+
+    /// Upload `buffer` as the next part, starting the upload first.
+    async fn upload_part(&mut self) -> Result<(), S3Error> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let response = self
+                    .config
+                    .send(Method::POST, &self.key, "uploads=", Vec::new())
+                    .await?;
+                let upload_id = xml_value(response.body(), "UploadId")
+                    .ok_or("S3 CreateMultipartUpload answer without UploadId")?;
+                self.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+        let query = format!(
+            "partNumber={}&uploadId={}",
+            self.etags.len() + 1,
+            encode(&upload_id, false)
+        );
+        let part = std::mem::take(&mut self.buffer);
+        let response = self
+            .config
+            .send(Method::PUT, &self.key, &query, part)
+            .await?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .ok_or("S3 UploadPart answer without ETag")?;
+        self.etags.push(etag.into());
+        Ok(())
+    }
+
+    async fn complete(&mut self) -> Result<(), S3Error> {
+        let upload_id = self.upload_id.clone().ok_or("no multipart upload")?;
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (index, etag) in self.etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                index + 1,
+                escape_xml(etag)
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let query = format!("uploadId={}", encode(&upload_id, false));
+        let response = self
+            .config
+            .send(Method::POST, &self.key, &query, body.into_bytes())
+            .await?;
+        // A failed completion can still be a 200, with an `Error` document.
+        if xml_value(response.body(), "Code").is_some() {
+            return Err(format!(
+                "S3 CompleteMultipartUpload failed: {}",
+                String::from_utf8_lossy(response.body())
+            )
+            .into());
+        }
+        self.upload_id = None;
+        Ok(())
+    }
+
+    /// Best effort, the upload failed already.
+    async fn abort(&mut self) {
+        if let Some(upload_id) = self.upload_id.take() {
+            let query = format!("uploadId={}", encode(&upload_id, false));
+            let abort = self
+                .config
+                .send(Method::DELETE, &self.key, &query, Vec::new());
+            if let Err(e) = abort.await {
+                dev_print!("S3 abort of {} failed: {}", self.key, e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BodySink for S3MultipartSink {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), S3Error> {
+        self.buffer.extend_from_slice(bytes);
+        while self.buffer.len() >= self.config.part_size {
+            let rest = self.buffer.split_off(self.config.part_size);
+            let uploaded = self.upload_part().await;
+            self.buffer = rest;
+            if let Err(e) = uploaded {
+                self.abort().await;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+    async fn finish(mut self: Box<Self>) -> Result<BodyStorage, S3Error> {
+        let mut result = Ok(());
+        if !self.buffer.is_empty() || self.etags.is_empty() {
+            result = self.upload_part().await;
+        }
+        if result.is_ok() {
+            result = self.complete().await;
+        }
+        if let Err(e) = result {
+            self.abort().await;
+            return Err(e);
+        }
+        Ok(BodyStorage::External(self.key.clone()))
+    }
+}
+
+struct Credentials<'a> {
+    access_key: &'a str,
+    secret_key: &'a str,
+    region: &'a str,
+    service: &'a str,
+}
+
+/// `Authorization` value of Signature Version 4. `headers` are the signed ones with lowercase
+/// names in order, `query` is sorted here.
+fn authorization(
+    credentials: &Credentials,
+    method: &Method,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    payload_hash: &str,
+) -> String {
+    let date = headers
+        .iter()
+        .find(|(name, _)| *name == "x-amz-date")
+        .map_or("", |(_, value)| value);
+    let mut pairs: Vec<String> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.contains('=') {
+            true => pair.to_string(),
+            false => format!("{}=", pair),
+        })
+        .collect();
+    pairs.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        pairs.join("&"),
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    let scope = format!(
+        "{}/{}/{}/aws4_request",
+        &date[..8.min(date.len())],
+        credentials.region,
+        credentials.service
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = format!("AWS4{}", credentials.secret_key).into_bytes();
+    for part in scope.split('/') {
+        key = hmac(&key, part.as_bytes());
+    }
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key,
+        scope,
+        signed_headers,
+        hex(&hmac(&key, string_to_sign.as_bytes()))
+    )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// `20150830T123600Z`, from the `Sun, 30 Aug 2015 12:36:00 GMT` form.
+fn amz_date(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let date = httpdate::fmt_http_date(time);
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let month = MONTHS
+        .iter()
+        .position(|month| *month == parts[2])
+        .unwrap_or(0)
+        + 1;
+    format!(
+        "{}{:02}{}T{}Z",
+        parts[3],
+        month,
+        parts[1],
+        parts[4].replace(':', "")
+    )
+}
+
+/// Percent-encoding of everything but unreserved characters, and `/` in keys.
+fn encode(value: &str, keep_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            b'/' if keep_slash => "/".into(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Text of the first `<name>` element, enough for the flat documents S3 answers with.
+fn xml_value(body: &[u8], name: &str) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + body[start..].find(&format!("</{}>", name))?;
+    Some(body[start..end].to_string())
+}
+
+/// Status, headers and body of a whole HTTP/1.1 response read until close.
+fn parse_response(output: &[u8]) -> Option<Response<Vec<u8>>> {
+    let head_end = output.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+    let head = std::str::from_utf8(&output[..head_end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()?
+        .split(' ')
+        .nth(1)?
+        .parse::<StatusCode>()
+        .ok()?;
+    let mut response = Response::new(Vec::new());
+    *response.status_mut() = status;
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':')?;
+        let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+        let value = HeaderValue::from_str(value.trim()).ok()?;
+        response.headers_mut().append(name, value);
+    }
+    let body = &output[head_end..];
+    let chunked = response
+        .headers()
+        .get(http::header::TRANSFER_ENCODING)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"chunked"));
+    *response.body_mut() = match chunked {
+        true => dechunk(body)?,
+        false => match response.headers().get(CONTENT_LENGTH) {
+            Some(length) => body.get(..length.to_str().ok()?.parse().ok()?)?.to_vec(),
+            None => body.to_vec(),
+        },
+    };
+    Some(response)
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const EXAMPLE: Credentials = Credentials {
+        access_key: "AKIDEXAMPLE",
+        secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+        region: "us-east-1",
+        service: "service",
+    };
+
+    /// `get-vanilla` and `get-vanilla-query-order-key-case` of the AWS SigV4 test suite.
+    #[test]
+    fn signs_the_aws_test_vectors() {
+        let headers = [
+            ("host", "example.amazonaws.com"),
+            ("x-amz-date", "20150830T123600Z"),
+        ];
+        let empty = hex(&Sha256::digest(b""));
+        let vanilla = authorization(&EXAMPLE, &Method::GET, "/", "", &headers, &empty);
+        assert_eq!(
+            vanilla,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+        let query = "Param2=value2&Param1=value1";
+        let ordered = authorization(&EXAMPLE, &Method::GET, "/", query, &headers, &empty);
+        assert!(ordered.ends_with(
+            "Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+        ));
+    }
+
+    #[test]
+    fn formats_amz_dates() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1440938160);
+        assert_eq!(amz_date(time), "20150830T123600Z");
+        assert_eq!(amz_date(SystemTime::UNIX_EPOCH), "19700101T000000Z");
+    }
+
+    /// A store answering from `answer` on a local port, the requests as `METHOD target body`.
+    async fn store(answer: fn(&Method, &str) -> String) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let requests = seen.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut input = Vec::new();
+                let mut buf = [0; 4096];
+                let head_end = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    input.extend_from_slice(&buf[..n]);
+                    if let Some(end) = input.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&input[..head_end]).into_owned();
+                assert!(head.contains("authorization: AWS4-HMAC-SHA256 Credential=ak/"));
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                while input.len() < head_end + length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    input.extend_from_slice(&buf[..n]);
+                }
+                let mut request_line = head.split(' ');
+                let method: Method = request_line.next().unwrap().parse().unwrap();
+                let target = request_line.next().unwrap().to_string();
+                let body = String::from_utf8_lossy(&input[head_end..]);
+                requests
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {} {}", method, target, body));
+                let output = answer(&method, &target);
+                stream.write_all(output.as_bytes()).await.unwrap();
+            }
+        });
+        (endpoint, seen)
+    }
+
+    fn answer(method: &Method, target: &str) -> String {
+        let (status, headers, body) = match method.as_str() {
+            "POST" if target.ends_with("?uploads=") => (
+                "200 OK",
+                String::new(),
+                "<InitiateMultipartUploadResult><UploadId>u/1</UploadId>\
+                 </InitiateMultipartUploadResult>",
+            ),
+            "PUT" if target.contains("partNumber=9") => {
+                ("500 Internal Server Error", "".into(), "")
+            }
+            "PUT" => {
+                let part = target.split("partNumber=").nth(1).unwrap();
+                let part = part.split('&').next().unwrap();
+                ("200 OK", format!("etag: \"e{}\"\r\n", part), "")
+            }
+            "POST" => ("200 OK", String::new(), "<CompleteMultipartUploadResult/>"),
+            _ => ("204 No Content", String::new(), ""),
+        };
+        format!(
+            "HTTP/1.1 {}\r\n{}content-length: {}\r\n\r\n{}",
+            status,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    #[tokio::test]
+    async fn uploads_a_body_in_parts() {
+        let (endpoint, seen) = store(answer).await;
+        let config = S3Config::new(&endpoint, "us-east-1", "uploads", "ak", "sk").part_size(4);
+        let mut sink: Box<dyn BodySink> = Box::new(S3MultipartSink::new(config, "in/a b.bin"));
+        sink.write(b"hello").await.unwrap();
+        sink.write(b"world!").await.unwrap();
+        let storage = sink.finish().await.unwrap();
+        assert!(matches!(storage, BodyStorage::External(key) if key == "in/a b.bin"));
+        let object = "/uploads/in/a%20b.bin";
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                format!("POST {}?uploads= ", object),
+                format!("PUT {}?partNumber=1&uploadId=u%2F1 hell", object),
+                format!("PUT {}?partNumber=2&uploadId=u%2F1 owor", object),
+                format!("PUT {}?partNumber=3&uploadId=u%2F1 ld!", object),
+                format!(
+                    "POST {}?uploadId=u%2F1 <CompleteMultipartUpload>\
+                     <Part><PartNumber>1</PartNumber><ETag>&quot;e1&quot;</ETag></Part>\
+                     <Part><PartNumber>2</PartNumber><ETag>&quot;e2&quot;</ETag></Part>\
+                     <Part><PartNumber>3</PartNumber><ETag>&quot;e3&quot;</ETag></Part>\
+                     </CompleteMultipartUpload>",
+                    object
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn aborts_after_a_failed_part() {
+        let (endpoint, seen) = store(answer).await;
+        let config = S3Config::new(&endpoint, "us-east-1", "uploads", "ak", "sk").part_size(1);
+        let mut sink = S3MultipartSink::new(config, "key");
+        assert!(sink.write(b"12345678").await.is_ok());
+        assert!(sink.write(b"9").await.is_err());
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 11, "{:?}", seen);
+        assert_eq!(seen[10], "DELETE /uploads/key?uploadId=u%2F1 ");
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::helpers::query::parse_query;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignedUrlError {
+    /// No `expires` or `signature` parameter.
+    Missing,
+    Malformed,
+    Expired,
+    BadSignature,
+}
+
+impl std::fmt::Display for SignedUrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SignedUrlError::Missing => "url is not signed",
+            SignedUrlError::Malformed => "malformed url signature",
+            SignedUrlError::Expired => "signed url expired",
+            SignedUrlError::BadSignature => "bad url signature",
+        })
+    }
+}
+
+impl std::error::Error for SignedUrlError {}
+
+/// HMAC-SHA256 signatures over path and query, for time limited links. Claims are query
+/// parameters added before signing, `expires` and `signature` are appended last.
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Arc<Vec<u8>>,
+}
+
+impl std::fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UrlSigner").finish_non_exhaustive()
+    }
+}
+
+impl UrlSigner {
+    pub fn new(key: impl AsRef<[u8]>) -> UrlSigner {
+        UrlSigner {
+            key: Arc::new(key.as_ref().to_vec()),
+        }
+    }
+
+    /// `url` (path and query, no host) valid until `expires`.
+    pub fn sign(&self, url: &str, expires: SystemTime) -> String {
+        let expires = expires
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let separator = match url.contains('?') {
+            true => '&',
+            false => '?',
+        };
+        let signed = format!("{}{}expires={}", url, separator, expires);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&signed).finalize().into_bytes());
+        format!("{}&signature={}", signed, signature)
+    }
+
+    /// `url` valid for `ttl` from now.
+    pub fn sign_for(&self, url: &str, ttl: Duration) -> String {
+        self.sign(url, SystemTime::now() + ttl)
+    }
+
+    /// Check a url made by `sign`, parameters after `signature` make it invalid.
+    pub fn verify(&self, url: &str) -> Result<(), SignedUrlError> {
+        let Some((signed, signature)) = url.rsplit_once("&signature=") else {
+            return Err(SignedUrlError::Missing);
+        };
+        let Some((_, query)) = signed.split_once('?') else {
+            return Err(SignedUrlError::Missing);
+        };
+        let expires = parse_query(query)
+            .into_iter()
+            .rev()
+            .find(|(key, _)| key == "expires")
+            .ok_or(SignedUrlError::Missing)?
+            .1
+            .parse::<u64>()
+            .map_err(|_| SignedUrlError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| SignedUrlError::Malformed)?;
+        self.mac(signed)
+            .verify_slice(&signature)
+            .map_err(|_| SignedUrlError::BadSignature)?;
+        if SystemTime::now() > UNIX_EPOCH + Duration::from_secs(expires) {
+            return Err(SignedUrlError::Expired);
+        }
+        Ok(())
+    }
+
+    fn mac(&self, signed: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(signed.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_a_signed_url() {
+        let signer = UrlSigner::new("secret");
+        let url = signer.sign_for("/downloads/a.zip?user=7", Duration::from_secs(60));
+        assert_eq!(signer.verify(&url), Ok(()));
+    }
+
+    #[test]
+    fn refuses_tampering() {
+        let signer = UrlSigner::new("secret");
+        let url = signer.sign_for("/downloads/a.zip?user=7", Duration::from_secs(60));
+        let moved = url.replace("a.zip", "b.zip");
+        assert_eq!(signer.verify(&moved), Err(SignedUrlError::BadSignature));
+        let claimed = url.replace("user=7", "user=8");
+        assert_eq!(signer.verify(&claimed), Err(SignedUrlError::BadSignature));
+        let appended = format!("{}&user=8", url);
+        assert_eq!(signer.verify(&appended), Err(SignedUrlError::Malformed));
+        let other = UrlSigner::new("other secret");
+        assert_eq!(other.verify(&url), Err(SignedUrlError::BadSignature));
+    }
+
+    #[test]
+    fn refuses_a_moved_expiry() {
+        let signer = UrlSigner::new("secret");
+        let expires = UNIX_EPOCH + Duration::from_secs(4_000_000_000);
+        let url = signer.sign("/a", expires);
+        let extended = url.replace("expires=4000000000", "expires=4000000001");
+        assert_eq!(signer.verify(&extended), Err(SignedUrlError::BadSignature));
+    }
+
+    #[test]
+    fn refuses_expired_urls() {
+        let signer = UrlSigner::new("secret");
+        let url = signer.sign("/a", UNIX_EPOCH + Duration::from_secs(10));
+        assert_eq!(signer.verify(&url), Err(SignedUrlError::Expired));
+    }
+
+    #[test]
+    fn refuses_unsigned_urls() {
+        let signer = UrlSigner::new("secret");
+        assert_eq!(signer.verify("/a?b=1"), Err(SignedUrlError::Missing));
+        assert_eq!(
+            signer.verify("/a&signature=x"),
+            Err(SignedUrlError::Missing)
+        );
+        assert_eq!(
+            signer.verify("/a?expires=soon&signature=x"),
+            Err(SignedUrlError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::time::Duration;
+
+use http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderValue, Response};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::Receiver;
+
+use crate::helpers::traits::http_response::{get_head, send_bytes};
+use crate::Writer;
+
+#[derive(Debug, Clone, Default)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<Duration>,
+}
+
+impl SseEvent {
+    pub fn new(data: &str) -> SseEvent {
+        SseEvent {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn event(mut self, event: &str) -> SseEvent {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn id(mut self, id: &str) -> SseEvent {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn retry(mut self, retry: Duration) -> SseEvent {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self) -> String {
+        let mut frame = String::new();
+        if let Some(event) = &self.event {
+            frame.push_str(&format!("event: {}\n", event));
+        }
+        if let Some(id) = &self.id {
+            frame.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = self.retry {
+            frame.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split('\n') {
+            frame.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        frame.push('\n');
+        frame
+    }
+}
+
+/// `text/event-stream` response, every event is flushed as it is sent.
+pub struct SseStream {
+    writer: Writer,
+    keep_alive: Duration,
+}
+
+impl SseStream {
+    pub async fn new(mut response: Response<Writer>) -> Result<SseStream, Box<dyn Error>> {
+        *response.status_mut() = http::StatusCode::OK;
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream; charset=utf-8"),
+        );
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        // Keep reverse proxies such as nginx from buffering the stream.
+        response
+            .headers_mut()
+            .insert("x-accel-buffering", HeaderValue::from_static("no"));
+        response.headers_mut().remove(CONTENT_LENGTH);
+        let head = get_head(&response)?;
+        let mut writer = response.into_body();
+        send_bytes(&mut writer.stream, head.as_bytes()).await?;
+        writer.stream.flush().await?;
+        Ok(SseStream {
+            writer,
+            keep_alive: Duration::from_secs(15),
+        })
+    }
+
+    /// Idle interval before `forward` sends a keep-alive comment.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> SseStream {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub async fn send(&mut self, event: &SseEvent) -> Result<(), Box<dyn Error>> {
+        self.write(event.encode().as_bytes()).await
+    }
+
+    pub async fn comment(&mut self, comment: &str) -> Result<(), Box<dyn Error>> {
+        self.write(format!(": {}\n\n", comment).as_bytes()).await
+    }
+
+    /// Send events from `events` until every sender is dropped, with keep-alive comments in between.
+    pub async fn forward(mut self, mut events: Receiver<SseEvent>) -> Result<(), Box<dyn Error>> {
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => self.send(&event).await?,
+                    None => return Ok(()),
+                },
+                _ = tokio::time::sleep(self.keep_alive) => self.comment("keep-alive").await?,
+            }
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.writer.send(bytes).await?;
+        self.writer.stream.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+/// Token bucket on bytes holding one second of `rate`. Bytes are taken after they moved, the
+/// debt is the delay before the next read or write.
+#[derive(Debug, Clone)]
+pub(crate) struct Throttle {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    /// `None` for a rate of 0, no limit.
+    pub(crate) fn new(bytes_per_second: u64) -> Option<Throttle> {
+        (bytes_per_second > 0).then(|| Throttle {
+            rate: bytes_per_second as f64,
+            tokens: bytes_per_second as f64,
+            last: Instant::now(),
+        })
+    }
+
+    /// Take `len` bytes, the wait before more may move when the bucket went into debt.
+    pub(crate) fn consume(&mut self, len: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate) - len as f64;
+        self.last = now;
+        (self.tokens < 0.0).then(|| Duration::from_secs_f64(-self.tokens / self.rate))
+    }
+}
+
+/// Rate of the first prefix `path` starts with, `default` when none does.
+pub(crate) fn route_rate(routes: &[(String, u64)], path: &str, default: u64) -> u64 {
+    routes
+        .iter()
+        .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+        .map_or(default, |(_, rate)| *rate)
+}
@@ -1,6 +1,5 @@
 pub trait SplitBytes {
     fn split_bytes(&self, delimiter: &[u8]) -> Vec<Vec<u8>>;
-    fn split_header_body(&self) -> (Vec<u8>, Vec<u8>);
 }
 
 impl SplitBytes for &[u8] {
@@ -10,36 +9,56 @@ impl SplitBytes for &[u8] {
         for (i, _) in self.iter().enumerate() {
             if self[i..].starts_with(delimiter) {
                 let bytes = self[start..i].to_vec();
-                if bytes.len() > 0 {
+                if !bytes.is_empty() {
                     result.push(bytes);
                 }
                 start = i + delimiter.len();
             }
         }
         let last = self[start..].to_vec();
-        if [45, 45, 13, 10] != last.as_slice() && last.len() > 0 {
+        if [45, 45, 13, 10] != last.as_slice() && !last.is_empty() {
             result.push(last);
         }
         result
     }
-    fn split_header_body(&self) -> (Vec<u8>, Vec<u8>) {
-        let mut header = Vec::new();
-        let mut body = Vec::new();
-        let mut is_header = true;
-        let mut count = 0;
-        for (i, _) in self.iter().enumerate() {
-            if self[i..].starts_with(b"\r\n\r\n") {
-                is_header = false;
+}
+
+pub trait ByteSlice {
+    fn split_lines(&self) -> impl Iterator<Item = &[u8]>;
+    fn trim_whitespace(&self) -> &[u8];
+}
+
+impl ByteSlice for [u8] {
+    fn split_lines(&self) -> impl Iterator<Item = &[u8]> {
+        let mut rest = self;
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
             }
-            if is_header {
-                header.push(self[i]);
-            } else {
-                if count > 3 {
-                    body.push(self[i]);
+            match rest.windows(2).position(|window| window == b"\r\n") {
+                Some(pos) => {
+                    let line = &rest[..pos];
+                    rest = &rest[pos + 2..];
+                    Some(line)
+                }
+                None => {
+                    done = true;
+                    Some(rest)
                 }
-                count += 1;
             }
-        }
-        (header, body)
+        })
+    }
+    fn trim_whitespace(&self) -> &[u8] {
+        let start = self
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .unwrap_or(self.len());
+        let end = self
+            .iter()
+            .rposition(|byte| !byte.is_ascii_whitespace())
+            .map(|pos| pos + 1)
+            .unwrap_or(start);
+        &self[start..end]
     }
 }
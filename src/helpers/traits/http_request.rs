@@ -1,21 +1,45 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::str::FromStr;
 
 use async_trait::async_trait;
+use http::header::{
+    CONNECTION, CONTENT_TYPE, COOKIE, IF_MATCH, IF_UNMODIFIED_SINCE, TE, TRAILER,
+    TRANSFER_ENCODING, UPGRADE,
+};
 use http::HeaderMap;
 use http::HeaderName;
 use http::Request;
+use http::Response;
+use http::StatusCode;
+use serde::de::{self, DeserializeOwned};
 use serde::Deserialize;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use serde_json::error::Category;
 
+use crate::helpers::body_sink::BodyStorage;
+use crate::helpers::conditional::{is_write_precondition_ok, Validators};
+use crate::helpers::cookie::{cookie_map, parse_cookies};
+use crate::helpers::multipart_upload::MultipartUpload;
+use crate::helpers::path::decode_path;
+use crate::helpers::query::{from_query, parse_query, QueryError};
 use crate::helpers::traits::{
-    bytes::SplitBytes,
+    bytes::{ByteSlice, SplitBytes},
     http_stream::{Form, Part},
     GetHeaderChild,
 };
-use crate::Body;
+#[cfg(feature = "signing")]
+use crate::CookieKey;
+use crate::{Body, ParseError, ResponseUtil, Writer};
 
-use super::StringUtil;
+const HOP_BY_HOP_HEADERS: [HeaderName; 7] = [
+    CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+    UPGRADE,
+];
 
 #[async_trait]
 pub trait RequestUtils {
@@ -23,42 +47,186 @@ pub trait RequestUtils {
     where
         T: Deserialize<'a>;
     async fn get_text(&mut self) -> Result<String, Box<dyn Error>>;
+    /// Body bytes deserialized into `T` without a `String` copy. Failures are a 400 `ParseError`
+    /// whose issue has the byte offset in the body.
+    fn json<T: DeserializeOwned>(&self) -> Result<T, Box<ParseError>>;
     async fn get_multi_part(&mut self) -> Result<Option<Form>, Box<dyn Error>>;
+    /// Parts split while reading when `Options.multipart_spool_threshold` is set, taken once.
+    fn take_multipart_upload(&mut self) -> Option<MultipartUpload>;
+    fn is_precondition_ok(&self, validators: &Validators) -> bool;
+    fn normalize_headers(&mut self);
+    /// `decode_path` of the URI path, `None` when it holds a NUL byte or invalid UTF-8.
+    fn decoded_path(&self) -> Option<String>;
+    /// Decoded query pairs of the URI in order, repeated keys included.
+    fn query(&self) -> Vec<(String, String)>;
+    /// First value of `key` in the query.
+    fn query_get(&self, key: &str) -> Option<String>;
+    /// Query deserialized into `T`, repeated keys fill `Vec` fields.
+    fn query_as<T: DeserializeOwned>(&self) -> Result<T, QueryError>;
+    /// Decoded `application/x-www-form-urlencoded` body pairs in order, `None` for other types.
+    fn form(&self) -> Option<Vec<(String, String)>>;
+    /// Urlencoded body deserialized into `T`, like `query_as`.
+    fn form_as<T: DeserializeOwned>(&self) -> Result<T, QueryError>;
+    /// `Cookie` headers by name, the first of duplicated names wins.
+    fn cookies(&self) -> HashMap<String, String>;
+    fn cookie(&self, name: &str) -> Option<String>;
+    /// Value of a cookie set by `ResponseUtil::set_signed_cookie`, `None` if missing or forged.
+    #[cfg(feature = "signing")]
+    fn signed_cookie(&self, name: &str, key: &CookieKey) -> Option<String>;
+    /// Value of a cookie set by `ResponseUtil::set_private_cookie`, `None` if missing or forged.
+    #[cfg(feature = "private_cookies")]
+    fn private_cookie(&self, name: &str, key: &CookieKey) -> Option<String>;
+    async fn check_preconditions(
+        &self,
+        response: &mut Response<Writer>,
+        validators: &Validators,
+    ) -> Result<bool, Box<dyn Error>>;
 }
 
 #[async_trait]
 impl RequestUtils for Request<Body> {
+    fn normalize_headers(&mut self) {
+        let headers = self.headers_mut();
+        let listed: Vec<HeaderName> = headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|name| HeaderName::from_str(name.trim()).ok())
+            .collect();
+        for name in listed.iter().chain(HOP_BY_HOP_HEADERS.iter()) {
+            headers.remove(name);
+        }
+
+        let mut normalized = HeaderMap::with_capacity(headers.len());
+        for (name, value) in headers.iter() {
+            if !normalized.get_all(name).iter().any(|exist| exist == value) {
+                normalized.append(name.clone(), value.clone());
+            }
+        }
+        *headers = normalized;
+    }
+    fn decoded_path(&self) -> Option<String> {
+        decode_path(self.uri().path())
+    }
+    fn query(&self) -> Vec<(String, String)> {
+        self.uri().query().map(parse_query).unwrap_or_default()
+    }
+    fn query_get(&self, key: &str) -> Option<String> {
+        self.query()
+            .into_iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+    fn query_as<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
+        from_query(self.uri().query().unwrap_or(""))
+    }
+    fn form(&self) -> Option<Vec<(String, String)>> {
+        form_body(self).map(|body| parse_query(&body))
+    }
+    fn form_as<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
+        let body = form_body(self).ok_or_else(|| {
+            de::Error::custom("content type is not application/x-www-form-urlencoded")
+        })?;
+        from_query(&body)
+    }
+    fn cookies(&self) -> HashMap<String, String> {
+        cookie_map(
+            self.headers()
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok()),
+        )
+    }
+    fn cookie(&self, name: &str) -> Option<String> {
+        self.headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_cookies)
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, value)| value)
+    }
+    #[cfg(feature = "signing")]
+    fn signed_cookie(&self, name: &str, key: &CookieKey) -> Option<String> {
+        key.verify(name, &self.cookie(name)?)
+    }
+    #[cfg(feature = "private_cookies")]
+    fn private_cookie(&self, name: &str, key: &CookieKey) -> Option<String> {
+        key.decrypt(name, &self.cookie(name)?)
+    }
+    fn take_multipart_upload(&mut self) -> Option<MultipartUpload> {
+        match self.body_mut().storage.take() {
+            Some(BodyStorage::Multipart(upload)) => Some(upload),
+            storage => {
+                self.body_mut().storage = storage;
+                None
+            }
+        }
+    }
+    fn is_precondition_ok(&self, validators: &Validators) -> bool {
+        let if_match = self
+            .headers()
+            .get(IF_MATCH)
+            .and_then(|value| value.to_str().ok());
+        let if_unmodified_since = self
+            .headers()
+            .get(IF_UNMODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok());
+        is_write_precondition_ok(if_match, if_unmodified_since, validators)
+    }
+    async fn check_preconditions(
+        &self,
+        response: &mut Response<Writer>,
+        validators: &Validators,
+    ) -> Result<bool, Box<dyn Error>> {
+        if self.is_precondition_ok(validators) {
+            return Ok(true);
+        }
+        *response.status_mut() = StatusCode::PRECONDITION_FAILED;
+        response.body_mut().body.clear();
+        response.body_mut().bytes.clear();
+        response.responser().await?;
+        Ok(false)
+    }
     async fn get_json<'a, T>(&'a mut self) -> Result<T, Box<dyn Error>>
     where
         T: Deserialize<'a>,
     {
-        if self.body().len > 0 {
-            self.body_mut().body = String::from_utf8_lossy(self.body().bytes.as_slice()).into();
-        }
-
-        let body: T = match self.body().body.as_str() {
+        let body: T = match self.body().body_str()? {
             "" => return Err("Empty body".into()),
             body => serde_json::from_str(body)?,
         };
         Ok(body)
     }
-    async fn get_text(&mut self) -> Result<String, Box<dyn Error>> {
-        if self.body().len > 0 {
-            self.body_mut().body = String::from_utf8_lossy(self.body().bytes.as_slice()).into();
+    fn json<T: DeserializeOwned>(&self) -> Result<T, Box<ParseError>> {
+        let bytes = self.body().bytes.as_ref();
+        let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+        if bytes.trim_whitespace().is_empty() {
+            error.push(0, None, "empty JSON body");
+            return Err(Box::new(error));
         }
-
-        Ok(self.body().body.copy_string())
+        serde_json::from_slice(bytes).map_err(|e| {
+            let kind = match e.classify() {
+                Category::Io => "io",
+                Category::Syntax => "syntax",
+                Category::Data => "data",
+                Category::Eof => "unexpected end",
+            };
+            let offset = json_offset(bytes, e.line(), e.column());
+            error.push(offset, None, format!("JSON {} error: {}", kind, e));
+            Box::new(error)
+        })
+    }
+    async fn get_text(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(self.body().body_str()?.into())
     }
     async fn get_multi_part(&mut self) -> Result<Option<Form>, Box<dyn Error>> {
         let content_type = self.headers().get("content-type");
-        if content_type.is_some()
-            && content_type
-                .unwrap()
-                .to_str()?
-                .contains("multipart/form-data")
+        if let Some(content_type) = content_type
+            .filter(|value| value.to_str().unwrap_or("").contains("multipart/form-data"))
         {
             let boundary = content_type
-                .unwrap()
                 .to_str()?
                 .split("boundary=")
                 .last()
@@ -66,24 +234,28 @@ impl RequestUtils for Request<Body> {
                 .to_owned();
             let mut form = Form {
                 text: ("".into(), "".into()),
+                fields: Vec::new(),
                 parts: Vec::new(),
             };
 
             for part_data in self
                 .body()
                 .bytes
-                .as_slice()
+                .as_ref()
                 .split_bytes(format!("--{}", &boundary).as_bytes())
             {
                 dev_print!("part_data: {:?}", &part_data.len());
-                let mut part_string = String::new();
-                let mut part_bytes = BufReader::new(part_data.as_slice());
-                while let Ok(n) = part_bytes.read_line(&mut part_string).await {
-                    if n == 0 {
-                        break;
-                    }
+                // Headers end at the first blank line, the body ends before the next boundary.
+                let Some(head_end) = part_data
+                    .windows(4)
+                    .position(|window| window == b"\r\n\r\n")
+                else {
+                    continue;
+                };
+                let mut body = part_data[head_end + 4..].to_vec();
+                if body.ends_with(b"\r\n") {
+                    body.truncate(body.len() - 2);
                 }
-                let line_split = part_string.split("\r\n");
 
                 let mut part = Part {
                     name: "".into(),
@@ -91,53 +263,36 @@ impl RequestUtils for Request<Body> {
                     headers: HeaderMap::new(),
                     body: Vec::new(),
                 };
-
-                let mut headers: Vec<(String, String)> = Vec::new();
-                line_split.for_each(|line| {
+                let mut is_file = false;
+                let head = String::from_utf8_lossy(&part_data[..head_end]);
+                for line in head.split("\r\n") {
                     dev_print!("{}", line);
-                    if line.is_empty() {
-                        return;
-                    }
-                    if line.to_lowercase().contains("content-disposition") {
-                        let size_split = line.split(": ");
-                        let value = size_split.last();
-
-                        if value.is_some() && value.unwrap().contains("filename=") {
-                            let headers = value.unwrap().get_header_child();
-                            let name = headers.get("name").unwrap();
-                            let file_name = headers.get("filename").unwrap();
-                            part.name = name.into();
-                            part.file_name = file_name.into();
-                        } else if value.is_some() && value.unwrap().contains("name=") {
-                            let headers = value.unwrap().get_header_child();
-                            let name = headers.get("name").unwrap();
-
-                            form.text = (name.into(), "".into());
-                        }
-                    } else if !line.contains(": ") {
-                        form.text.1 = line.into();
-                    } else {
-                        let mut size_split = line.split(": ");
-                        if size_split.clone().count() != 2 {
-                            return;
+                    let Some((key, value)) = line.split_once(": ") else {
+                        continue;
+                    };
+                    if key.eq_ignore_ascii_case("content-disposition") {
+                        let children = value.get_header_child();
+                        part.name = children.get("name").cloned().unwrap_or_default();
+                        if let Some(file_name) = children.get("filename") {
+                            part.file_name = file_name.clone();
+                            is_file = true;
                         }
-                        let key = size_split.next();
-                        let value = size_split.next();
-
-                        headers.push((key.unwrap().to_lowercase(), value.unwrap().into()));
                     }
-                });
-
-                part.headers = HeaderMap::from_iter(headers.into_iter().map(|(key, value)| {
-                    (
-                        HeaderName::from_str(key.as_str()).unwrap(),
-                        value.parse().unwrap(),
-                    )
-                }));
+                    if let (Ok(key), Ok(value)) = (
+                        HeaderName::from_str(&key.to_lowercase()),
+                        value.parse::<http::HeaderValue>(),
+                    ) {
+                        part.headers.append(key, value);
+                    }
+                }
 
-                if !part.file_name.is_empty() {
-                    part_bytes.read_to_end(&mut part.body).await?;
+                if is_file {
+                    part.body = body;
                     form.parts.push(part);
+                } else {
+                    let value = String::from_utf8_lossy(&body).into_owned();
+                    form.text = (part.name.clone(), value.clone());
+                    form.fields.push((part.name, value));
                 }
             }
             return Ok(Some(form));
@@ -145,3 +300,173 @@ impl RequestUtils for Request<Body> {
         Ok(None)
     }
 }
+
+/// Body text of an urlencoded form request.
+fn form_body(request: &Request<Body>) -> Option<String> {
+    let content_type = request.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if !media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&request.body().bytes).into_owned())
+}
+
+/// Byte offset of a 1-based serde_json line and column.
+fn json_offset(bytes: &[u8], line: usize, column: usize) -> usize {
+    let line_start = match line {
+        0 | 1 => 0,
+        line => bytes
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == b'\n')
+            .nth(line - 2)
+            .map(|(index, _)| index + 1)
+            .unwrap_or(0),
+    };
+    (line_start + column.saturating_sub(1)).min(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::HeaderValue;
+
+    use super::*;
+
+    /// `uri` with `headers` and `body`, as the parser hands it over.
+    fn request(uri: &str, headers: &[(&str, &str)], body: &str) -> Request<Body> {
+        let mut request = Request::new(Body {
+            bytes: Bytes::copy_from_slice(body.as_bytes()),
+            len: body.len(),
+            ip: None,
+            storage: None,
+            trailers: HeaderMap::new(),
+            pending: None,
+        });
+        *request.uri_mut() = uri.parse().unwrap();
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).unwrap();
+            let value = HeaderValue::from_str(value).unwrap();
+            request.headers_mut().append(name, value);
+        }
+        request
+    }
+
+    fn owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_query() {
+        let search = request("/search?q=rust+http&tag=a&tag=b%26c&empty", &[], "");
+        assert_eq!(
+            search.query(),
+            owned(&[
+                ("q", "rust http"),
+                ("tag", "a"),
+                ("tag", "b&c"),
+                ("empty", "")
+            ])
+        );
+        assert_eq!(search.query_get("tag").as_deref(), Some("a"));
+        assert_eq!(search.query_get("empty").as_deref(), Some(""));
+        assert_eq!(search.query_get("missing"), None);
+        assert!(request("/search", &[], "").query().is_empty());
+    }
+
+    #[test]
+    fn deserializes_the_query() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Page {
+            page: u32,
+            size: Option<u32>,
+        }
+        let paged = request("/items?page=3&size=", &[], "");
+        assert_eq!(
+            paged.query_as::<Page>(),
+            Ok(Page {
+                page: 3,
+                size: None
+            })
+        );
+        let error = request("/items", &[], "").query_as::<Page>().unwrap_err();
+        assert!(error.to_string().contains("page"), "{}", error);
+    }
+
+    #[test]
+    fn reads_urlencoded_forms() {
+        let form = [(
+            "content-type",
+            "application/x-www-form-urlencoded; charset=utf-8",
+        )];
+        let login = request("/login", &form, "user=kim+a&pw=%3D1&pw=2");
+        assert_eq!(
+            login.form(),
+            Some(owned(&[("user", "kim a"), ("pw", "=1"), ("pw", "2")]))
+        );
+        let json = [("content-type", "application/json")];
+        assert_eq!(request("/login", &json, "user=kim").form(), None);
+        assert_eq!(request("/login", &[], "user=kim").form(), None);
+        assert_eq!(request("/login", &form, "").form(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn deserializes_urlencoded_forms() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Login {
+            user: String,
+            remember: Option<bool>,
+        }
+        let form = [("content-type", "Application/X-WWW-Form-Urlencoded")];
+        let login = request("/login", &form, "user=kim&remember=true");
+        let expected = Login {
+            user: "kim".into(),
+            remember: Some(true),
+        };
+        assert_eq!(login.form_as::<Login>(), Ok(expected));
+        let error = request("/login", &form, "remember=yes").form_as::<Login>();
+        assert_eq!(error.unwrap_err().key.as_deref(), Some("remember"));
+        let json = [("content-type", "application/json")];
+        let error = request("/login", &json, "user=kim").form_as::<Login>();
+        assert!(error.unwrap_err().message.contains("x-www-form-urlencoded"));
+    }
+
+    #[test]
+    fn reads_cookies_of_every_header() {
+        let headers = [
+            ("cookie", "sid=narrow; theme=\"dark\""),
+            ("cookie", "sid=wide; lang=ko"),
+        ];
+        let browser = request("/", &headers, "");
+        let cookies = browser.cookies();
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies["sid"], "narrow");
+        assert_eq!(cookies["theme"], "dark");
+        assert_eq!(browser.cookie("sid").as_deref(), Some("narrow"));
+        assert_eq!(browser.cookie("lang").as_deref(), Some("ko"));
+        assert_eq!(browser.cookie("missing"), None);
+        assert!(request("/", &[], "").cookies().is_empty());
+    }
+
+    #[test]
+    fn takes_the_multipart_upload_once() {
+        let mut upload = request("/upload", &[], "");
+        upload.body_mut().storage = Some(BodyStorage::Multipart(MultipartUpload {
+            fields: owned(&[("title", "hello")]),
+            files: Vec::new(),
+        }));
+        let taken = upload.take_multipart_upload().unwrap();
+        assert_eq!(taken.fields, owned(&[("title", "hello")]));
+        assert!(upload.take_multipart_upload().is_none());
+        let mut spooled = request("/upload", &[], "");
+        spooled.body_mut().storage = Some(BodyStorage::Memory(b"raw".to_vec()));
+        assert!(spooled.take_multipart_upload().is_none());
+        assert!(matches!(
+            spooled.body().storage,
+            Some(BodyStorage::Memory(_))
+        ));
+    }
+}
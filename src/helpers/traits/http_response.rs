@@ -1,121 +1,1186 @@
 use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use async_trait::async_trait;
-use http::Response;
+use http::{HeaderMap, Method, Response, StatusCode, Version};
 use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
 
-use crate::Writer;
+use crate::helpers::cache_control::set_directive;
+#[cfg(feature = "compression")]
+use crate::helpers::compression::get_compression;
+#[cfg(all(feature = "compression", feature = "response_file"))]
+use crate::helpers::compression::{encode_file, MAX_FILE_SIZE};
+use crate::helpers::conditional::{is_if_range_fresh, is_not_modified};
 #[cfg(feature = "response_file")]
+use crate::helpers::file_cache::FileInfo;
+use crate::helpers::file_cache::{read_file, stat_file};
+#[cfg(feature = "response_file")]
+use crate::helpers::file_policy::find_mount;
+use crate::helpers::file_policy::{check_file_access, resolve_mount, root_mount};
+use crate::helpers::memory::MemoryLease;
+use crate::helpers::path::decode_path;
+use crate::helpers::precompressed::find_precompressed;
+use crate::helpers::random::random_hex;
+use crate::helpers::range::SourceBody;
+use crate::helpers::throttle::Throttle;
+#[cfg(feature = "signing")]
+use crate::CookieKey;
+#[cfg(feature = "response_file")]
+use crate::StaticMount;
+use crate::{
+    parse_ranges, safe_redirect_target, ByteRange, ByteSource, InternalErrorKind, MultipartBuilder,
+    Options, RequestSummary, SetCookie, Validators, Writer,
+};
 use std::path::Path;
 
+/// Largest write between two waits of a paced response.
+const WRITE_PIECE_SIZE: usize = 16384;
+
 impl Writer {
+    /// Request this writer answers, for `Options::report_internal_error`.
+    pub fn request_summary(&self) -> RequestSummary {
+        RequestSummary {
+            method: self.request_method.clone(),
+            path: self.request_path.clone(),
+            request_id: self
+                .request_headers
+                .get("x-request-id")
+                .and_then(|value| value.to_str().ok())
+                .map(String::from),
+            started: self.started,
+        }
+    }
+
     pub async fn write_bytes(&mut self) -> Result<(), Box<dyn Error>> {
-        send_bytes(&mut self.stream, self.bytes.as_slice()).await?;
+        let bytes = std::mem::take(&mut self.bytes);
+        let sent = self.send(&bytes).await;
+        self.bytes = bytes;
+        sent
+    }
+
+    /// Pace the rest of this response at `bytes_per_second`, 0 for no limit. Overrides
+    /// `Options::write_rate_limit` and `write_rate_routes`.
+    pub fn set_write_rate(&mut self, bytes_per_second: u64) {
+        self.write_throttle = Throttle::new(bytes_per_second);
+    }
+
+    /// `send_bytes` paced by the write rate of the response.
+    pub(crate) async fn send(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let Some(throttle) = self.write_throttle.as_mut() else {
+            return send_bytes(&mut self.stream, bytes).await;
+        };
+        for piece in bytes.chunks(WRITE_PIECE_SIZE) {
+            send_bytes(&mut self.stream, piece).await?;
+            if let Some(delay) = throttle.consume(piece.len()) {
+                self.stream.flush().await?;
+                tokio::time::sleep(delay).await;
+            }
+        }
         Ok(())
     }
 
+    /// Serve `len` bytes from a seekable source, `Range` requests get 206/416.
+    pub fn response_reader<R>(&mut self, reader: R, len: u64)
+    where
+        R: ByteSource + 'static,
+    {
+        self.source = Some(SourceBody {
+            reader: Box::new(reader),
+            len,
+        });
+    }
+
+    /// Serve `path` below `root_path`, as `RequestUtils::decoded_path` gives it. A leading `/`
+    /// is dropped, `..`, `.` and absolute paths are refused with an error.
     #[cfg(feature = "response_file")]
     pub fn response_file<P>(&mut self, path: P) -> Result<(), Box<dyn Error>>
     where
         P: AsRef<Path>,
     {
-        let root_path = &self.options.root_path;
-        let path = root_path.join(path);
+        use std::path::Component;
+
+        let relative = path.as_ref().strip_prefix("/").unwrap_or(path.as_ref());
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(format!("Refused file path `{}`", path.as_ref().display()).into());
+        }
+        let path = self.options.root_path.join(relative);
         self.body = path.to_str().unwrap().to_string();
         self.use_file = true;
         Ok(())
     }
+
+    /// Serve request `path` from the `static_mounts` entry with the longest matching prefix,
+    /// under the policies of that mount. `false` when no mount matches, `path` does not decode
+    /// or leaves the mount root, the response is left as it was.
+    #[cfg(feature = "response_file")]
+    pub fn response_static(&mut self, path: &str) -> bool {
+        let Some(path) = decode_path(path) else {
+            return false;
+        };
+        let Some((mount, rest)) = find_mount(&self.options, &path) else {
+            return false;
+        };
+        self.response_mount_file(mount.clone(), rest)
+    }
+
+    /// Serve `rest` of a decoded path under the root and policies of `mount`. `false` for a
+    /// `rest` with other than normal components, like a `..\` segment on Windows.
+    #[cfg(feature = "response_file")]
+    pub(crate) fn response_mount_file(&mut self, mount: StaticMount, rest: &str) -> bool {
+        use std::path::Component;
+
+        let relative = Path::new(rest.trim_start_matches('/'));
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return false;
+        }
+        self.body = mount.root.join(relative).to_string_lossy().into_owned();
+        self.use_file = true;
+        self.mount = Some(mount);
+        true
+    }
 }
 
 #[async_trait]
 pub trait ResponseUtil {
     async fn responser(&mut self) -> Result<(), Box<dyn Error>>;
+    fn response_multipart(&mut self, multipart: MultipartBuilder) -> Result<(), Box<dyn Error>>;
+    /// Redirect with `status` to `location`, refused unless `safe_redirect_target` accepts it.
+    fn redirect(&mut self, location: &str, status: StatusCode) -> Result<(), Box<dyn Error>>;
+    /// `Cache-Control: max-age` with a matching `Expires`, other directives are kept.
+    fn max_age(&mut self, max_age: Duration) -> &mut Self;
+    /// Adds `immutable`, the response never changes while fresh.
+    fn immutable(&mut self) -> &mut Self;
+    /// `public, max-age` of a year and `immutable`, for fingerprinted assets.
+    fn cache_forever(&mut self) -> &mut Self;
+    /// `Cache-Control: no-store` alone, `Expires` is removed.
+    fn no_store(&mut self) -> &mut Self;
+    /// Appends a `Set-Cookie` header.
+    fn set_cookie(&mut self, cookie: SetCookie) -> Result<(), Box<dyn Error>>;
+    /// Cookie whose value is signed with `key`, read back with `RequestUtils::signed_cookie`.
+    #[cfg(feature = "signing")]
+    fn set_signed_cookie(
+        &mut self,
+        cookie: SetCookie,
+        key: &CookieKey,
+    ) -> Result<(), Box<dyn Error>>;
+    /// Cookie whose value is encrypted with `key`, read back with `RequestUtils::private_cookie`.
+    #[cfg(feature = "private_cookies")]
+    fn set_private_cookie(
+        &mut self,
+        cookie: SetCookie,
+        key: &CookieKey,
+    ) -> Result<(), Box<dyn Error>>;
+    /// Whether the connection can serve another request after this response.
+    fn is_keep_alive(&self) -> bool;
+    /// Stream and options for `Server::parse_next_request`, `None` if the connection must close.
+    fn into_keep_alive(self) -> Option<(TcpStream, Options)>;
 }
 
 #[async_trait]
 impl ResponseUtil for Response<Writer> {
+    fn response_multipart(&mut self, multipart: MultipartBuilder) -> Result<(), Box<dyn Error>> {
+        use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+
+        self.headers_mut()
+            .insert(CONTENT_TYPE, multipart.content_type().parse()?);
+        let bytes = multipart.build();
+        self.headers_mut()
+            .insert(CONTENT_LENGTH, bytes.len().into());
+        self.body_mut().bytes = bytes;
+        Ok(())
+    }
+    fn redirect(&mut self, location: &str, status: StatusCode) -> Result<(), Box<dyn Error>> {
+        use http::header::{CONTENT_TYPE, LOCATION};
+
+        if !status.is_redirection() {
+            return Err(format!("{} is not a redirect status", status).into());
+        }
+        let target = safe_redirect_target(location, &self.body().options.redirect_allowed_hosts)
+            .ok_or_else(|| format!("Refused redirect to `{}`", location))?;
+        *self.status_mut() = status;
+        self.headers_mut().remove(CONTENT_TYPE);
+        self.headers_mut().insert(LOCATION, target.parse()?);
+        self.body_mut().body.clear();
+        self.body_mut().bytes.clear();
+        Ok(())
+    }
+    fn max_age(&mut self, max_age: Duration) -> &mut Self {
+        use http::header::EXPIRES;
+
+        set_directive(
+            self.headers_mut(),
+            "max-age",
+            Some(max_age.as_secs().to_string()),
+        );
+        let expires = httpdate::fmt_http_date(SystemTime::now() + max_age);
+        if let Ok(expires) = expires.parse() {
+            self.headers_mut().insert(EXPIRES, expires);
+        }
+        self
+    }
+    fn immutable(&mut self) -> &mut Self {
+        set_directive(self.headers_mut(), "immutable", None);
+        self
+    }
+    fn cache_forever(&mut self) -> &mut Self {
+        set_directive(self.headers_mut(), "public", None);
+        self.max_age(Duration::from_secs(31536000)).immutable()
+    }
+    fn no_store(&mut self) -> &mut Self {
+        use http::header::{HeaderValue, CACHE_CONTROL, EXPIRES};
+
+        self.headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        self.headers_mut().remove(EXPIRES);
+        self
+    }
+    fn set_cookie(&mut self, cookie: SetCookie) -> Result<(), Box<dyn Error>> {
+        use http::header::SET_COOKIE;
+
+        let header = cookie.to_header()?;
+        self.headers_mut().append(SET_COOKIE, header.parse()?);
+        Ok(())
+    }
+    #[cfg(feature = "signing")]
+    fn set_signed_cookie(
+        &mut self,
+        mut cookie: SetCookie,
+        key: &CookieKey,
+    ) -> Result<(), Box<dyn Error>> {
+        cookie.value = key.sign(&cookie.name, &cookie.value);
+        self.set_cookie(cookie)
+    }
+    #[cfg(feature = "private_cookies")]
+    fn set_private_cookie(
+        &mut self,
+        mut cookie: SetCookie,
+        key: &CookieKey,
+    ) -> Result<(), Box<dyn Error>> {
+        cookie.value = key.encrypt(&cookie.name, &cookie.value);
+        self.set_cookie(cookie)
+    }
+    fn is_keep_alive(&self) -> bool {
+        let options = &self.body().options;
+        if !options.keep_alive
+            || options.draining
+            || self.body().unread_body
+            || self.body().send_failed
+            || is_unframed(self)
+            || has_connection_token(&self.body().request_headers, "close")
+            || has_connection_token(self.headers(), "close")
+        {
+            return false;
+        }
+        match self.version() {
+            Version::HTTP_11 => true,
+            Version::HTTP_10 => has_connection_token(&self.body().request_headers, "keep-alive"),
+            _ => false,
+        }
+    }
+    fn into_keep_alive(self) -> Option<(TcpStream, Options)> {
+        if !self.is_keep_alive() {
+            return None;
+        }
+        let writer = self.into_body();
+        Some((writer.stream, writer.options))
+    }
     async fn responser(&mut self) -> Result<(), Box<dyn Error>> {
-        let mut send_string = String::new();
-        if cfg!(feature = "response_file") && self.body().use_file {
-            use http::StatusCode;
-            *self.status_mut() = StatusCode::from_u16(200)?;
-        }
-        let status_line = format!("{:?} {}\r\n", self.version(), self.status());
-        send_string.push_str(&status_line);
-
-        if cfg!(feature = "response_file") && self.body().use_file {
-            use tokio::{
-                fs,
-                io::{self, AsyncReadExt},
+        let result = send_response(self).await;
+        if let Err(error) = &result {
+            self.body_mut().send_failed = true;
+            let kind = match error.downcast_ref::<io::Error>() {
+                Some(e) if e.kind() == io::ErrorKind::TimedOut => InternalErrorKind::Timeout,
+                _ => InternalErrorKind::Send,
+            };
+            let summary = self.body().request_summary();
+            self.body()
+                .options
+                .report_internal_error(&summary, kind, error.as_ref());
+        }
+        result
+    }
+}
+
+async fn send_source(
+    response: &mut Response<Writer>,
+    mut source: SourceBody,
+) -> Result<(), Box<dyn Error>> {
+    use http::header::{
+        HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE,
+        LAST_MODIFIED, RANGE,
+    };
+
+    let len = source.len;
+    let headers = response.headers();
+    let mut validators = Validators::new();
+    if let Some(etag) = headers.get(ETAG).and_then(|value| value.to_str().ok()) {
+        validators = validators.etag(etag);
+    }
+    if let Some(modified) = headers
+        .get(LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+    {
+        validators = validators.last_modified(modified);
+    }
+    let request_headers = &response.body().request_headers;
+    // A stale `If-Range` asks for the whole new representation instead.
+    let if_range_fresh = request_headers
+        .get(IF_RANGE)
+        .map(|value| is_if_range_fresh(value.to_str().unwrap_or(""), &validators))
+        .unwrap_or(true);
+    let max_ranges = response.body().options.max_ranges;
+    let ranges = match request_headers.get(RANGE) {
+        Some(value) if if_range_fresh => {
+            parse_ranges(value.to_str().unwrap_or(""), len, max_ranges)
+        }
+        _ => vec![ByteRange::Full],
+    };
+    response
+        .headers_mut()
+        .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    if ranges.len() > 1 {
+        // Each part carries the type and range, the response is `multipart/byteranges`.
+        let boundary = format!("atomic_http_{}", random_hex(24));
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|content_type| format!("content-type: {}\r\n", content_type))
+            .unwrap_or_default();
+        let mut parts = Vec::with_capacity(ranges.len());
+        let mut body_len = 0;
+        for range in &ranges {
+            let ByteRange::Partial(start, end) = *range else {
+                continue;
             };
+            let part_head = format!(
+                "\r\n--{}\r\n{}content-range: bytes {}-{}/{}\r\n\r\n",
+                boundary, content_type, start, end, len
+            );
+            body_len += part_head.len() as u64 + end - start + 1;
+            parts.push((part_head, start, end - start + 1));
+        }
+        let closing = format!("\r\n--{}--\r\n", boundary);
+        body_len += closing.len() as u64;
+
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            format!("multipart/byteranges; boundary={}", boundary).parse()?,
+        );
+        response
+            .headers_mut()
+            .insert(CONTENT_LENGTH, body_len.into());
+        let head = get_head(response)?;
+        response.body_mut().send(head.as_bytes()).await?;
+        if response.body().request_method == Method::HEAD {
+            return Ok(());
+        }
+        for (part_head, start, length) in parts {
+            response.body_mut().send(part_head.as_bytes()).await?;
+            send_source_range(response, &mut source, start, length).await?;
+        }
+        return response.body_mut().send(closing.as_bytes()).await;
+    }
+
+    let (start, length) = match ranges[0] {
+        ByteRange::Full => {
+            *response.status_mut() = StatusCode::OK;
+            (0, len)
+        }
+        ByteRange::Partial(start, end) => {
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            response.headers_mut().insert(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, len).parse()?,
+            );
+            (start, end - start + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            response
+                .headers_mut()
+                .insert(CONTENT_RANGE, format!("bytes */{}", len).parse()?);
+            response.headers_mut().insert(CONTENT_LENGTH, 0.into());
+            let head = get_head(response)?;
+            return response.body_mut().send(head.as_bytes()).await;
+        }
+    };
+    response.headers_mut().insert(CONTENT_LENGTH, length.into());
+    let head = get_head(response)?;
+    response.body_mut().send(head.as_bytes()).await?;
+    if response.body().request_method == Method::HEAD {
+        return Ok(());
+    }
+
+    send_source_range(response, &mut source, start, length).await
+}
+
+/// Copy `length` bytes of `source` from `start` to the client.
+async fn send_source_range(
+    response: &mut Response<Writer>,
+    source: &mut SourceBody,
+    start: u64,
+    length: u64,
+) -> Result<(), Box<dyn Error>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+    let deadline = response.body().deadline;
+    let seek = before_deadline(deadline, source.reader.seek(SeekFrom::Start(start)));
+    seek.await.map_err(deadline_error)??;
+    let mut reader = (&mut source.reader).take(length);
+    let mut buffer = vec![0; length.clamp(1, 1048576) as usize];
+    loop {
+        let read = before_deadline(deadline, reader.read(&mut buffer));
+        let n = read.await.map_err(deadline_error)??;
+        if n == 0 {
+            break;
+        }
+        response.body_mut().send(&buffer[..n]).await?;
+    }
+    Ok(())
+}
+
+/// `If-None-Match`, or `If-Modified-Since` without it, against a file for GET and HEAD.
+fn is_file_not_modified(
+    response: &Response<Writer>,
+    etag: Option<&str>,
+    modified: Option<std::time::SystemTime>,
+) -> bool {
+    use http::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+
+    let method = &response.body().request_method;
+    if method != Method::GET && method != Method::HEAD {
+        return false;
+    }
+    let mut validators = Validators::new();
+    if let Some(etag) = etag {
+        validators = validators.etag(etag);
+    }
+    if let Some(modified) = modified {
+        validators = validators.last_modified(modified);
+    }
+    let headers = &response.body().request_headers;
+    is_not_modified(
+        headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()),
+        headers.get(IF_MODIFIED_SINCE).and_then(|v| v.to_str().ok()),
+        &validators,
+    )
+}
+
+async fn send_file_not_modified(
+    response: &mut Response<Writer>,
+    etag: Option<String>,
+    modified: Option<std::time::SystemTime>,
+    coding: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    use http::header::{HeaderValue, ETAG, LAST_MODIFIED, VARY};
+
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+    if let Some(etag) = etag {
+        response.headers_mut().insert(ETAG, etag.parse()?);
+    }
+    if let Some(modified) = modified {
+        if !response.headers().contains_key(LAST_MODIFIED) {
+            response
+                .headers_mut()
+                .insert(LAST_MODIFIED, httpdate::fmt_http_date(modified).parse()?);
+        }
+    }
+    if coding.is_some() {
+        response
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    let head = get_head(response)?;
+    response.body_mut().send(head.as_bytes()).await?;
+    response.body_mut().stream.flush().await?;
+    Ok(())
+}
+
+/// `response_file` with a `Range` header, 206 or 416 through `send_source`.
+#[cfg(feature = "response_file")]
+async fn send_file_range(
+    response: &mut Response<Writer>,
+    info: FileInfo,
+) -> Result<(), Box<dyn Error>> {
+    use http::header::{
+        HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, LAST_MODIFIED, X_CONTENT_TYPE_OPTIONS,
+    };
+
+    let path = response.body().body.clone();
+    response
+        .headers_mut()
+        .entry(X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    match path.split('.').next_back() {
+        Some("zip") => {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, "application/zip".parse()?);
+            response
+                .headers_mut()
+                .insert(CONTENT_DISPOSITION, attachment_disposition(&path).parse()?);
+        }
+        _ => {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, get_content_type(&path).parse()?);
+        }
+    }
+    let modified = info
+        .modified
+        .filter(|_| response.body().options.file_validators);
+    if let Some(modified) = modified {
+        if !response.headers().contains_key(ETAG) {
+            let etag = file_etag(info.len, modified);
+            response.headers_mut().insert(ETAG, etag.parse()?);
+        }
+        if !response.headers().contains_key(LAST_MODIFIED) {
+            response
+                .headers_mut()
+                .insert(LAST_MODIFIED, httpdate::fmt_http_date(modified).parse()?);
+        }
+    }
+
+    let deadline = response.body().deadline;
+    let Ok(file) = before_deadline(deadline, tokio::fs::File::open(&path)).await else {
+        return response_deadline(response).await;
+    };
+    send_source(
+        response,
+        SourceBody {
+            reader: Box::new(file?),
+            len: info.len,
+        },
+    )
+    .await
+}
+
+/// `Elapsed` of `before_deadline` as the io `TimedOut` error `responser` reports as a timeout.
+fn deadline_error(_: Elapsed) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        "File IO passed the request deadline",
+    )
+}
+
+/// Run file IO against the request deadline, `Elapsed` when the deadline passed first.
+pub(crate) async fn before_deadline<F: Future>(
+    deadline: Option<Instant>,
+    io: F,
+) -> Result<F::Output, Elapsed> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), io).await,
+        None => Ok(io.await),
+    }
+}
+
+async fn send_response(response: &mut Response<Writer>) -> Result<(), Box<dyn Error>> {
+    if !response.body().options.transforms.is_empty() {
+        let transforms = response.body().options.transforms.clone();
+        transforms.apply_response(response);
+    }
+    #[cfg(debug_assertions)]
+    check_conformance(response);
+
+    let _memory_lease = MemoryLease::new(response.body().body.len() + response.body().bytes.len());
+    let mut send_string = String::new();
+    let head_only = response.body().request_method == Method::HEAD;
+    if is_unframed(response) && !is_bodyless_status(response.status()) {
+        let length = response.body().bytes.len();
+        response
+            .headers_mut()
+            .insert(http::header::CONTENT_LENGTH, length.into());
+    }
+    if response.body().options.keep_alive {
+        use http::header::{HeaderValue, CONNECTION};
+        if !response.is_keep_alive() {
+            response
+                .headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+        } else if response.version() == Version::HTTP_10 {
+            response
+                .headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+        }
+    }
+    if response.body().options.draining {
+        use http::header::{HeaderValue, CONNECTION};
+        response
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    if let Some(path) = take_internal_redirect(response) {
+        send_internal(response, &path).await?;
+        response.body_mut().stream.flush().await?;
+        return Ok(());
+    }
+    if let Some(source) = response.body_mut().source.take() {
+        send_source(response, source).await?;
+        response.body_mut().stream.flush().await?;
+        return Ok(());
+    }
+    if cfg!(feature = "response_file") && response.body().use_file {
+        *response.status_mut() = StatusCode::from_u16(200)?;
+    }
+    #[cfg(feature = "compression")]
+    if !response.body().use_file {
+        compress_body(response)?;
+    }
+    #[cfg(feature = "checksum")]
+    if !response.body().use_file {
+        set_content_digest(response)?;
+    }
+    let status_line = format!("{:?} {}\r\n", response.version(), response.status());
+    send_string.push_str(&status_line);
+
+    if cfg!(feature = "response_file") && response.body().use_file {
+        use tokio::{
+            fs,
+            io::{self, AsyncReadExt},
+        };
+
+        #[cfg(feature = "response_file")]
+        {
+            use http::header::CONTENT_TYPE;
+            response.headers_mut().remove(CONTENT_TYPE);
+            match response.body().body.split('.').next_back().unwrap() {
+                "zip" => {
+                    send_string.push_str("Content-Type: application/zip\r\n");
+                    send_string.push_str(&format!(
+                        "content-disposition: {}\r\n",
+                        attachment_disposition(&response.body().body)
+                    ));
+                }
+                _ => {
+                    send_string.push_str(&format!(
+                        "Content-Type: {}\r\n",
+                        get_content_type(&response.body().body)
+                    ));
+                }
+            }
+        }
+
+        for (key, value) in response.headers().iter() {
+            send_string.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+        }
 
-            #[cfg(feature = "response_file")]
+        let mount = match &response.body().mount {
+            Some(mount) => mount.clone(),
+            None => root_mount(&response.body().options),
+        };
+        if let Some(status) = check_file_access(Path::new(&response.body().body), &mount).await {
+            dev_print!("file refused: {}", &response.body().body);
+            send_empty(response, status).await?;
+            response.body_mut().stream.flush().await?;
+            return Ok(());
+        }
+        let deadline = response.body().deadline;
+        let cache = response.body().options.file_cache.clone();
+        let Ok(info) = stat_file(&response.body().body, deadline, cache.as_ref()).await else {
+            return response_deadline(response).await;
+        };
+        let mut info = info?;
+        let mut path = response.body().body.clone();
+        let is_range = response.body().request_method == Method::GET
+            && response
+                .body()
+                .request_headers
+                .contains_key(http::header::RANGE);
+        // A `.br`, `.zst` or `.gz` sibling the client accepts is sent in place of the file.
+        let mut coding: Option<&str> = None;
+        if response.body().options.precompressed && !is_range {
+            let accept_encoding = response
+                .body()
+                .request_headers
+                .get(http::header::ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("");
+            if let Some((sibling_coding, sibling, sibling_info)) =
+                find_precompressed(&path, accept_encoding, &mount, deadline, cache.as_ref()).await
             {
-                use http::header::CONTENT_TYPE;
-                self.headers_mut().remove(CONTENT_TYPE);
-                match self.body().body.split('.').last().unwrap() {
-                    "zip" => {
-                        send_string.push_str("Content-Type: application/zip\r\n");
-                        send_string.push_str(&format!(
-                            "content-disposition: attachment; filename={}\r\n",
-                            self.body().body
-                        ));
+                coding = Some(sibling_coding);
+                path = sibling;
+                info = sibling_info;
+            }
+        }
+        let precompressed = coding.is_some();
+        let content_length = info.len;
+        // The coding is picked first, each coding has its own ETag.
+        #[cfg(all(feature = "compression", feature = "response_file"))]
+        let encoding = get_compression(
+            response,
+            content_length as usize,
+            Some(&get_content_type(&response.body().body)),
+        )
+        .filter(|_| !precompressed && content_length <= MAX_FILE_SIZE);
+        #[cfg(all(feature = "compression", feature = "response_file"))]
+        let coding = encoding.map(|encoding| encoding.as_str()).or(coding);
+
+        // Without `file_validators` the file gets neither validator nor 304.
+        let modified = info
+            .modified
+            .filter(|_| response.body().options.file_validators);
+        let etag = match response.headers().get(http::header::ETAG) {
+            Some(etag) => etag.to_str().ok().map(String::from),
+            None => modified.map(|modified| {
+                let etag = file_etag(content_length, modified);
+                match coding {
+                    // Each coding is its own representation and needs its own validator.
+                    Some(coding) => format!("{}-{}\"", etag.trim_end_matches('"'), coding),
+                    None => etag,
+                }
+            }),
+        };
+        if is_file_not_modified(response, etag.as_deref(), modified) {
+            return send_file_not_modified(response, etag, modified, coding).await;
+        }
+        #[cfg(feature = "response_file")]
+        if is_range {
+            return send_file_range(response, info).await;
+        }
+
+        // Compressed file, HEAD names the coding without reading the file.
+        let encoded: Option<Arc<Vec<u8>>> = None;
+        #[cfg(all(feature = "compression", feature = "response_file"))]
+        let encoded = match encoding {
+            Some(encoding) if !head_only => {
+                let key = format!("{}\n{}", path, encoding.as_str());
+                match cache.as_ref().and_then(|cache| cache.body(&key, info)) {
+                    Some(bytes) => Some(bytes),
+                    None => {
+                        let cache_dir = response.body().options.compression_cache_dir.clone();
+                        let cache_dir = cache_dir.as_deref();
+                        let encode = encode_file(&path, info, encoding, deadline, cache_dir);
+                        let Ok(bytes) = encode.await else {
+                            return response_deadline(response).await;
+                        };
+                        let bytes = Arc::new(bytes?);
+                        if let Some(cache) = &cache {
+                            cache.put_body(&key, info, bytes.clone());
+                        }
+                        Some(bytes)
                     }
-                    _ => {
-                        send_string.push_str(&format!(
-                            "Content-Type: {}\r\n",
-                            get_content_type(&self.body().body)
-                        ));
+                }
+            }
+            _ => encoded,
+        };
+        // Small enough files come whole from `file_cache`.
+        let encoded = match (&cache, encoded) {
+            (Some(cache), None) if !head_only && cache.fits(content_length) => {
+                let Ok(bytes) = read_file(&path, info, deadline, cache).await else {
+                    return response_deadline(response).await;
+                };
+                Some(bytes?)
+            }
+            (_, encoded) => encoded,
+        };
+        match coding {
+            Some(coding) => {
+                match &encoded {
+                    Some(bytes) => {
+                        send_string.push_str(&format!("content-length: {}\r\n", bytes.len()))
                     }
+                    None if precompressed => {
+                        send_string.push_str(&format!("content-length: {}\r\n", content_length))
+                    }
+                    None => {}
                 }
+                send_string.push_str(&format!(
+                    "content-encoding: {}\r\nvary: accept-encoding\r\n",
+                    coding
+                ));
             }
-
-            for (key, value) in self.headers().iter() {
-                send_string.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+            None => send_string.push_str(&format!(
+                "content-length: {}\r\naccept-ranges: bytes\r\n",
+                encoded
+                    .as_ref()
+                    .map_or(content_length, |bytes| bytes.len() as u64)
+            )),
+        }
+        if !response
+            .headers()
+            .contains_key(http::header::X_CONTENT_TYPE_OPTIONS)
+        {
+            send_string.push_str("x-content-type-options: nosniff\r\n");
+        }
+        if let Some(etag) = &etag {
+            if !response.headers().contains_key(http::header::ETAG) {
+                send_string.push_str(&format!("etag: {}\r\n", etag));
+            }
+        }
+        if let Some(modified) = modified {
+            if !response.headers().contains_key(http::header::LAST_MODIFIED) {
+                send_string.push_str(&format!(
+                    "last-modified: {}\r\n",
+                    httpdate::fmt_http_date(modified)
+                ));
             }
+        }
 
-            let file = fs::File::open(&self.body().body).await?;
-            let content_length = file.metadata().await?.len();
-            send_string.push_str(format!("content-length: {}\r\n", content_length).as_str());
+        // HEAD never opens the file, the metadata is enough. Others open it before the head
+        // so a stalled open still gets 504.
+        let file = match head_only || encoded.is_some() {
+            true => None,
+            false => match before_deadline(deadline, fs::File::open(&path)).await {
+                Ok(file) => Some(file?),
+                Err(_) => return response_deadline(response).await,
+            },
+        };
 
-            send_string.push_str("\r\n");
-            send_bytes(&mut self.body_mut().stream, send_string.as_bytes()).await?;
+        send_string.push_str("\r\n");
+        response.body_mut().send(send_string.as_bytes()).await?;
+        if let Some(bytes) = encoded {
+            response.body_mut().send(&bytes).await?;
+        }
+        let Some(file) = file else {
+            response.body_mut().stream.flush().await?;
+            return Ok(());
+        };
 
-            let mut reader = io::BufReader::new(file);
-            let mut buffer = match content_length < 1048576 * 5 {
-                true => vec![0; content_length as usize],
-                false => vec![0; 1048576 * 5],
-            };
-            while let Ok(len) = reader.read(&mut buffer).await {
-                if len == 0 {
-                    break;
-                }
-                send_bytes(&mut self.body_mut().stream, &buffer[0..len]).await?;
-            }
-        } else if !self.body().bytes.is_empty() {
-            for (key, value) in self.headers().iter() {
-                send_string.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+        let mut reader = io::BufReader::new(file);
+        let mut buffer = match content_length < 1048576 * 5 {
+            true => vec![0; content_length as usize],
+            false => vec![0; 1048576 * 5],
+        };
+        let _buffer_lease = MemoryLease::new(buffer.len());
+        // Past the head a stalled or failed read can only drop the connection.
+        loop {
+            let read = before_deadline(deadline, reader.read(&mut buffer));
+            let len = read.await.map_err(deadline_error)??;
+            if len == 0 {
+                break;
             }
-            send_string.push_str("\r\n");
-            let mut send_string = send_string.as_bytes().to_vec();
-            send_string.extend(self.body().bytes.clone());
-            self.body_mut().bytes = send_string;
-            self.body_mut().write_bytes().await?;
-        } else {
-            let (body, content_string) = get_body(self.body().body.as_str()).await;
+            response.body_mut().send(&buffer[0..len]).await?;
+        }
+    } else if !response.body().bytes.is_empty() {
+        for (key, value) in response.headers().iter() {
+            send_string.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+        }
+        send_string.push_str("\r\n");
+        let mut send_string = send_string.as_bytes().to_vec();
+        if !head_only {
+            send_string.extend(response.body().bytes.clone());
+        }
+        response.body_mut().bytes = send_string;
+        response.body_mut().write_bytes().await?;
+    } else {
+        let (body, content_string) = get_body(response.body().body.as_str()).await;
+        if !is_bodyless_status(response.status()) {
             send_string.push_str(&content_string);
+        }
 
-            for (key, value) in self.headers().iter() {
-                send_string.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
-            }
-            dev_print!("headers: {}", &send_string);
-            send_string.push_str("\r\n");
+        for (key, value) in response.headers().iter() {
+            send_string.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+        }
+        dev_print!("headers: {}", &send_string);
+        send_string.push_str("\r\n");
 
+        if !head_only {
             send_string.push_str(&body);
-            send_bytes(&mut self.body_mut().stream, send_string.as_bytes()).await?;
         }
-        self.body_mut().stream.flush().await?;
-        Ok(())
+        response.body_mut().send(send_string.as_bytes()).await?;
+    }
+    response.body_mut().stream.flush().await?;
+    Ok(())
+}
+
+/// 504 for file IO that outlived the request deadline, nothing of the response was sent yet.
+async fn response_deadline(response: &mut Response<Writer>) -> Result<(), Box<dyn Error>> {
+    let head = format!(
+        "{:?} 504 Gateway Timeout\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+        response.version()
+    );
+    response.body_mut().send(head.as_bytes()).await?;
+    response.body_mut().stream.flush().await?;
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "File IO passed the request deadline",
+    )
+    .into())
+}
+
+/// `X-Accel-Redirect` or `X-Sendfile` set by the handler, removed from the response.
+fn take_internal_redirect(response: &mut Response<Writer>) -> Option<String> {
+    let headers = response.headers_mut();
+    let value = headers
+        .remove("x-accel-redirect")
+        .or_else(|| headers.remove("x-sendfile"))?;
+    headers.remove("x-sendfile");
+    value.to_str().ok().map(String::from)
+}
+
+async fn send_empty(
+    response: &mut Response<Writer>,
+    status: StatusCode,
+) -> Result<(), Box<dyn Error>> {
+    use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+
+    *response.status_mut() = status;
+    response.headers_mut().remove(CONTENT_TYPE);
+    response.headers_mut().insert(CONTENT_LENGTH, 0.into());
+    let head = get_head(response)?;
+    response.body_mut().send(head.as_bytes()).await
+}
+
+/// Serve a file under its `static_mounts` root or `root_path` with validators, 304 and range
+/// support.
+async fn send_internal(response: &mut Response<Writer>, path: &str) -> Result<(), Box<dyn Error>> {
+    use http::header::{
+        HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED, X_CONTENT_TYPE_OPTIONS,
+    };
+    use std::path::Component;
+
+    // Same canonical form as `RequestUtils::decoded_path`, NUL bytes are not found.
+    let Some(path) = decode_path(path) else {
+        return send_empty(response, StatusCode::NOT_FOUND).await;
+    };
+    let (mount, rest) = resolve_mount(&response.body().options, &path);
+    let relative = std::path::Path::new(rest.trim_start_matches('/'));
+    let deadline = response.body().deadline;
+    let metadata = match relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        true => {
+            let path = mount.root.join(relative);
+            if let Some(status) = check_file_access(&path, &mount).await {
+                dev_print!("file refused: {}", path.display());
+                return send_empty(response, status).await;
+            }
+            let Ok(metadata) = before_deadline(deadline, tokio::fs::metadata(path)).await else {
+                return response_deadline(response).await;
+            };
+            metadata.ok().filter(|metadata| metadata.is_file())
+        }
+        false => None,
+    };
+    let Some(metadata) = metadata else {
+        dev_print!("internal redirect not found: {}", path);
+        return send_empty(response, StatusCode::NOT_FOUND).await;
+    };
+    let path = mount.root.join(relative);
+
+    let mut validators = Validators::new();
+    let modified = metadata
+        .modified()
+        .ok()
+        .filter(|_| response.body().options.file_validators);
+    if let Some(modified) = modified {
+        let etag = file_etag(metadata.len(), modified);
+        response.headers_mut().insert(ETAG, etag.parse()?);
+        response
+            .headers_mut()
+            .insert(LAST_MODIFIED, httpdate::fmt_http_date(modified).parse()?);
+        validators = validators.etag(&etag).last_modified(modified);
+    }
+    #[cfg(feature = "response_file")]
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        get_content_type(&path.to_string_lossy()).parse()?,
+    );
+    #[cfg(not(feature = "response_file"))]
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/octet-stream".parse()?);
+    response
+        .headers_mut()
+        .entry(X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+
+    let headers = &response.body().request_headers;
+    let not_modified = is_not_modified(
+        headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()),
+        headers.get(IF_MODIFIED_SINCE).and_then(|v| v.to_str().ok()),
+        &validators,
+    );
+    let method = &response.body().request_method;
+    if not_modified && (method == Method::GET || method == Method::HEAD) {
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        response.headers_mut().remove(CONTENT_TYPE);
+        response.headers_mut().remove(CONTENT_LENGTH);
+        let head = get_head(response)?;
+        return response.body_mut().send(head.as_bytes()).await;
+    }
+
+    let Ok(file) = before_deadline(deadline, tokio::fs::File::open(&path)).await else {
+        return response_deadline(response).await;
+    };
+    let file = file?;
+    send_source(
+        response,
+        SourceBody {
+            reader: Box::new(file),
+            len: metadata.len(),
+        },
+    )
+    .await
+}
+
+#[cfg(feature = "compression")]
+fn compress_body(response: &mut Response<Writer>) -> Result<(), Box<dyn Error>> {
+    use http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+
+    let len = match response.body().bytes.is_empty() {
+        true => response.body().body.len(),
+        false => response.body().bytes.len(),
+    };
+    let Some(encoding) = get_compression(response, len, None) else {
+        return Ok(());
+    };
+    let writer = response.body_mut();
+    writer.bytes = match writer.bytes.is_empty() {
+        true => encoding.encode(writer.body.as_bytes())?,
+        false => encoding.encode(&writer.bytes)?,
+    };
+    writer.body.clear();
+    let length = writer.bytes.len();
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_LENGTH, length.into());
+    headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    Ok(())
+}
+
+/// `Content-Digest` of the string or bytes body as sent, after compression.
+#[cfg(feature = "checksum")]
+fn set_content_digest(response: &mut Response<Writer>) -> Result<(), Box<dyn Error>> {
+    use crate::helpers::checksum::{content_digest, CONTENT_DIGEST};
+
+    let Some(algorithm) = response.body().options.response_checksum else {
+        return Ok(());
+    };
+    if is_bodyless_status(response.status()) || response.headers().contains_key(CONTENT_DIGEST) {
+        return Ok(());
+    }
+    let writer = response.body();
+    let digest = match writer.bytes.is_empty() {
+        true => content_digest(algorithm, writer.body.as_bytes()),
+        false => content_digest(algorithm, &writer.bytes),
+    };
+    response
+        .headers_mut()
+        .insert(CONTENT_DIGEST, digest.parse()?);
+    Ok(())
+}
+
+/// A bytes body with neither Content-Length nor Transfer-Encoding, it only ends when the
+/// connection does.
+fn is_unframed(response: &Response<Writer>) -> bool {
+    use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+
+    let writer = response.body();
+    !writer.bytes.is_empty()
+        && !writer.use_file
+        && writer.source.is_none()
+        && !response.headers().contains_key(CONTENT_LENGTH)
+        && !response.headers().contains_key(TRANSFER_ENCODING)
+}
+
+fn has_connection_token(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get_all(http::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+pub(crate) fn get_head(response: &Response<Writer>) -> Result<String, Box<dyn Error>> {
+    let mut head = format!("{:?} {}\r\n", response.version(), response.status());
+    for (key, value) in response.headers().iter() {
+        head.push_str(&format!("{}: {}\r\n", key.as_str(), value.to_str()?));
+    }
+    head.push_str("\r\n");
+    Ok(head)
+}
+
+/// Size and mtime based validator, the same scheme nginx uses for static files.
+pub(crate) fn file_etag(len: u64, modified: std::time::SystemTime) -> String {
+    let secs = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", secs, len)
+}
+
+fn is_bodyless_status(status: http::StatusCode) -> bool {
+    status.is_informational()
+        || status == http::StatusCode::NO_CONTENT
+        || status == http::StatusCode::NOT_MODIFIED
+}
+
+/// Log handler bugs before they hit the wire, only in debug builds. The response is still sent.
+#[cfg(debug_assertions)]
+fn check_conformance(response: &Response<Writer>) {
+    use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+
+    let writer = response.body();
+    if writer.use_file || writer.source.is_some() {
+        return;
+    }
+    let mut violations: Vec<String> = Vec::new();
+    let body_len = match writer.bytes.is_empty() {
+        true => writer.body.len(),
+        false => writer.bytes.len(),
+    };
+    let content_length = response.headers().get(CONTENT_LENGTH);
+    let chunked = response
+        .headers()
+        .get(TRANSFER_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_lowercase().contains("chunked"))
+        .unwrap_or(false);
+
+    if is_bodyless_status(response.status()) {
+        if body_len > 0 {
+            violations.push(format!("body on {} response", response.status()));
+        }
+        if content_length.is_some() || chunked {
+            violations.push(format!("framing header on {} response", response.status()));
+        }
+    } else {
+        if content_length.is_some() && chunked {
+            violations.push("both Content-Length and chunked Transfer-Encoding".into());
+        }
+        // A HEAD response carries the Content-Length of the GET body it leaves out.
+        let head_only = writer.request_method == Method::HEAD;
+        if let Some(content_length) = content_length.filter(|_| !head_only) {
+            match content_length
+                .to_str()
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+            {
+                Some(length) if length != body_len => violations.push(format!(
+                    "Content-Length {} does not match body size {}",
+                    length, body_len
+                )),
+                Some(_) => {}
+                None => violations.push("invalid Content-Length".into()),
+            }
+        }
+    }
+
+    if !violations.is_empty() {
+        dev_print!("Response conformance failed: {}", violations.join(", "));
     }
 }
 
@@ -128,7 +1193,7 @@ pub async fn send_bytes(
 }
 
 #[cfg(feature = "response_file")]
-fn get_content_type(file_name: &str) -> String {
+pub(crate) fn get_content_type(file_name: &str) -> String {
     let guess = mime_guess::from_path(file_name);
 
     if let Some(mime) = guess.first() {
@@ -138,6 +1203,40 @@ fn get_content_type(file_name: &str) -> String {
     }
 }
 
+/// `attachment` with the quoted file name of `path`, never its directories. A name outside
+/// ASCII goes in `filename*` with `_` standing in for those characters in `filename`.
+#[cfg(feature = "response_file")]
+fn attachment_disposition(path: &str) -> String {
+    let name = std::path::Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("download");
+    let quoted: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => format!("\\{}", c),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            _ => "_".into(),
+        })
+        .collect();
+    if name.is_ascii() {
+        return format!("attachment; filename=\"{}\"", quoted);
+    }
+    let encoded: String = name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        quoted, encoded
+    )
+}
+
 async fn get_body(body: &str) -> (String, String) {
     let length = body.len();
 
@@ -145,3 +1244,52 @@ async fn get_body(body: &str) -> (String, String) {
     dev_print!("content-length: {}\n", &content_length);
     (body.into(), content_length)
 }
+
+#[cfg(all(test, feature = "response_file"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disposition_names_only_the_file() {
+        assert_eq!(
+            attachment_disposition("/srv/www/files/report 1.zip"),
+            "attachment; filename=\"report 1.zip\""
+        );
+        assert_eq!(
+            attachment_disposition("files/a\"b\\c.zip"),
+            "attachment; filename=\"a\\\"b\\\\c.zip\""
+        );
+        assert_eq!(
+            attachment_disposition("files/보고서.zip"),
+            "attachment; filename=\"___.zip\"; filename*=UTF-8''%EB%B3%B4%EA%B3%A0%EC%84%9C.zip"
+        );
+    }
+
+    #[cfg(not(feature = "tokio_rustls"))]
+    #[tokio::test]
+    async fn keeps_mount_files_below_the_root() {
+        use tokio::net::TcpListener;
+
+        use crate::{Options, Server, StaticMount};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let parsed = Server::parse_request(stream, Options::new()).await;
+        let (_, mut response) = parsed.unwrap();
+        let writer = response.body_mut();
+        let mount = StaticMount::new("/assets", "/srv/assets");
+        for rest in ["/../etc/passwd", "a/../../etc/passwd", "./a", "/a//../b"] {
+            assert!(!writer.response_mount_file(mount.clone(), rest), "{}", rest);
+            assert!(!writer.use_file, "{}", rest);
+        }
+        assert!(writer.response_mount_file(mount, "/css/site.css"));
+        assert_eq!(writer.body, "/srv/assets/css/site.css");
+        assert!(writer.use_file);
+    }
+}
@@ -1,16 +1,37 @@
 use async_trait::async_trait;
-use http::header::CONTENT_TYPE;
-use http::{HeaderMap, Request, Response};
+use bytes::Bytes;
+use http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, EXPECT, HOST,
+    ORIGIN, RETRY_AFTER, TRANSFER_ENCODING, VARY,
+};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
 use std::error::Error;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
-use crate::helpers::traits::bytes::SplitBytes;
-use crate::{Body, Options, Writer};
+use crate::helpers::body_sink::{BodySink, BodyStorage, FileSink};
+use crate::helpers::body_stream::PendingBody;
+use crate::helpers::chunked::{is_chunked, ChunkedDecoder};
+use crate::helpers::error::ParseError;
+use crate::helpers::lanes::LanePermit;
+use crate::helpers::memory::{MemoryLease, MemoryUsage};
+use crate::helpers::metrics::{thread_allocations, ParserMetrics};
+use crate::helpers::multipart_upload::{form_data_boundary, MultipartLimits, MultipartSink};
+use crate::helpers::throttle::{route_rate, Throttle};
+use crate::helpers::traits::bytes::ByteSlice;
+use crate::{
+    Body, HeaderValueMode, InternalErrorKind, Options, RequestSummary, RequestUtils, ResponseUtil,
+    Writer,
+};
 
 pub struct Form {
+    /// Last text field.
     pub text: (String, String),
+    /// Every text field in order.
+    pub fields: Vec<(String, String)>,
     pub parts: Vec<Part>,
 }
 
@@ -35,12 +56,277 @@ impl StreamHttp for TcpStream {
         options: &Options,
     ) -> Result<(Request<Body>, Response<Writer>), Box<dyn Error>> {
         self.set_nodelay(options.no_delay)?;
+        let started = Instant::now();
+        let deadline = match options.request_timeout_miliseconds {
+            0 => None,
+            timeout => Some(Instant::now() + Duration::from_millis(timeout)),
+        };
 
-        let (bytes, stream) = get_bytes_from_reader(self, options).await?;
+        if MemoryUsage::is_over(options.memory_budget) {
+            let mut error = ParseError::new(StatusCode::SERVICE_UNAVAILABLE);
+            error.push(
+                0,
+                None,
+                format!(
+                    "{} bytes in request and response buffers, budget is {}",
+                    MemoryUsage::in_use(),
+                    options.memory_budget
+                ),
+            );
+            error
+                .headers
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+            error.headers.insert(RETRY_AFTER, HeaderValue::from(1));
+            response_parse_error(self, &error, options).await?;
+            return Err(error.into());
+        }
+
+        let RequestBytes {
+            bytes,
+            storage,
+            trailers,
+            pipelined,
+            pending,
+            memory_lease,
+            lane_permit,
+            stream,
+        } = get_bytes_from_reader(self, options, started).await?;
+
+        let request = match get_request(bytes, options).await {
+            Ok(mut request) => {
+                request.body_mut().set_storage(storage);
+                request.body_mut().trailers = trailers;
+                request.body_mut().pending = pending;
+                request
+            }
+            Err(error) => {
+                response_parse_error(stream, &error, options).await?;
+                return Err(error.into());
+            }
+        };
+        if let Some(error) = check_content_type(&request, options) {
+            response_parse_error(stream, &error, options).await?;
+            return Err(error.into());
+        }
+        if let Some(error) = check_multipart(&request, options) {
+            response_parse_error(stream, &error, options).await?;
+            return Err(error.into());
+        }
+
+        let (request, mut response) = get_parse_result_from_request(request, stream, options)?;
+        response.body_mut().options.pipelined = pipelined;
+        response.body_mut().memory_lease = memory_lease;
+        response.body_mut().lane_permit = lane_permit;
+        response.body_mut().deadline = deadline;
+        response.body_mut().started = started;
+        response.body_mut().unread_body = request
+            .body()
+            .pending
+            .as_ref()
+            .is_some_and(|pending| !pending.is_received());
+
+        if options.draining
+            && options
+                .drain_health_paths
+                .iter()
+                .any(|path| path == request.uri().path())
+        {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response.responser().await?;
+            return Err("Server is draining".into());
+        }
+
+        if options.handle_server_options
+            && request.method() == Method::OPTIONS
+            && request.uri() == "*"
+        {
+            *response.status_mut() = StatusCode::OK;
+            response.headers_mut().remove(CONTENT_TYPE);
+            response
+                .headers_mut()
+                .insert(ALLOW, options.server_options_allow.parse()?);
+            response.responser().await?;
+            return Err("Handled server-wide OPTIONS request".into());
+        }
+
+        if options.handle_preflight && is_preflight(&request) {
+            response_preflight(&request, &mut response, options).await?;
+            return Err("Handled CORS preflight request".into());
+        }
+
+        #[cfg(feature = "signing")]
+        if let Err(error) = check_signed_url(&request, options) {
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            response.body_mut().body = error.to_string();
+            response.responser().await?;
+            return Err(error.into());
+        }
+
+        let mut request = request;
+        if options.normalize_request {
+            request.normalize_headers();
+        }
+        options.transforms.apply_request(&mut request);
+
+        Ok((request, response))
+    }
+}
+
+/// Signature of requests under `signed_url_paths`, `Ok` for other paths.
+#[cfg(feature = "signing")]
+fn check_signed_url(
+    request: &Request<Body>,
+    options: &Options,
+) -> Result<(), crate::SignedUrlError> {
+    let path = request.uri().path();
+    if !options
+        .signed_url_paths
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()))
+    {
+        return Ok(());
+    }
+    let Some(url_signer) = &options.url_signer else {
+        return Err(crate::SignedUrlError::Missing);
+    };
+    let url = request
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or(path);
+    url_signer.verify(url)
+}
+
+/// `OPTIONS` with `Origin` and `Access-Control-Request-Method`.
+fn is_preflight(request: &Request<Body>) -> bool {
+    request.method() == Method::OPTIONS
+        && request.headers().contains_key(ORIGIN)
+        && request
+            .headers()
+            .contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+async fn response_preflight(
+    request: &Request<Body>,
+    response: &mut Response<Writer>,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let origin = request.headers()[ORIGIN].to_str().unwrap_or("");
+    let headers = response.headers_mut();
+    headers.remove(CONTENT_TYPE);
+    headers.insert(VARY, HeaderValue::from_static("origin"));
+    if !options.is_cors_origin_allowed(origin) {
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        return response.responser().await;
+    }
+
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    // `*` is not accepted together with credentials, echo the origin instead.
+    let allow_origin = match options
+        .cors_allow_origins
+        .iter()
+        .any(|allowed| allowed == "*")
+        && !options.cors_allow_credentials
+    {
+        true => HeaderValue::from_static("*"),
+        false => origin.parse()?,
+    };
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_METHODS,
+        options.cors_allow_methods.parse()?,
+    );
+    let allow_headers = match &options.cors_allow_headers {
+        Some(allow_headers) => Some(allow_headers.parse()?),
+        None => request
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_HEADERS)
+            .cloned(),
+    };
+    if let Some(allow_headers) = allow_headers {
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+    }
+    if options.cors_allow_credentials {
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+    if let Some(max_age) = options.cors_max_age {
+        headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+    }
+    response.responser().await
+}
+
+async fn response_maintenance(
+    response: &mut Response<Writer>,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    if let Some(retry_after) = options.maintenance_retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+    }
+    response.body_mut().body = options.maintenance_page.clone();
+    response.responser().await
+}
 
-        let request = get_request(bytes).await?;
+async fn response_parse_error(
+    stream: TcpStream,
+    error: &ParseError,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let mut response = Response::builder()
+        .status(error.status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(get_writer(stream, options))?;
+    response.headers_mut().extend(error.headers.clone());
+    if options.keep_alive {
+        response
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    response.body_mut().body = error.to_string();
+    response.responser().await
+}
+
+/// Sink errors that are a `ParseError`, like `MultipartSink` limits, are answered first.
+async fn sink_error(
+    stream: TcpStream,
+    error: Box<dyn Error + Send + Sync>,
+    options: &Options,
+) -> Box<dyn Error> {
+    if let Some(parse_error) = error.downcast_ref::<ParseError>() {
+        let _ = response_parse_error(stream, parse_error, options).await;
+    }
+    error
+}
 
-        Ok(get_parse_result_from_request(request, stream, options)?)
+fn get_writer(stream: TcpStream, options: &Options) -> Writer {
+    Writer {
+        stream,
+        body: String::new(),
+        bytes: vec![],
+        use_file: false,
+        options: options.clone(),
+        lane_permit: None,
+        request_method: Method::GET,
+        request_headers: HeaderMap::new(),
+        request_path: String::new(),
+        started: Instant::now(),
+        unread_body: false,
+        send_failed: false,
+        write_throttle: None,
+        mount: None,
+        source: None,
+        memory_lease: MemoryLease::default(),
+        deadline: None,
     }
 }
 
@@ -52,32 +338,62 @@ fn get_parse_result_from_request(
     let version = request.version();
     request.body_mut().ip = options.current_client_addr;
 
+    let mut writer = get_writer(stream, options);
+    writer.request_method = request.method().clone();
+    writer.request_headers = request.headers().clone();
+    writer.request_path = request.uri().path().into();
+    let rate = route_rate(
+        &options.write_rate_routes,
+        &writer.request_path,
+        options.write_rate_limit,
+    );
+    writer.write_throttle = Throttle::new(rate);
+
     Ok((
         request,
         Response::builder()
             .version(version)
             .header(CONTENT_TYPE, "application/json")
             .status(400)
-            .body(Writer {
-                stream,
-                body: String::new(),
-                bytes: vec![],
-                use_file: false,
-                options: options.clone(),
-            })?,
+            .body(writer)?,
     ))
 }
 
+/// What the reader collected for one request.
+struct RequestBytes {
+    bytes: Vec<u8>,
+    storage: Option<BodyStorage>,
+    trailers: HeaderMap,
+    /// Bytes read past the end of this request, the start of a pipelined one.
+    pipelined: Vec<u8>,
+    /// Body left on the socket with `stream_request_body`.
+    pending: Option<PendingBody>,
+    memory_lease: MemoryLease,
+    /// Taken once the head is parsed, before the body is read.
+    lane_permit: Option<LanePermit>,
+    stream: TcpStream,
+}
+
 async fn get_bytes_from_reader(
     mut stream: TcpStream,
     options: &Options,
-) -> Result<(Vec<u8>, TcpStream), Box<dyn Error>> {
+    started: Instant,
+) -> Result<RequestBytes, Box<dyn Error>> {
     let mut bytes: Vec<u8> = vec![];
+    let mut pending = options.pipelined.clone();
+    let mut pipelined: Vec<u8> = vec![];
+    let mut sink: Option<Box<dyn BodySink>> = None;
+    let mut chunked: Option<ChunkedDecoder> = None;
+    let mut pending_body = None;
+    let mut throttle: Option<Throttle> = None;
+    let mut spooled = 0;
+    let mut lane_permit = None;
     let buffer_size = match options.read_buffer_size {
         0 => 4096,
         _ => options.read_buffer_size,
     };
     let mut buf = vec![0; buffer_size];
+    let mut memory_lease = MemoryLease::new(buf.len());
     let mut retry_count = 0;
     let max_retry = options.read_max_retry;
 
@@ -85,21 +401,36 @@ async fn get_bytes_from_reader(
     let mut _content_length = None;
     let mut expected_total_length = None;
     while retry_count < max_retry {
-        match tokio::time::timeout(
-            Duration::from_millis(options.read_timeout_miliseconds),
-            stream.read(&mut buf),
-        )
-        .await
-        {
+        memory_lease.resize(buf.len() + bytes.capacity());
+        // Bytes left over from the previous request on this connection come first.
+        let read = match pending.is_empty() {
+            true => {
+                tokio::time::timeout(
+                    Duration::from_millis(options.read_timeout_miliseconds),
+                    stream.read(&mut buf),
+                )
+                .await
+            }
+            false => {
+                let n = pending.len();
+                if buf.len() < n {
+                    buf.resize(n, 0);
+                }
+                buf[..n].copy_from_slice(&pending);
+                pending.clear();
+                Ok(Ok(n))
+            }
+        };
+        match read {
             Ok(read_result) => match read_result {
                 Ok(n) => {
                     if n == 0 {
                         // 연결이 끊겼지만 데이터가 부족한 경우
                         if let Some(expected) = expected_total_length {
-                            if bytes.len() < expected {
+                            if bytes.len() + spooled < expected {
                                 dev_print!(
                                     "Connection closed but data incomplete: {}/{} bytes",
-                                    bytes.len(),
+                                    bytes.len() + spooled,
                                     expected
                                 );
                                 retry_count += 1;
@@ -108,27 +439,245 @@ async fn get_bytes_from_reader(
                         }
                         break;
                     }
-                    bytes.extend_from_slice(&buf[..n]);
+                    if let Some(delay) = throttle.as_mut().and_then(|t| t.consume(n)) {
+                        tokio::time::sleep(delay).await;
+                    }
+                    if let Some(decoder) = chunked.as_mut() {
+                        let consumed = match feed_chunked(decoder, &buf[..n], &mut bytes, options) {
+                            Ok(consumed) => consumed,
+                            Err(error) => {
+                                let _ = response_parse_error(stream, &error, options).await;
+                                return Err(error);
+                            }
+                        };
+                        if decoder.is_done() {
+                            pipelined.extend_from_slice(&buf[consumed..n]);
+                            break;
+                        }
+                        continue;
+                    }
+                    match sink.as_mut() {
+                        Some(sink) => {
+                            let take = match expected_total_length {
+                                Some(expected) => {
+                                    n.min(expected.saturating_sub(bytes.len() + spooled))
+                                }
+                                None => n,
+                            };
+                            if let Err(error) = sink.write(&buf[..take]).await {
+                                return Err(sink_error(stream, error, options).await);
+                            }
+                            spooled += take;
+                            pipelined.extend_from_slice(&buf[take..n]);
+                        }
+                        None => bytes.extend_from_slice(&buf[..n]),
+                    }
 
                     if !headers_done {
+                        // Stop buffering at the limit instead of reading the rest of the headers.
+                        let header_length = find_headers_end(&bytes).unwrap_or(bytes.len());
+                        if options.max_header_size > 0 && header_length > options.max_header_size {
+                            let mut error =
+                                ParseError::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+                            error.push(
+                                options.max_header_size,
+                                None,
+                                format!(
+                                    "request head is over max_header_size of {} bytes",
+                                    options.max_header_size
+                                ),
+                            );
+                            error
+                                .headers
+                                .insert(CONNECTION, HeaderValue::from_static("close"));
+                            let _ = response_parse_error(stream, &error, options).await;
+                            return Err(error.into());
+                        }
+                        if let Some(error) = check_header_lines(&bytes[..header_length], options) {
+                            let _ = response_parse_error(stream, &error, options).await;
+                            return Err(error.into());
+                        }
+                        if let Some(error) = check_bare_lf(&bytes[..header_length], options) {
+                            let _ = response_parse_error(stream, &error, options).await;
+                            return Err(error.into());
+                        }
                         if let Some(headers_end) = find_headers_end(&bytes) {
                             headers_done = true;
-                            _content_length = parse_content_length(&bytes[..headers_end]);
+                            // Framed from the fields as `get_request` splits them, never from
+                            // a second reading of the head.
+                            let framing = head_fields(&bytes[..headers_end])
+                                .and_then(|fields| Ok((request_framing(&fields)?, fields)));
+                            let (framing, fields) = match framing {
+                                Ok(framing) => framing,
+                                Err(error) => {
+                                    let _ = response_parse_error(stream, &error, options).await;
+                                    return Err(error);
+                                }
+                            };
+                            let head = head_request(&bytes[..headers_end]);
+                            // Refused before any body byte is read or `100 Continue` sent.
+                            if let Some(head) = head.as_ref().filter(|head| {
+                                options.maintenance
+                                    && !options.is_maintenance_allowed(head.uri().path())
+                            }) {
+                                let mut response = Response::builder()
+                                    .version(head.version())
+                                    .body(get_writer(stream, options))?;
+                                response.body_mut().request_method = head.method().clone();
+                                response.body_mut().request_headers = head.headers().clone();
+                                response.body_mut().unread_body = framing != Framing::None;
+                                response_maintenance(&mut response, options).await?;
+                                return Err("Server is in maintenance mode".into());
+                            }
+                            let is_chunked_body = framing == Framing::Chunked;
+                            let length_field = match framing {
+                                Framing::Length(length) => Some(length),
+                                _ => None,
+                            };
+                            if let Some(error) = check_body_size(length_field, options) {
+                                let _ = response_parse_error(stream, &error, options).await;
+                                return Err(error.into());
+                            }
+                            // The lane budget holds while the body is read, not only the
+                            // handler.
+                            if let (Some(priority_lanes), Some(head)) =
+                                (&options.priority_lanes, &head)
+                            {
+                                lane_permit = Some(priority_lanes.acquire(head).await?);
+                            }
+                            let expects_continue = fields.get(EXPECT).is_some_and(|value| {
+                                value.as_bytes().eq_ignore_ascii_case(b"100-continue")
+                            });
+                            if expects_continue
+                                && bytes.len() == headers_end
+                                && (is_chunked_body
+                                    || length_field.is_some_and(|length| length > 0))
+                            {
+                                if let Some(error) = check_expect(&bytes[..headers_end], options) {
+                                    let _ = response_parse_error(stream, &error, options).await;
+                                    return Err(error.into());
+                                }
+                                dev_print!("Sending 100 Continue");
+                                stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+                                stream.flush().await?;
+                            }
+                            throttle = Throttle::new(read_rate(&bytes[..headers_end], options));
+                            let head_body = bytes.len() - headers_end;
+                            if let Some(delay) =
+                                throttle.as_mut().and_then(|t| t.consume(head_body))
+                            {
+                                tokio::time::sleep(delay).await;
+                            }
+                            let content_length = length_field.unwrap_or(0);
+                            if options.stream_request_body
+                                && (is_chunked_body || content_length > 0)
+                            {
+                                let mut raw = bytes.split_off(headers_end);
+                                if !is_chunked_body {
+                                    if raw.len() > content_length {
+                                        pipelined = raw.split_off(content_length);
+                                    }
+                                    let body = PendingBody::length(raw, content_length);
+                                    pending_body = Some(body.throttled(throttle));
+                                    break;
+                                }
+                                let mut decoder = ChunkedDecoder::new(options);
+                                let mut decoded = Vec::new();
+                                let consumed =
+                                    match feed_chunked(&mut decoder, &raw, &mut decoded, options) {
+                                        Ok(consumed) => consumed,
+                                        Err(error) => {
+                                            let _ =
+                                                response_parse_error(stream, &error, options).await;
+                                            return Err(error);
+                                        }
+                                    };
+                                if decoder.is_done() {
+                                    pipelined.extend_from_slice(&raw[consumed..]);
+                                }
+                                let body = PendingBody::chunked(decoded, decoder);
+                                pending_body = Some(body.throttled(throttle));
+                                break;
+                            }
+                            if is_chunked_body {
+                                let raw = bytes.split_off(headers_end);
+                                let mut decoder = ChunkedDecoder::new(options);
+                                let consumed =
+                                    match feed_chunked(&mut decoder, &raw, &mut bytes, options) {
+                                        Ok(consumed) => consumed,
+                                        Err(error) => {
+                                            let _ =
+                                                response_parse_error(stream, &error, options).await;
+                                            return Err(error);
+                                        }
+                                    };
+                                if decoder.is_done() {
+                                    pipelined.extend_from_slice(&raw[consumed..]);
+                                    chunked = Some(decoder);
+                                    break;
+                                }
+                                chunked = Some(decoder);
+                                continue;
+                            }
+                            _content_length = length_field;
 
-                            if let Some(length) = _content_length {
+                            if let Framing::Length(length) = framing {
+                                let multipart_boundary = fields
+                                    .get(CONTENT_TYPE)
+                                    .and_then(|value| value.to_str().ok())
+                                    .and_then(form_data_boundary);
+                                if multipart_boundary.is_some() {
+                                    let limits = MultipartLimits::new(options);
+                                    if let Some(error) = limits.check_size(length) {
+                                        let _ = response_parse_error(stream, &error, options).await;
+                                        return Err(error.into());
+                                    }
+                                }
+                                let multipart_boundary = multipart_boundary
+                                    .filter(|_| options.multipart_spool_threshold > 0);
+                                if multipart_boundary.is_some()
+                                    || options.body_spool_threshold > 0
+                                        && length > options.body_spool_threshold
+                                {
+                                    let mut body_sink: Box<dyn BodySink> =
+                                        match (multipart_boundary, &options.body_sink) {
+                                            (Some(boundary), _) => {
+                                                Box::new(MultipartSink::new(&boundary, options))
+                                            }
+                                            (None, Some(factory)) => factory.create(length),
+                                            (None, None) => Box::new(
+                                                FileSink::new(&options.body_spool_dir)
+                                                    .await
+                                                    .map_err(|e| e.to_string())?,
+                                            ),
+                                        };
+                                    let mut body = bytes.split_off(headers_end);
+                                    if body.len() > length {
+                                        pipelined = body.split_off(length);
+                                    }
+                                    if let Err(error) = body_sink.write(&body).await {
+                                        return Err(sink_error(stream, error, options).await);
+                                    }
+                                    spooled += body.len();
+                                    sink = Some(body_sink);
+                                }
                                 expected_total_length = Some(headers_end + length);
                                 dev_print!("Expected total length: {}", headers_end + length);
                                 if let Some(expected) = expected_total_length {
-                                    if bytes.len() >= expected {
+                                    if bytes.len() + spooled >= expected {
                                         break;
                                     }
                                 }
-                            } else {
+                            } else if framing == Framing::None {
+                                // Neither Content-Length nor Transfer-Encoding, so no body: what
+                                // follows the head is the next request. Any other framing was
+                                // either read above or refused, closing the connection.
+                                pipelined = bytes.split_off(headers_end);
                                 break;
                             }
                         }
                     } else if let Some(expected) = expected_total_length {
-                        if bytes.len() >= expected {
+                        if bytes.len() + spooled >= expected {
                             break;
                         }
                     }
@@ -147,19 +696,60 @@ async fn get_bytes_from_reader(
         }
     }
 
-    if bytes.len() == 0 {
+    if bytes.is_empty() {
         stream.flush().await?;
         return Err("no data".into());
     }
 
     // 최종 데이터 검증
+    if chunked.as_ref().is_some_and(|decoder| !decoder.is_done()) {
+        stream.flush().await?;
+        return Err(format!(
+            "Incomplete chunked body after {} retries: decoded {} bytes",
+            max_retry,
+            chunked
+                .as_ref()
+                .map(|decoder| decoder.decoded())
+                .unwrap_or(0)
+        )
+        .into());
+    }
     if let Some(expected) = expected_total_length {
-        if bytes.len() < expected {
+        if bytes.len() + spooled < expected {
             stream.flush().await?;
+            if let Some(request) =
+                find_headers_end(&bytes).and_then(|end| head_request(&bytes[..end]))
+            {
+                let summary = RequestSummary::new(&request, started);
+                let error = io::Error::new(io::ErrorKind::TimedOut, "request body read timed out");
+                options.report_internal_error(&summary, InternalErrorKind::Timeout, &error);
+            }
+            if options.read_timeout_response {
+                if let (Some(headers_end), Some(content_length)) =
+                    (find_headers_end(&bytes), _content_length)
+                {
+                    let received = bytes.len() + spooled - headers_end;
+                    let mut error = ParseError::new(StatusCode::REQUEST_TIMEOUT);
+                    error.push(
+                        bytes.len() + spooled,
+                        None,
+                        format!("received {}/{} body bytes", received, content_length),
+                    );
+                    error.headers.insert(
+                        HeaderName::from_static("x-bytes-received"),
+                        HeaderValue::from(received),
+                    );
+                    error
+                        .headers
+                        .insert(CONNECTION, HeaderValue::from_static("close"));
+                    let _ = response_parse_error(stream, &error, options).await;
+                    return Err(error.into());
+                }
+            }
             return Err(format!(
                 "Incomplete data after {} retries: got {}/{} bytes{}",
                 max_retry,
-                bytes.len(),
+                bytes.len() + spooled,
                 expected,
                 match options.read_imcomplete_size {
                     0 => "".into(),
@@ -178,134 +768,940 @@ async fn get_bytes_from_reader(
         }
     }
 
-    Ok((bytes, stream))
+    if let Some(expected) = expected_total_length {
+        if sink.is_none() && bytes.len() > expected {
+            pipelined = bytes.split_off(expected);
+        }
+    }
+    if !pipelined.is_empty() {
+        dev_print!("Pipelined bytes: {}", pipelined.len());
+    }
+
+    let storage = match sink {
+        Some(sink) => match sink.finish().await {
+            Ok(storage) => Some(storage),
+            Err(error) => return Err(sink_error(stream, error, options).await),
+        },
+        None => None,
+    };
+
+    let trailers = match chunked.as_mut() {
+        Some(decoder) => decoder.take_trailers(),
+        None => HeaderMap::new(),
+    };
+
+    drop(buf);
+    memory_lease.resize(bytes.capacity());
+
+    Ok(RequestBytes {
+        bytes,
+        storage,
+        trailers,
+        pipelined,
+        pending: pending_body,
+        memory_lease,
+        lane_permit,
+        stream,
+    })
 }
 
-async fn get_request(bytes: Vec<u8>) -> Result<Request<Body>, Box<dyn Error>> {
-    dev_print!("bytes len: {:?}", &bytes.len());
+/// `max_multipart_*` limits of an in memory multipart body, spooled bodies were checked while
+/// reading.
+fn check_multipart(request: &Request<Body>, options: &Options) -> Option<ParseError> {
+    let content_type = request.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+    let boundary = form_data_boundary(content_type)?;
+    MultipartLimits::new(options).check_body(&request.body().bytes, &boundary)
+}
+
+/// Magic-byte check of an in memory body against its declared type, for the types in
+/// `verify_content_types`. Spooled bodies are not checked.
+fn check_content_type(request: &Request<Body>, options: &Options) -> Option<ParseError> {
+    let declared = request.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+    let media_type = declared.split(';').next()?.trim().to_ascii_lowercase();
+    if !options
+        .verify_content_types
+        .iter()
+        .any(|verified| verified.eq_ignore_ascii_case(&media_type))
+    {
+        return None;
+    }
+    let body = request.body().bytes.as_ref();
+    if body.is_empty() {
+        return None;
+    }
+    let matches = match media_type.as_str() {
+        "application/json" => matches!(
+            body.trim_whitespace().first(),
+            Some(b'{' | b'[' | b'"' | b'-' | b'0'..=b'9' | b't' | b'f' | b'n')
+        ),
+        "multipart/form-data" => declared
+            .split("boundary=")
+            .nth(1)
+            .map(|boundary| boundary.split(';').next().unwrap_or("").trim_matches('"'))
+            .is_some_and(|boundary| {
+                let delimiter = format!("--{}", boundary);
+                body.starts_with(delimiter.as_bytes())
+                    || body.windows(delimiter.len() + 2).any(|window| {
+                        window[2..] == *delimiter.as_bytes() && window[..2] == *b"\r\n"
+                    })
+            }),
+        _ => true,
+    };
+    if matches {
+        return None;
+    }
+    let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+    error.push(
+        0,
+        Some("content-type"),
+        format!("body does not look like {}", media_type),
+    );
+    Some(error)
+}
+
+/// Request line and headers of a head, the body is left out.
+fn head_request(head: &[u8]) -> Option<Request<()>> {
+    let mut lines = head.split_lines();
+    let mut request_line = lines.next()?.split(|&byte| byte == b' ');
+    let mut request = Request::new(());
+    if let Ok(method) = Method::from_bytes(request_line.next()?) {
+        *request.method_mut() = method;
+    }
+    if let Ok(uri) = http::Uri::try_from(request_line.next()?) {
+        *request.uri_mut() = uri;
+    }
+    if request_line.next() == Some(b"HTTP/1.0") {
+        *request.version_mut() = http::Version::HTTP_10;
+    }
+    for line in lines {
+        let Ok((name, value)) = split_header_line(line) else {
+            continue;
+        };
+        if let (Ok(name), Ok(value)) =
+            (HeaderName::from_bytes(name), HeaderValue::from_bytes(value))
+        {
+            request.headers_mut().append(name, value);
+        }
+    }
+    Some(request)
+}
+
+/// `max_header_line_size` and `max_header_count` over the head read so far, 431 when over.
+fn check_header_lines(head: &[u8], options: &Options) -> Option<ParseError> {
+    if options.max_header_line_size == 0 && options.max_header_count == 0 {
+        return None;
+    }
+    let mut offset = head.iter().position(|&byte| byte == b'\n')? + 1;
+    let mut count = 0;
+    let mut issue = None;
+    while offset < head.len() {
+        let end = head[offset..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(head.len(), |end| offset + end);
+        let line = head[offset..end]
+            .strip_suffix(b"\r")
+            .unwrap_or(&head[offset..end]);
+        if !line.is_empty() {
+            count += 1;
+        }
+        let name = line.split(|&byte| byte == b':').next().unwrap_or(line);
+        let name = String::from_utf8_lossy(name).into_owned();
+        if options.max_header_line_size > 0 && line.len() > options.max_header_line_size {
+            let message = format!(
+                "header line of {} bytes is over max_header_line_size of {} bytes",
+                line.len(),
+                options.max_header_line_size
+            );
+            issue = Some((offset, Some(name), message));
+            break;
+        }
+        if options.max_header_count > 0 && count > options.max_header_count {
+            let message = format!(
+                "{} headers is over max_header_count of {}",
+                count, options.max_header_count
+            );
+            issue = Some((offset, None, message));
+            break;
+        }
+        offset = end + 1;
+    }
+    let (offset, name, message) = issue?;
+    let mut error = ParseError::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    error.push(offset, name.as_deref(), message);
+    error
+        .headers
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+    Some(error)
+}
+
+/// 400 for a bare LF in the head read so far under `strict_framing`. A head ending in `\n\n`
+/// never has the `\r\n\r\n` the reader waits for, so it is refused here instead of timing out.
+fn check_bare_lf(head: &[u8], options: &Options) -> Option<ParseError> {
+    if !options.strict_framing {
+        return None;
+    }
+    let index = (0..head.len())
+        .find(|&index| head[index] == b'\n' && (index == 0 || head[index - 1] != b'\r'))?;
+    let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+    error.push(index, None, "bare LF in request head");
+    error
+        .headers
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+    Some(error)
+}
+
+/// `read_rate_limit`, or the rate of the first `read_rate_routes` prefix of the request path.
+fn read_rate(head: &[u8], options: &Options) -> u64 {
+    if options.read_rate_routes.is_empty() {
+        return options.read_rate_limit;
+    }
+    match head_request(head) {
+        Some(request) => route_rate(
+            &options.read_rate_routes,
+            request.uri().path(),
+            options.read_rate_limit,
+        ),
+        None => options.read_rate_limit,
+    }
+}
+
+/// 413 for a Content-Length over `max_body_size`, answered before the body is read.
+fn check_body_size(length: Option<usize>, options: &Options) -> Option<ParseError> {
+    let length = length?;
+    if options.max_body_size == 0 || length <= options.max_body_size {
+        return None;
+    }
+    let mut error = ParseError::new(StatusCode::PAYLOAD_TOO_LARGE);
+    error.push(
+        0,
+        Some("content-length"),
+        format!(
+            "body of {} bytes is over max_body_size of {} bytes",
+            length, options.max_body_size
+        ),
+    );
+    error
+        .headers
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+    Some(error)
+}
+
+/// Ask `expect_continue` about the head, 417 when the body is refused.
+fn check_expect(head: &[u8], options: &Options) -> Option<ParseError> {
+    let expect_continue = options.expect_continue.as_ref()?;
+    let request = head_request(head)?;
+    if expect_continue.check(&request) {
+        return None;
+    }
+    let mut error = ParseError::new(StatusCode::EXPECTATION_FAILED);
+    error.push(0, Some("expect"), "request body refused before upload");
+    Some(error)
+}
+
+fn feed_chunked(
+    decoder: &mut ChunkedDecoder,
+    input: &[u8],
+    bytes: &mut Vec<u8>,
+    options: &Options,
+) -> Result<usize, Box<ParseError>> {
+    let consumed = match decoder.feed(input, bytes) {
+        Ok(consumed) => consumed,
+        Err(message) => {
+            let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+            error.push(bytes.len(), Some("transfer-encoding"), message);
+            return Err(Box::new(error));
+        }
+    };
+    if options.max_body_size > 0 && decoder.declared() > options.max_body_size {
+        let mut error = ParseError::new(StatusCode::PAYLOAD_TOO_LARGE);
+        error.push(
+            bytes.len(),
+            Some("transfer-encoding"),
+            format!(
+                "chunked body is over max_body_size of {} bytes",
+                options.max_body_size
+            ),
+        );
+        return Err(Box::new(error));
+    }
+    if options.max_chunked_size > 0 && decoder.declared() > options.max_chunked_size {
+        let mut error = ParseError::new(StatusCode::PAYLOAD_TOO_LARGE);
+        error.push(
+            bytes.len(),
+            Some("transfer-encoding"),
+            format!("chunked body exceeds {} bytes", options.max_chunked_size),
+        );
+        return Err(Box::new(error));
+    }
+    Ok(consumed)
+}
 
-    let (header, bytes) = bytes.as_slice().split_header_body();
-    let headers_string: String = String::from_utf8_lossy(&header).into();
+/// The body, request target and header values are slices of `bytes`, not copies.
+async fn get_request(bytes: Vec<u8>, options: &Options) -> Result<Request<Body>, ParseError> {
+    dev_print!("bytes len: {:?}", &bytes.len());
+    let started = Instant::now();
+    let allocations = thread_allocations();
 
-    dev_print!("headers_string: {:?}", &headers_string);
-    dev_print!("headers_string len: {:?}", &headers_string.len());
+    let bytes = Bytes::from(bytes);
+    let (header_len, body_start) = match find_headers_end(&bytes) {
+        Some(headers_end) => (headers_end - 4, headers_end),
+        None => (bytes.len(), bytes.len()),
+    };
+    let mut body = bytes.slice(body_start..);
+    let header = &bytes[..header_len];
 
-    let len: usize = bytes.len();
+    dev_print!("headers_string: {:?}", String::from_utf8_lossy(header));
+    dev_print!("headers_string len: {:?}", header.len());
 
     let mut method_option = None;
     let mut uri_option = None;
     let mut version_option = None;
-    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut headers = HeaderMap::new();
+    let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+    let mut host_offsets: Vec<usize> = Vec::new();
 
-    if !headers_string.is_empty() {
-        let line_split = headers_string.split("\r\n");
-
-        line_split.enumerate().for_each(|(index, line)| {
-            dev_print!("{}", line);
-            if line == "" {
-                return;
+    let mut offset = 0;
+    for (index, line) in header.split_lines().enumerate() {
+        let line_offset = offset;
+        offset += line.len() + 2;
+        if line.is_empty() {
+            continue;
+        }
+        if index == 0 {
+            let mut line_split_sub = line.split(|&byte| byte == b' ');
+            match line_split_sub.next() {
+                Some(method) => match http::Method::from_bytes(method) {
+                    Ok(method) => method_option = Some(method),
+                    Err(_) => error.push(
+                        line_offset,
+                        None,
+                        format!("invalid method `{}`", String::from_utf8_lossy(method)),
+                    ),
+                },
+                None => {
+                    dev_print!("method is None");
+                }
             }
-            if index == 0 {
-                let mut line_split_sub = line.split(" ");
-                match line_split_sub.next() {
-                    Some(method) => {
-                        if let Ok(method) = method.parse::<http::Method>() {
-                            method_option = Some(method);
-                        }
-                    }
-                    None => {
-                        dev_print!("method is None");
-                    }
+            match line_split_sub.next() {
+                Some(uri) if options.max_uri_length > 0 && uri.len() > options.max_uri_length => {
+                    error.push_status(
+                        StatusCode::URI_TOO_LONG,
+                        line_offset,
+                        None,
+                        format!(
+                            "request target is {} bytes, limit is {}",
+                            uri.len(),
+                            options.max_uri_length
+                        ),
+                    );
                 }
-                match line_split_sub.next() {
-                    Some(uri) => {
-                        if let Ok(uri) = uri.parse::<http::Uri>() {
-                            uri_option = Some(uri);
-                        }
-                    }
-                    None => {
-                        dev_print!("uri is None");
-                    }
+                Some(uri) => match http::Uri::from_maybe_shared(bytes.slice_ref(uri)) {
+                    Ok(uri) => uri_option = Some(uri),
+                    Err(e) => error.push(
+                        line_offset,
+                        None,
+                        format!(
+                            "invalid request target `{}`: {}",
+                            String::from_utf8_lossy(uri),
+                            e
+                        ),
+                    ),
+                },
+                None => {
+                    dev_print!("uri is None");
                 }
-                match line_split_sub.next() {
-                    Some(version) => {
-                        let version = match version {
-                            "HTTP/0.9" => http::Version::HTTP_09,
-                            "HTTP/1.0" => http::Version::HTTP_10,
-                            "HTTP/1.1" => http::Version::HTTP_11,
-                            "HTTP/2.0" => http::Version::HTTP_2,
-                            "HTTP/3.0" => http::Version::HTTP_3,
-                            _ => http::Version::HTTP_11,
-                        };
-                        version_option = Some(version);
-                    }
-                    None => {
-                        version_option = Some(http::Version::HTTP_11);
-                        dev_print!("version is None");
-                    }
+            }
+            version_option = Some(match line_split_sub.next() {
+                Some(b"HTTP/0.9") => http::Version::HTTP_09,
+                Some(b"HTTP/1.0") => http::Version::HTTP_10,
+                Some(b"HTTP/2.0") => http::Version::HTTP_2,
+                Some(b"HTTP/3.0") => http::Version::HTTP_3,
+                _ => http::Version::HTTP_11,
+            });
+        } else {
+            let (key, value) = match split_header_line(line) {
+                Ok(field) => field,
+                Err(message) => {
+                    error.push(line_offset, None, message);
+                    continue;
                 }
-            } else {
-                let mut size_split = line.trim().split(": ");
-                let key = size_split.next();
-                let value = size_split.next();
-
-                match key.is_some() && value.is_some() {
-                    true => {
-                        headers.push((key.unwrap().to_lowercase().into(), value.unwrap().into()));
-                    }
-                    false => {
-                        dev_print!("key or value is None");
+            };
+            let colon = key.len();
+
+            let name = match HeaderName::from_bytes(key) {
+                Ok(name) => name,
+                Err(_) => {
+                    error.push(
+                        line_offset,
+                        Some(&String::from_utf8_lossy(key)),
+                        "invalid header name",
+                    );
+                    continue;
+                }
+            };
+            match get_header_value(bytes.slice_ref(value), options.header_value_mode) {
+                Ok(value) => {
+                    if name == HOST {
+                        host_offsets.push(line_offset);
                     }
+                    headers.append(name, value);
+                }
+                Err(message) => {
+                    let value_offset = line_offset + colon + 1;
+                    let byte_offset = line[colon + 1..]
+                        .iter()
+                        .position(|&byte| is_invalid_value_byte(byte, options.header_value_mode))
+                        .unwrap_or(0);
+                    error.push(value_offset + byte_offset, Some(name.as_str()), message)
                 }
             }
-        });
+        }
+    }
+
+    if let Some(query) = uri_option.as_ref().and_then(|uri| uri.query()) {
+        let count = query.split('&').filter(|pair| !pair.is_empty()).count();
+        if options.max_query_params > 0 && count > options.max_query_params {
+            error.push_status(
+                StatusCode::URI_TOO_LONG,
+                0,
+                None,
+                format!(
+                    "{} query parameters, limit is {}",
+                    count, options.max_query_params
+                ),
+            );
+        }
+    }
+
+    if let (Some(uri), Some(method)) = (&uri_option, &method_option) {
+        if uri == "*" && method != Method::OPTIONS {
+            error.push(0, None, "asterisk-form target is only allowed for OPTIONS");
+        }
+    }
+
+    if options.require_host && version_option == Some(http::Version::HTTP_11) {
+        match host_offsets.as_slice() {
+            [] => error.push(0, Some("host"), "missing Host header"),
+            [_] => {}
+            [_, duplicated, ..] => error.push(*duplicated, Some("host"), "duplicate Host header"),
+        }
+    }
+
+    if options.strict_framing {
+        check_framing(header, &mut error);
     }
-    let version = match version_option {
-        Some(version) => version,
-        None => http::Version::HTTP_11,
+    let framing = match request_framing(&headers) {
+        Ok(framing) => framing,
+        Err(framing_error) => {
+            error.issues.extend(framing_error.issues);
+            error.headers.extend(framing_error.headers);
+            Framing::None
+        }
     };
 
-    let mut request = Request::builder();
-    if headers.len() > 0 {
-        for (key, value) in headers {
-            request = request.header(key, value);
+    if error.is_empty() && !options.allowed_hosts.is_empty() {
+        let host = headers
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| uri_option.as_ref().and_then(|uri| uri.host()));
+        if let Some(host) = host.filter(|host| !options.is_host_allowed(host)) {
+            error.push_status(
+                StatusCode::MISDIRECTED_REQUEST,
+                host_offsets.first().copied().unwrap_or(0),
+                Some("host"),
+                format!("host `{}` is not served here", host),
+            );
+            error
+                .headers
+                .insert(CONNECTION, HeaderValue::from_static("close"));
         }
     }
 
-    let request = request
-        .method(match method_option {
-            Some(method) => method,
-            None => http::Method::GET,
-        })
-        .uri(match uri_option {
-            Some(uri) => uri,
-            None => "/".parse()?,
-        })
-        .version(version)
-        .body(Body {
-            body: String::new(),
-            bytes,
-            len,
-            ip: None,
-        })?;
+    ParserMetrics::record(
+        headers.len(),
+        header.len(),
+        started.elapsed(),
+        !error.is_empty(),
+        thread_allocations() - allocations,
+    );
+    if !error.is_empty() {
+        return Err(error);
+    }
+
+    // Without Content-Length a request has no body, whatever follows the head is not ours.
+    // Chunked bodies are already decoded by the reader.
+    let len = match framing {
+        Framing::Length(length) => length.min(body.len()),
+        Framing::Chunked => body.len(),
+        Framing::None => 0,
+    };
+    body.truncate(len);
+
+    let mut request = Request::new(Body {
+        bytes: body,
+        len,
+        ip: None,
+        storage: None,
+        trailers: HeaderMap::new(),
+        pending: None,
+    });
+    *request.method_mut() = method_option.unwrap_or(http::Method::GET);
+    *request.uri_mut() = uri_option.unwrap_or_else(|| http::Uri::from_static("/"));
+    *request.version_mut() = version_option.unwrap_or(http::Version::HTTP_11);
+    *request.headers_mut() = headers;
 
     Ok(request)
 }
 
+/// Smuggling checks for `strict_framing`, bare CR/LF in the head. Content-Length is checked by
+/// `content_length` in every mode.
+fn check_framing(header: &[u8], error: &mut ParseError) {
+    for (index, &byte) in header.iter().enumerate() {
+        match byte {
+            b'\n' if index == 0 || header[index - 1] != b'\r' => {
+                error.push(index, None, "bare LF in request head")
+            }
+            b'\r' if header.get(index + 1) != Some(&b'\n') => {
+                error.push(index, None, "bare CR in request head")
+            }
+            _ => {}
+        }
+    }
+    if !error.is_empty() {
+        error
+            .headers
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+}
+
+fn is_invalid_value_byte(byte: u8, mode: HeaderValueMode) -> bool {
+    match byte {
+        b'\t' | 0x20..=0x7e => false,
+        0x80..=0xff => mode == HeaderValueMode::Strict,
+        _ => true,
+    }
+}
+
+fn get_header_value(value: Bytes, mode: HeaderValueMode) -> Result<HeaderValue, &'static str> {
+    if let Some(&byte) = value
+        .iter()
+        .find(|&&byte| is_invalid_value_byte(byte, mode))
+    {
+        return Err(match byte {
+            0x80..=0xff => "non-ASCII byte in header value",
+            _ => "control character in header value",
+        });
+    }
+    let value = match mode {
+        HeaderValueMode::Strict | HeaderValueMode::Opaque => HeaderValue::from_maybe_shared(value),
+        HeaderValueMode::Lossy => {
+            HeaderValue::from_bytes(String::from_utf8_lossy(&value).as_bytes())
+        }
+        HeaderValueMode::Latin1 => HeaderValue::from_bytes(
+            value
+                .iter()
+                .map(|&byte| byte as char)
+                .collect::<String>()
+                .as_bytes(),
+        ),
+    };
+    value.map_err(|_| "invalid header value")
+}
+
+/// Content-Length of all its field values, each a `1*DIGIT` list. A list of one repeated value
+/// collapses to it, differing values, signs, spaces inside a number or empty elements are an
+/// error. `Ok(None)` without the field, it is never read as "no body" when malformed.
+fn content_length<V>(values: V) -> Result<Option<usize>, &'static str>
+where
+    V: IntoIterator,
+    V::Item: AsRef<[u8]>,
+{
+    let mut length = None;
+    for value in values {
+        for element in value.as_ref().split(|&byte| byte == b',') {
+            let element = element.trim_whitespace();
+            if element.is_empty() || !element.iter().all(u8::is_ascii_digit) {
+                return Err("invalid Content-Length");
+            }
+            let parsed = std::str::from_utf8(element)
+                .ok()
+                .and_then(|element| element.parse::<usize>().ok())
+                .ok_or("Content-Length is too large")?;
+            match length {
+                Some(length) if length != parsed => {
+                    return Err("conflicting Content-Length values")
+                }
+                _ => length = Some(parsed),
+            }
+        }
+    }
+    Ok(length)
+}
+
+/// How the body after a head is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// No Content-Length and no Transfer-Encoding, the request has no body.
+    None,
+    Length(usize),
+    Chunked,
+}
+
+/// Framing of a request from its header fields, a 400 closing the connection when it is
+/// ambiguous. The reader and `get_request` both decide with this.
+fn request_framing(fields: &HeaderMap) -> Result<Framing, Box<ParseError>> {
+    let transfer_encoding = fields.get_all(TRANSFER_ENCODING).iter().next_back();
+    let framing = match content_length(fields.get_all(CONTENT_LENGTH)) {
+        Err(message) => Err((CONTENT_LENGTH, message)),
+        Ok(Some(_)) if transfer_encoding.is_some() => Err((
+            TRANSFER_ENCODING,
+            "both Transfer-Encoding and Content-Length",
+        )),
+        Ok(Some(length)) => Ok(Framing::Length(length)),
+        Ok(None) => match transfer_encoding {
+            Some(value) if is_chunked(value.as_bytes()) => Ok(Framing::Chunked),
+            Some(_) => Err((TRANSFER_ENCODING, "final transfer coding is not chunked")),
+            None => Ok(Framing::None),
+        },
+    };
+    framing.map_err(|(name, message)| {
+        let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+        error.push(0, Some(name.as_str()), message);
+        error
+            .headers
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+        Box::new(error)
+    })
+}
+
+/// Name and trimmed value of a header line, split at the first `:`. Whitespace between the name
+/// and the `:` is refused as RFC 9112 5.1 requires, whitespace before the name fails
+/// `HeaderName` validation.
+fn split_header_line(line: &[u8]) -> Result<(&[u8], &[u8]), &'static str> {
+    let Some(colon) = line.iter().position(|&byte| byte == b':') else {
+        return Err("header line without `:` separator");
+    };
+    let name = &line[..colon];
+    if name.last().is_some_and(|byte| byte.is_ascii_whitespace()) {
+        return Err("whitespace between header name and `:`");
+    }
+    Ok((name, line[colon + 1..].trim_whitespace()))
+}
+
+/// Header fields of a complete head, split as `get_request` splits them. A line it would refuse
+/// is a 400 before any body byte is read.
+fn head_fields(head: &[u8]) -> Result<HeaderMap, Box<ParseError>> {
+    let mut fields = HeaderMap::new();
+    let mut offset = 0;
+    for (index, line) in head.split_lines().enumerate() {
+        let line_offset = offset;
+        offset += line.len() + 2;
+        if index == 0 || line.is_empty() {
+            continue;
+        }
+        let field = split_header_line(line).and_then(|(name, value)| {
+            let name = HeaderName::from_bytes(name).map_err(|_| "invalid header name")?;
+            let value = HeaderValue::from_bytes(value).map_err(|_| "invalid header value")?;
+            Ok((name, value))
+        });
+        match field {
+            Ok((name, value)) => {
+                fields.append(name, value);
+            }
+            Err(message) => {
+                let mut error = ParseError::new(StatusCode::BAD_REQUEST);
+                error.push(line_offset, None, message);
+                error
+                    .headers
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+                return Err(Box::new(error));
+            }
+        }
+    }
+    Ok(fields)
+}
+
 fn find_headers_end(data: &[u8]) -> Option<usize> {
     data.windows(4)
         .position(|window| window == b"\r\n\r\n")
         .map(|pos| pos + 4)
 }
 
-fn parse_content_length(headers: &[u8]) -> Option<usize> {
-    let headers_str = String::from_utf8_lossy(headers);
-    headers_str
-        .lines()
-        .find(|line| line.to_lowercase().starts_with("content-length:"))
-        .and_then(|line| {
-            line.split(':')
-                .nth(1)
-                .and_then(|len| len.trim().parse().ok())
+#[cfg(all(test, not(feature = "tokio_rustls")))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{LaneMatcher, PriorityLanes, Server};
+
+    /// Handler of `serve`, it sees each request and fills its response.
+    type Respond = fn(&Request<Body>, &mut Response<Writer>);
+
+    /// Serve one kept-alive connection on a local port, recording the requests the handler saw
+    /// as `METHOD target body`.
+    async fn serve(
+        options: Options,
+        respond: Respond,
+    ) -> (
+        TcpStream,
+        tokio::task::JoinHandle<()>,
+        Arc<Mutex<Vec<String>>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handled = seen.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut next = Server::parse_request(stream, options).await.ok();
+            while let Some((request, mut response)) = next {
+                let body = String::from_utf8_lossy(&request.body().bytes).into_owned();
+                let line = format!("{} {} {}", request.method(), request.uri(), body);
+                handled.lock().unwrap().push(line.trim_end().to_string());
+                respond(&request, &mut response);
+                if response.responser().await.is_err() {
+                    break;
+                }
+                let Some((stream, options)) = response.into_keep_alive() else {
+                    break;
+                };
+                next = Server::parse_next_request(stream, options)
+                    .await
+                    .ok()
+                    .flatten();
+            }
+        });
+        let client = TcpStream::connect(address).await.unwrap();
+        (client, server, seen)
+    }
+
+    /// `input` written at once to a connection answering "ok", the raw output until the server
+    /// closes and the requests it saw.
+    async fn exchange(options: Options, input: &[u8]) -> (String, Vec<String>) {
+        let (mut client, server, seen) = serve(options, |_, response| {
+            *response.status_mut() = StatusCode::OK;
+            response.body_mut().body = "ok".into();
         })
+        .await;
+        client.write_all(input).await.unwrap();
+        let mut output = Vec::new();
+        let read = client.read_to_end(&mut output);
+        let _ = tokio::time::timeout(Duration::from_secs(5), read).await;
+        server.await.unwrap();
+        let seen = seen.lock().unwrap().clone();
+        (String::from_utf8_lossy(&output).into_owned(), seen)
+    }
+
+    /// One response read off `client`, framed by its Content-Length.
+    async fn read_response(client: &mut TcpStream) -> String {
+        let mut output = Vec::new();
+        let mut byte = [0; 1];
+        while !output.ends_with(b"\r\n\r\n") {
+            assert_eq!(
+                client.read(&mut byte).await.unwrap(),
+                1,
+                "closed in the head"
+            );
+            output.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&output).to_lowercase();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .expect("response without content-length")
+            .parse::<usize>()
+            .unwrap();
+        let mut body = vec![0; length];
+        client.read_exact(&mut body).await.unwrap();
+        output.extend(body);
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    fn keep_alive_options() -> Options {
+        let mut options = Options::new();
+        options.keep_alive = true;
+        options.keep_alive_timeout_miliseconds = 200;
+        options.read_timeout_miliseconds = 200;
+        options
+    }
+
+    /// `POST /a` carrying a whole `GET /smuggled` request as a 36 byte body.
+    fn smuggling(content_length: &str) -> Vec<u8> {
+        let smuggled = "GET /smuggled HTTP/1.1\r\nHost: ab\r\n\r\n";
+        assert_eq!(smuggled.len(), 36);
+        format!(
+            "POST /a HTTP/1.1\r\nHost: a\r\n{}\r\n\r\n{}",
+            content_length, smuggled
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn parses_content_length_strictly() {
+        assert_eq!(content_length([b"36"]), Ok(Some(36)));
+        assert_eq!(content_length([b" 36 "]), Ok(Some(36)));
+        assert_eq!(content_length([&b"36, 36"[..]]), Ok(Some(36)));
+        assert_eq!(content_length([b"36", b"36"]), Ok(Some(36)));
+        assert_eq!(content_length(Vec::<&[u8]>::new()), Ok(None));
+        for invalid in ["+36", "-36", "0x24", "3 6", "", "36,", "36;", "३६"] {
+            assert!(content_length([invalid]).is_err(), "{:?}", invalid);
+        }
+        assert!(content_length([&b"36, 37"[..]]).is_err());
+        assert!(content_length([b"36", b"37"]).is_err());
+        assert!(content_length([b"99999999999999999999999"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn collapses_repeated_content_length() {
+        for header in [
+            "Content-Length: 36, 36",
+            "Content-Length: 36\r\nContent-Length: 36",
+        ] {
+            let mut options = keep_alive_options();
+            options.strict_framing = true;
+            let (output, seen) = exchange(options, &smuggling(header)).await;
+            assert!(output.starts_with("HTTP/1.1 200 OK"), "{}", output);
+            assert_eq!(seen.len(), 1, "{:?}", seen);
+            assert!(seen[0].starts_with("POST /a GET /smuggled"), "{:?}", seen);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_malformed_content_length() {
+        for header in [
+            "Content-Length: +36",
+            "Content-Length: 36, 37",
+            "Content-Length: 36\r\nContent-Length: 37",
+            "Content-Length: 0x24",
+            "Content-Length: ",
+        ] {
+            for strict_framing in [false, true] {
+                let mut options = keep_alive_options();
+                options.strict_framing = strict_framing;
+                let (output, seen) = exchange(options, &smuggling(header)).await;
+                assert!(output.starts_with("HTTP/1.1 400"), "{}: {}", header, output);
+                assert!(output.contains("connection: close"), "{}", output);
+                assert!(seen.is_empty(), "{}: {:?}", header, seen);
+            }
+        }
+    }
+
+    #[test]
+    fn splits_header_lines_at_the_first_colon() {
+        let (name, value) = split_header_line(b"Host: a:80 ").unwrap();
+        assert_eq!((name, value), (&b"Host"[..], &b"a:80"[..]));
+        assert!(split_header_line(b"Content-Length : 36").is_err());
+        assert!(split_header_line(b"Content-Length\t: 36").is_err());
+        assert!(split_header_line(b"Content-Length 36").is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_whitespace_around_field_names() {
+        for header in [
+            "Content-Length : 36",
+            "Content-Length\t: 36",
+            " Content-Length: 36",
+        ] {
+            let (output, seen) = exchange(keep_alive_options(), &smuggling(header)).await;
+            assert!(
+                output.starts_with("HTTP/1.1 400"),
+                "{:?}: {}",
+                header,
+                output
+            );
+            assert!(output.contains("connection: close"), "{}", output);
+            assert!(seen.is_empty(), "{:?}: {:?}", header, seen);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_requests_pipelined_in_one_write() {
+        let input = b"GET /a HTTP/1.1\r\nHost: a\r\n\r\n\
+            POST /b HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhi\
+            GET /c HTTP/1.1\r\nHost: a\r\n\r\n";
+        let (output, seen) = exchange(keep_alive_options(), input).await;
+        assert_eq!(seen, ["GET /a", "POST /b hi", "GET /c"]);
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 3, "{}", output);
+    }
+
+    #[tokio::test]
+    async fn closes_instead_of_pipelining_after_unknown_framing() {
+        for header in [
+            "Content-Length: 2x",
+            "Transfer-Encoding: gzip",
+            "Content-Length: 0\r\nTransfer-Encoding: chunked",
+        ] {
+            let input = format!(
+                "GET /a HTTP/1.1\r\nHost: a\r\n{}\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\n\r\n",
+                header
+            );
+            let (output, seen) = exchange(keep_alive_options(), input.as_bytes()).await;
+            assert!(output.starts_with("HTTP/1.1 400"), "{}: {}", header, output);
+            assert_eq!(output.matches("HTTP/1.1 ").count(), 1, "{}", output);
+            assert!(seen.is_empty(), "{}: {:?}", header, seen);
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_alive_after_a_bytes_body() {
+        let (mut client, server, seen) = serve(keep_alive_options(), |request, response| {
+            *response.status_mut() = StatusCode::OK;
+            response.body_mut().bytes = request.uri().path().as_bytes().to_vec();
+        })
+        .await;
+        for path in ["/first", "/second"] {
+            let request = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", path);
+            client.write_all(request.as_bytes()).await.unwrap();
+            let output = read_response(&mut client).await;
+            assert!(output.starts_with("HTTP/1.1 200 OK"), "{}", output);
+            assert!(!output.contains("connection: close"), "{}", output);
+            assert!(output.ends_with(&format!("\r\n\r\n{}", path)), "{}", output);
+        }
+        drop(client);
+        server.await.unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["GET /first", "GET /second"]);
+    }
+
+    #[tokio::test]
+    async fn refuses_maintenance_before_the_body() {
+        let mut options = keep_alive_options();
+        options.maintenance = true;
+        options.maintenance_allowlist = vec!["/health".into()];
+        let input = b"POST /a HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\
+            Expect: 100-continue\r\n\r\n";
+        let (output, seen) = exchange(options.clone(), input).await;
+        assert!(output.starts_with("HTTP/1.1 503"), "{}", output);
+        assert!(!output.contains("100 Continue"), "{}", output);
+        assert!(output.contains("connection: close"), "{}", output);
+        assert!(seen.is_empty(), "{:?}", seen);
+
+        let input = b"GET /health HTTP/1.1\r\nHost: a\r\n\r\n";
+        let (output, seen) = exchange(options, input).await;
+        assert!(output.starts_with("HTTP/1.1 200 OK"), "{}", output);
+        assert_eq!(seen, ["GET /health"]);
+    }
+
+    #[tokio::test]
+    async fn takes_the_lane_permit_before_the_body() {
+        let mut options = keep_alive_options();
+        let lanes = PriorityLanes::new(1).add_lane(
+            "uploads",
+            0,
+            vec![LaneMatcher::PathPrefix("/upload".into())],
+        );
+        options.priority_lanes = Some(lanes);
+        let (mut client, server, seen) = serve(options, |_, _| {}).await;
+        let head = b"POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\
+            Expect: 100-continue\r\n\r\n";
+        client.write_all(head).await.unwrap();
+        let mut output = [0; 64];
+        let read = client.read(&mut output);
+        let waited = tokio::time::timeout(Duration::from_millis(300), read).await;
+        assert!(waited.is_err(), "answered without a lane permit");
+        server.abort();
+        assert!(seen.lock().unwrap().is_empty());
+    }
 }
@@ -26,13 +26,3 @@ impl GetHeaderChild for &str {
         result
     }
 }
-
-pub trait StringUtil {
-    fn copy_string(&self) -> String;
-}
-
-impl StringUtil for String {
-    fn copy_string(&self) -> String {
-        self.as_str().into()
-    }
-}
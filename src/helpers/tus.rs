@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use http::header::{HeaderName, HeaderValue, CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use http::{Method, Request, Response, StatusCode};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::helpers::random::secure_random_hex;
+use crate::{Body, BodyStorage, ResponseUtil, Writer};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+
+#[derive(Debug, Clone)]
+pub struct UploadInfo {
+    pub id: String,
+    pub offset: u64,
+    pub length: u64,
+    /// Raw `Upload-Metadata` value as sent by the client.
+    pub metadata: Option<String>,
+}
+
+/// Storage backend for resumable uploads.
+#[async_trait]
+pub trait UploadStore: Send + Sync {
+    async fn create(
+        &self,
+        length: u64,
+        metadata: Option<String>,
+    ) -> Result<UploadInfo, Box<dyn Error + Send + Sync>>;
+    async fn info(&self, id: &str) -> Result<Option<UploadInfo>, Box<dyn Error + Send + Sync>>;
+    /// Append at `offset` and return the new offset.
+    async fn append(
+        &self,
+        id: &str,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<u64, Box<dyn Error + Send + Sync>>;
+}
+
+#[derive(Default)]
+pub struct MemoryUploadStore {
+    uploads: Mutex<HashMap<String, (UploadInfo, Vec<u8>)>>,
+}
+
+impl MemoryUploadStore {
+    pub fn new() -> MemoryUploadStore {
+        MemoryUploadStore::default()
+    }
+
+    /// Take the bytes of a finished upload out of the store.
+    pub fn take(&self, id: &str) -> Option<Vec<u8>> {
+        let mut uploads = self.uploads.lock().unwrap();
+        match uploads.get(id) {
+            Some((info, _)) if info.offset == info.length => {
+                uploads.remove(id).map(|(_, bytes)| bytes)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl UploadStore for MemoryUploadStore {
+    async fn create(
+        &self,
+        length: u64,
+        metadata: Option<String>,
+    ) -> Result<UploadInfo, Box<dyn Error + Send + Sync>> {
+        let info = UploadInfo {
+            id: secure_random_hex(32)?,
+            offset: 0,
+            length,
+            metadata,
+        };
+        self.uploads
+            .lock()
+            .unwrap()
+            .insert(info.id.clone(), (info.clone(), Vec::new()));
+        Ok(info)
+    }
+    async fn info(&self, id: &str) -> Result<Option<UploadInfo>, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .uploads
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|(info, _)| info.clone()))
+    }
+    async fn append(
+        &self,
+        id: &str,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let Some((info, data)) = uploads.get_mut(id) else {
+            return Err(format!("Upload not found: {}", id).into());
+        };
+        if info.offset != offset {
+            return Err(format!("Offset mismatch: {} != {}", offset, info.offset).into());
+        }
+        data.extend_from_slice(bytes);
+        info.offset += bytes.len() as u64;
+        Ok(info.offset)
+    }
+}
+
+/// Uploads kept as `<id>` data files with a `<id>.info` sidecar.
+pub struct FileUploadStore {
+    dir: PathBuf,
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl FileUploadStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> FileUploadStore {
+        FileUploadStore {
+            dir: dir.into(),
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn is_valid_id(id: &str) -> bool {
+        !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+    }
+}
+
+#[async_trait]
+impl UploadStore for FileUploadStore {
+    async fn create(
+        &self,
+        length: u64,
+        metadata: Option<String>,
+    ) -> Result<UploadInfo, Box<dyn Error + Send + Sync>> {
+        fs::create_dir_all(&self.dir).await?;
+        let id = secure_random_hex(32)?;
+        fs::File::create(self.path(&id)).await?;
+        fs::write(
+            self.dir.join(format!("{}.info", id)),
+            format!("{}\n{}", length, metadata.clone().unwrap_or_default()),
+        )
+        .await?;
+        Ok(UploadInfo {
+            id,
+            offset: 0,
+            length,
+            metadata,
+        })
+    }
+    async fn info(&self, id: &str) -> Result<Option<UploadInfo>, Box<dyn Error + Send + Sync>> {
+        if !FileUploadStore::is_valid_id(id) {
+            return Ok(None);
+        }
+        let Ok(info) = fs::read_to_string(self.dir.join(format!("{}.info", id))).await else {
+            return Ok(None);
+        };
+        let (length, metadata) = info.split_once('\n').unwrap_or((&info, ""));
+        let offset = fs::metadata(self.path(id)).await?.len();
+        Ok(Some(UploadInfo {
+            id: id.into(),
+            offset,
+            length: length.parse()?,
+            metadata: match metadata.is_empty() {
+                true => None,
+                false => Some(metadata.into()),
+            },
+        }))
+    }
+    async fn append(
+        &self,
+        id: &str,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let _guard = self.lock.lock().await;
+        let Some(info) = self.info(id).await? else {
+            return Err(format!("Upload not found: {}", id).into());
+        };
+        if info.offset != offset {
+            return Err(format!("Offset mismatch: {} != {}", offset, info.offset).into());
+        }
+        let mut file = OpenOptions::new().append(true).open(self.path(id)).await?;
+        file.write_all(bytes).await?;
+        file.flush().await?;
+        Ok(offset + bytes.len() as u64)
+    }
+}
+
+/// tus 1.0.0 endpoint (core protocol and `creation`) mounted at `base_path`.
+pub struct TusHandler {
+    base_path: String,
+    store: Arc<dyn UploadStore>,
+    max_size: Option<u64>,
+}
+
+impl TusHandler {
+    pub fn new(base_path: &str, store: Arc<dyn UploadStore>) -> TusHandler {
+        TusHandler {
+            base_path: base_path.trim_end_matches('/').into(),
+            store,
+            max_size: None,
+        }
+    }
+
+    pub fn max_size(mut self, max_size: u64) -> TusHandler {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub fn matches(&self, request: &Request<Body>) -> bool {
+        let path = request.uri().path();
+        match path.strip_prefix(self.base_path.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    /// Answer a request for `base_path` or `base_path/<id>` and send the response.
+    pub async fn handle(
+        &self,
+        request: &Request<Body>,
+        response: &mut Response<Writer>,
+    ) -> Result<(), Box<dyn Error>> {
+        response.body_mut().body.clear();
+        response.body_mut().bytes.clear();
+        let headers = response.headers_mut();
+        headers.remove(CONTENT_TYPE);
+        headers.insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+
+        let id = request
+            .uri()
+            .path()
+            .strip_prefix(self.base_path.as_str())
+            .unwrap_or("")
+            .trim_matches('/')
+            .to_string();
+        let status = if request.method() == Method::OPTIONS {
+            self.options(response)
+        } else if get_header(request, &TUS_RESUMABLE) != Some(TUS_VERSION) {
+            response
+                .headers_mut()
+                .insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+            StatusCode::PRECONDITION_FAILED
+        } else {
+            match (request.method(), id.is_empty()) {
+                (&Method::POST, true) => self.create(request, response).await?,
+                (&Method::HEAD, false) => self.head(&id, response).await?,
+                (&Method::PATCH, false) => self.patch(&id, request, response).await?,
+                _ => StatusCode::METHOD_NOT_ALLOWED,
+            }
+        };
+        dev_print!("tus {} {}: {}", request.method(), id, status);
+        *response.status_mut() = status;
+        response.responser().await
+    }
+
+    fn options(&self, response: &mut Response<Writer>) -> StatusCode {
+        let headers = response.headers_mut();
+        headers.insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+        headers.insert(TUS_EXTENSION, HeaderValue::from_static("creation"));
+        if let Some(max_size) = self.max_size {
+            headers.insert(TUS_MAX_SIZE, max_size.into());
+        }
+        StatusCode::NO_CONTENT
+    }
+
+    async fn create(
+        &self,
+        request: &Request<Body>,
+        response: &mut Response<Writer>,
+    ) -> Result<StatusCode, Box<dyn Error>> {
+        let Some(length) = get_header(request, &UPLOAD_LENGTH).and_then(|v| v.parse::<u64>().ok())
+        else {
+            return Ok(StatusCode::BAD_REQUEST);
+        };
+        if self.max_size.is_some_and(|max_size| length > max_size) {
+            return Ok(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        let metadata = get_header(request, &UPLOAD_METADATA).map(String::from);
+        let info = self
+            .store
+            .create(length, metadata)
+            .await
+            .map_err(store_error)?;
+        response
+            .headers_mut()
+            .insert(LOCATION, format!("{}/{}", self.base_path, info.id).parse()?);
+        Ok(StatusCode::CREATED)
+    }
+
+    async fn head(
+        &self,
+        id: &str,
+        response: &mut Response<Writer>,
+    ) -> Result<StatusCode, Box<dyn Error>> {
+        let headers = response.headers_mut();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        let Some(info) = self.store.info(id).await.map_err(store_error)? else {
+            return Ok(StatusCode::NOT_FOUND);
+        };
+        headers.insert(UPLOAD_OFFSET, info.offset.into());
+        headers.insert(UPLOAD_LENGTH, info.length.into());
+        if let Some(metadata) = info.metadata {
+            headers.insert(UPLOAD_METADATA, metadata.parse()?);
+        }
+        Ok(StatusCode::OK)
+    }
+
+    async fn patch(
+        &self,
+        id: &str,
+        request: &Request<Body>,
+        response: &mut Response<Writer>,
+    ) -> Result<StatusCode, Box<dyn Error>> {
+        if get_header(request, &CONTENT_TYPE) != Some("application/offset+octet-stream") {
+            return Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        let Some(offset) = get_header(request, &UPLOAD_OFFSET).and_then(|v| v.parse::<u64>().ok())
+        else {
+            return Ok(StatusCode::BAD_REQUEST);
+        };
+        let Some(info) = self.store.info(id).await.map_err(store_error)? else {
+            return Ok(StatusCode::NOT_FOUND);
+        };
+        if info.offset != offset {
+            return Ok(StatusCode::CONFLICT);
+        }
+        let bytes = match &request.body().storage {
+            Some(BodyStorage::File(file)) => fs::read(file.path()).await?,
+            Some(BodyStorage::External(_)) => return Ok(StatusCode::INTERNAL_SERVER_ERROR),
+            _ => request.body().bytes.to_vec(),
+        };
+        if offset + bytes.len() as u64 > info.length {
+            return Ok(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        let offset = match self.store.append(id, offset, &bytes).await {
+            Ok(offset) => offset,
+            Err(e) => {
+                dev_print!("tus append failed: {}", e);
+                return Ok(StatusCode::CONFLICT);
+            }
+        };
+        response.headers_mut().insert(UPLOAD_OFFSET, offset.into());
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+fn store_error(e: Box<dyn Error + Send + Sync>) -> Box<dyn Error> {
+    e.to_string().into()
+}
+
+fn get_header<'a>(request: &'a Request<Body>, name: &HeaderName) -> Option<&'a str> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim())
+}
+
+#[cfg(all(test, not(feature = "tokio_rustls")))]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{Options, Server};
+
+    /// Raw response of `handler` to one request on its own connection.
+    async fn send(handler: &Arc<TusHandler>, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let handler = handler.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let parsed = Server::parse_request(stream, Options::new()).await.ok();
+            let Some((request, mut response)) = parsed else {
+                return false;
+            };
+            handler.handle(&request, &mut response).await.is_ok()
+        });
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut output = Vec::new();
+        let read = client.read_to_end(&mut output);
+        let _ = tokio::time::timeout(Duration::from_secs(5), read).await;
+        assert!(server.await.unwrap(), "handler failed");
+        String::from_utf8(output).unwrap()
+    }
+
+    fn header<'a>(output: &'a str, name: &str) -> Option<&'a str> {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+    }
+
+    fn patch(location: &str, offset: u64, bytes: &str) -> String {
+        format!(
+            "PATCH {} HTTP/1.1\r\nHost: a\r\nTus-Resumable: 1.0.0\r\n\
+             Content-Type: application/offset+octet-stream\r\nUpload-Offset: {}\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            location,
+            offset,
+            bytes.len(),
+            bytes
+        )
+    }
+
+    #[tokio::test]
+    async fn creates_and_resumes_uploads() {
+        let store = Arc::new(MemoryUploadStore::new());
+        let handler = Arc::new(TusHandler::new("/files", store.clone()));
+        let create = "POST /files HTTP/1.1\r\nHost: a\r\nTus-Resumable: 1.0.0\r\n\
+                      Upload-Length: 10\r\nContent-Length: 0\r\n\r\n";
+        let output = send(&handler, create).await;
+        assert!(output.starts_with("HTTP/1.1 201"), "{}", output);
+        let location = header(&output, "location").unwrap().to_string();
+        let id = location.strip_prefix("/files/").unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        assert!(id.bytes().all(|byte| byte.is_ascii_hexdigit()), "{}", id);
+        let other = send(&handler, create).await;
+        assert_ne!(header(&other, "location"), Some(location.as_str()));
+
+        let head = format!(
+            "HEAD {} HTTP/1.1\r\nHost: a\r\nTus-Resumable: 1.0.0\r\n\r\n",
+            location
+        );
+        let output = send(&handler, &head).await;
+        assert!(output.starts_with("HTTP/1.1 200"), "{}", output);
+        assert_eq!(header(&output, "upload-offset"), Some("0"));
+        assert_eq!(header(&output, "upload-length"), Some("10"));
+
+        let output = send(&handler, &patch(&location, 0, "hello")).await;
+        assert!(output.starts_with("HTTP/1.1 204"), "{}", output);
+        assert_eq!(header(&output, "upload-offset"), Some("5"));
+        let output = send(&handler, &patch(&location, 0, "hello")).await;
+        assert!(output.starts_with("HTTP/1.1 409"), "{}", output);
+        let output = send(&handler, &patch(&location, 5, "world!")).await;
+        assert!(output.starts_with("HTTP/1.1 413"), "{}", output);
+        let output = send(&handler, &patch(&location, 5, "world")).await;
+        assert!(output.starts_with("HTTP/1.1 204"), "{}", output);
+        assert_eq!(header(&output, "upload-offset"), Some("10"));
+
+        let output = send(&handler, &head).await;
+        assert_eq!(header(&output, "upload-offset"), Some("10"));
+        assert_eq!(store.take(&id).as_deref(), Some(&b"helloworld"[..]));
+        let missing = send(&handler, &patch("/files/00", 0, "x")).await;
+        assert!(missing.starts_with("HTTP/1.1 404"), "{}", missing);
+    }
+
+    #[tokio::test]
+    async fn refuses_uploads_over_max_size() {
+        let store = Arc::new(MemoryUploadStore::new());
+        let handler = Arc::new(TusHandler::new("/files", store).max_size(8));
+        let options = "OPTIONS /files HTTP/1.1\r\nHost: a\r\n\r\n";
+        let output = send(&handler, options).await;
+        assert!(output.starts_with("HTTP/1.1 204"), "{}", output);
+        assert_eq!(header(&output, "tus-max-size"), Some("8"));
+        let create = "POST /files HTTP/1.1\r\nHost: a\r\nTus-Resumable: 1.0.0\r\n\
+                      Upload-Length: 9\r\nContent-Length: 0\r\n\r\n";
+        let output = send(&handler, create).await;
+        assert!(output.starts_with("HTTP/1.1 413"), "{}", output);
+        let unversioned = "POST /files HTTP/1.1\r\nHost: a\r\nUpload-Length: 1\r\n\r\n";
+        let output = send(&handler, unversioned).await;
+        assert!(output.starts_with("HTTP/1.1 412"), "{}", output);
+    }
+}
@@ -0,0 +1,577 @@
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+
+use http::header::{HeaderName, HeaderValue, ALLOW, CONTENT_TYPE};
+use http::{Request, Response, StatusCode, Uri};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::helpers::file_policy::check_file_access;
+use crate::helpers::path::decode_path;
+use crate::helpers::traits::http_response::{file_etag, get_content_type};
+use crate::{Body, BodyStorage, ResponseUtil, StaticMount, Writer};
+
+const DAV: HeaderName = HeaderName::from_static("dav");
+const DEPTH: HeaderName = HeaderName::from_static("depth");
+const DESTINATION: HeaderName = HeaderName::from_static("destination");
+const OVERWRITE: HeaderName = HeaderName::from_static("overwrite");
+const METHODS: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL, MOVE";
+
+/// WebDAV class 1 subset over the files of `mount`: PROPFIND, MKCOL, MOVE, PUT and DELETE,
+/// GET and HEAD through `response_file`. No locks and no custom properties.
+pub struct DavHandler {
+    mount: StaticMount,
+}
+
+impl DavHandler {
+    /// `mount.prefix` is the URL of the root collection, its policies apply to every method.
+    pub fn new(mount: StaticMount) -> DavHandler {
+        DavHandler { mount }
+    }
+
+    pub fn matches(&self, request: &Request<Body>) -> bool {
+        self.relative(request.uri().path()).is_some()
+    }
+
+    /// Answer a request below `mount.prefix` and send the response.
+    pub async fn handle(
+        &self,
+        request: &mut Request<Body>,
+        response: &mut Response<Writer>,
+    ) -> Result<(), Box<dyn Error>> {
+        response.body_mut().body.clear();
+        response.body_mut().bytes.clear();
+        response.headers_mut().remove(CONTENT_TYPE);
+
+        let rest = decode_path(request.uri().path())
+            .and_then(|path| self.relative(&path).map(String::from));
+        let status = match rest {
+            Some(rest) => match self.resolve(&rest).await {
+                Ok(path) => self.dispatch(&rest, path, request, response).await?,
+                Err(status) => Some(status),
+            },
+            None => Some(StatusCode::NOT_FOUND),
+        };
+        dev_print!(
+            "dav {} {}: {:?}",
+            request.method(),
+            request.uri().path(),
+            status
+        );
+        if let Some(status) = status {
+            *response.status_mut() = status;
+        }
+        response.responser().await
+    }
+
+    /// Status of the request, `None` when the response was already set up as a file.
+    async fn dispatch(
+        &self,
+        rest: &str,
+        path: PathBuf,
+        request: &mut Request<Body>,
+        response: &mut Response<Writer>,
+    ) -> Result<Option<StatusCode>, Box<dyn Error>> {
+        let status = match request.method().as_str() {
+            "OPTIONS" => {
+                let headers = response.headers_mut();
+                headers.insert(DAV, HeaderValue::from_static("1"));
+                headers.insert(ALLOW, HeaderValue::from_static(METHODS));
+                StatusCode::OK
+            }
+            "GET" | "HEAD" => match fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => {
+                    let mount = self.mount.clone();
+                    if !response.body_mut().response_mount_file(mount, rest) {
+                        return Ok(Some(StatusCode::FORBIDDEN));
+                    }
+                    *response.status_mut() = StatusCode::OK;
+                    return Ok(None);
+                }
+                Ok(_) => {
+                    response
+                        .headers_mut()
+                        .insert(ALLOW, HeaderValue::from_static(METHODS));
+                    StatusCode::METHOD_NOT_ALLOWED
+                }
+                Err(_) => StatusCode::NOT_FOUND,
+            },
+            "PUT" => self.put(&path, request, response).await?,
+            "DELETE" if path == self.mount.root => StatusCode::FORBIDDEN,
+            "DELETE" => self.delete(&path).await?,
+            "MKCOL" => mkcol(&path, request).await?,
+            "MOVE" => self.move_to(&path, request).await?,
+            "PROPFIND" => self.propfind(rest, &path, request, response).await?,
+            _ => {
+                response
+                    .headers_mut()
+                    .insert(ALLOW, HeaderValue::from_static(METHODS));
+                StatusCode::METHOD_NOT_ALLOWED
+            }
+        };
+        Ok(Some(status))
+    }
+
+    /// Rest of a decoded `path` below `mount.prefix`, starting with `/` or empty.
+    fn relative<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(self.mount.prefix.trim_end_matches('/'))?;
+        (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+    }
+
+    /// File of `rest` under the mount root, refused under the mount policies.
+    async fn resolve(&self, rest: &str) -> Result<PathBuf, StatusCode> {
+        let relative = Path::new(rest.trim_start_matches('/'));
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let path = self.mount.root.join(relative);
+        match check_file_access(&path, &self.mount).await {
+            Some(status) => Err(status),
+            None => Ok(path),
+        }
+    }
+
+    /// 201 for a new file, 204 for a replaced one, 409 without the parent collection.
+    async fn put(
+        &self,
+        path: &Path,
+        request: &mut Request<Body>,
+        response: &mut Response<Writer>,
+    ) -> Result<StatusCode, Box<dyn Error>> {
+        if path == self.mount.root {
+            return Ok(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let existed = match fs::metadata(path).await {
+            Ok(metadata) if metadata.is_dir() => return Ok(StatusCode::METHOD_NOT_ALLOWED),
+            Ok(_) => true,
+            Err(_) => false,
+        };
+        if !is_collection(path.parent()).await {
+            return Ok(StatusCode::CONFLICT);
+        }
+        match &request.body().storage {
+            Some(BodyStorage::File(file)) => {
+                fs::copy(file.path(), path).await?;
+            }
+            Some(BodyStorage::External(_)) => return Ok(StatusCode::INTERNAL_SERVER_ERROR),
+            _ => {
+                let mut file = fs::File::create(path).await?;
+                let mut reader = request.body_mut().reader(response.body_mut());
+                tokio::io::copy(&mut reader, &mut file).await?;
+                file.flush().await?;
+            }
+        }
+        Ok(match existed {
+            true => StatusCode::NO_CONTENT,
+            false => StatusCode::CREATED,
+        })
+    }
+
+    /// 204 for a removed file or collection with everything in it, 403 when the policies
+    /// refuse anything in the collection.
+    async fn delete(&self, path: &Path) -> Result<StatusCode, Box<dyn Error>> {
+        match fs::symlink_metadata(path).await {
+            Ok(metadata) if metadata.is_dir() => {
+                if let Some(status) = self.check_tree(path).await? {
+                    return Ok(status);
+                }
+                fs::remove_dir_all(path).await?
+            }
+            Ok(_) => fs::remove_file(path).await?,
+            Err(_) => return Ok(StatusCode::NOT_FOUND),
+        }
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// 403 when `check_file_access` refuses any entry below collection `path`, hidden
+    /// entries are not removed with it.
+    async fn check_tree(&self, path: &Path) -> Result<Option<StatusCode>, Box<dyn Error>> {
+        let mut collections = vec![path.to_path_buf()];
+        while let Some(collection) = collections.pop() {
+            let mut entries = fs::read_dir(&collection).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if check_file_access(&path, &self.mount).await.is_some() {
+                    return Ok(Some(StatusCode::FORBIDDEN));
+                }
+                if entry.file_type().await?.is_dir() {
+                    collections.push(path);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// 201 or 204 as PUT, 412 for an existing target with `Overwrite: F`, 403 for a
+    /// collection target holding entries the policies refuse, 502 for a `Destination`
+    /// outside the mount.
+    async fn move_to(
+        &self,
+        path: &Path,
+        request: &Request<Body>,
+    ) -> Result<StatusCode, Box<dyn Error>> {
+        let Some(destination) = request
+            .headers()
+            .get(DESTINATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Uri>().ok())
+        else {
+            return Ok(StatusCode::BAD_REQUEST);
+        };
+        let Some(target) = decode_path(destination.path()) else {
+            return Ok(StatusCode::BAD_REQUEST);
+        };
+        let Some(target) = self.relative(&target) else {
+            return Ok(StatusCode::BAD_GATEWAY);
+        };
+        let target = match self.resolve(target).await {
+            Ok(target) => target,
+            Err(status) => return Ok(status),
+        };
+        if fs::metadata(path).await.is_err() {
+            return Ok(StatusCode::NOT_FOUND);
+        }
+        if path == self.mount.root || target == self.mount.root || target.starts_with(path) {
+            return Ok(StatusCode::FORBIDDEN);
+        }
+        if !is_collection(target.parent()).await {
+            return Ok(StatusCode::CONFLICT);
+        }
+        let overwrite = request
+            .headers()
+            .get(OVERWRITE)
+            .map_or(true, |value| !value.as_bytes().eq_ignore_ascii_case(b"F"));
+        let existed = match fs::metadata(&target).await {
+            Ok(_) if !overwrite => return Ok(StatusCode::PRECONDITION_FAILED),
+            Ok(metadata) if metadata.is_dir() => {
+                if let Some(status) = self.check_tree(&target).await? {
+                    return Ok(status);
+                }
+                fs::remove_dir_all(&target).await?;
+                true
+            }
+            Ok(_) => {
+                fs::remove_file(&target).await?;
+                true
+            }
+            Err(_) => false,
+        };
+        fs::rename(path, &target).await?;
+        Ok(match existed {
+            true => StatusCode::NO_CONTENT,
+            false => StatusCode::CREATED,
+        })
+    }
+
+    /// 207 with the live properties of `path` and, for `Depth: 1`, its members. Every
+    /// property is returned whatever the request body asks for, `Depth: infinity` is 403.
+    async fn propfind(
+        &self,
+        rest: &str,
+        path: &Path,
+        request: &Request<Body>,
+        response: &mut Response<Writer>,
+    ) -> Result<StatusCode, Box<dyn Error>> {
+        let depth = request
+            .headers()
+            .get(DEPTH)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("infinity");
+        let with_members = match depth.trim() {
+            "0" => false,
+            "1" => true,
+            _ => return Ok(StatusCode::FORBIDDEN),
+        };
+        let Ok(metadata) = fs::metadata(path).await else {
+            return Ok(StatusCode::NOT_FOUND);
+        };
+        let base = format!("{}{}", self.mount.prefix.trim_end_matches('/'), rest);
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+        );
+        xml.push_str(&prop_response(&base, &metadata));
+        if with_members && metadata.is_dir() {
+            let mut entries = fs::read_dir(path).await?;
+            let mut members = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                let Some(name) = entry.file_name().to_str().map(String::from) else {
+                    continue;
+                };
+                if check_file_access(&entry.path(), &self.mount)
+                    .await
+                    .is_some()
+                {
+                    continue;
+                }
+                if let Ok(metadata) = fs::metadata(entry.path()).await {
+                    members.push((name, metadata));
+                }
+            }
+            members.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, metadata) in members {
+                let href = format!("{}/{}", base.trim_end_matches('/'), name);
+                xml.push_str(&prop_response(&href, &metadata));
+            }
+        }
+        xml.push_str("</D:multistatus>\n");
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        );
+        response.body_mut().body = xml;
+        Ok(StatusCode::MULTI_STATUS)
+    }
+}
+
+/// 201 for a new collection, 405 when `path` exists, 415 for a request body.
+async fn mkcol(path: &Path, request: &Request<Body>) -> Result<StatusCode, Box<dyn Error>> {
+    if request.body().len > 0 || request.body().pending.is_some() {
+        return Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    if fs::metadata(path).await.is_ok() {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    if !is_collection(path.parent()).await {
+        return Ok(StatusCode::CONFLICT);
+    }
+    fs::create_dir(path).await?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn is_collection(path: Option<&Path>) -> bool {
+    match path {
+        Some(path) => fs::metadata(path).await.is_ok_and(|m| m.is_dir()),
+        None => false,
+    }
+}
+
+/// `D:response` of one resource at decoded `path`, collections get a trailing `/` on the href.
+fn prop_response(path: &str, metadata: &std::fs::Metadata) -> String {
+    let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+    let mut href = encode_href(path);
+    let mut props = String::new();
+    if metadata.is_dir() {
+        if !href.ends_with('/') {
+            href.push('/');
+        }
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        props.push_str("<D:resourcetype/>");
+        props.push_str(&format!(
+            "<D:getcontentlength>{}</D:getcontentlength>",
+            metadata.len()
+        ));
+        props.push_str(&format!(
+            "<D:getcontenttype>{}</D:getcontenttype>",
+            escape_xml(&get_content_type(name))
+        ));
+    }
+    props.push_str(&format!(
+        "<D:displayname>{}</D:displayname>",
+        escape_xml(name)
+    ));
+    if let Ok(modified) = metadata.modified() {
+        props.push_str(&format!(
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            httpdate::fmt_http_date(modified)
+        ));
+        if metadata.is_file() {
+            props.push_str(&format!(
+                "<D:getetag>{}</D:getetag>",
+                escape_xml(&file_etag(metadata.len(), modified))
+            ));
+        }
+    }
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape_xml(&href),
+        props
+    )
+}
+
+/// Percent-encode everything of `path` but unreserved characters and `/`.
+fn encode_href(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(all(test, not(feature = "tokio_rustls")))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{DotfilePolicy, Options, Server};
+
+    /// Raw response of `handler` to one request on its own connection.
+    async fn send(handler: &Arc<DavHandler>, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let handler = handler.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let parsed = Server::parse_request(stream, Options::new()).await.ok();
+            let Some((mut request, mut response)) = parsed else {
+                return false;
+            };
+            handler.handle(&mut request, &mut response).await.is_ok()
+        });
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut output = Vec::new();
+        let read = client.read_to_end(&mut output);
+        let _ = tokio::time::timeout(Duration::from_secs(5), read).await;
+        assert!(server.await.unwrap(), "handler failed");
+        String::from_utf8(output).unwrap()
+    }
+
+    fn request(method: &str, path: &str, headers: &str, body: &str) -> String {
+        format!(
+            "{} {} HTTP/1.1\r\nHost: a\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    fn status(output: &str) -> &str {
+        &output[9..12]
+    }
+
+    /// Handler over a fresh directory named after the test, removed by the caller.
+    fn handler(name: &str) -> (Arc<DavHandler>, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("atomic_http_dav_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let mut mount = StaticMount::new("/dav", &root);
+        mount.dotfile_policy = DotfilePolicy::Deny;
+        (Arc::new(DavHandler::new(mount)), root)
+    }
+
+    #[tokio::test]
+    async fn manages_files_and_collections() {
+        let (dav, root) = handler("manage");
+        let output = send(&dav, &request("OPTIONS", "/dav/", "", "")).await;
+        assert_eq!(status(&output), "200");
+        assert!(output.contains("dav: 1\r\n"), "{}", output);
+        assert!(output.contains(METHODS), "{}", output);
+
+        let output = send(&dav, &request("MKCOL", "/dav/docs", "", "")).await;
+        assert_eq!(status(&output), "201");
+        let output = send(&dav, &request("MKCOL", "/dav/docs", "", "")).await;
+        assert_eq!(status(&output), "405");
+        let output = send(&dav, &request("MKCOL", "/dav/a/b", "", "")).await;
+        assert_eq!(status(&output), "409");
+        let output = send(&dav, &request("MKCOL", "/dav/c", "", "body")).await;
+        assert_eq!(status(&output), "415");
+
+        let output = send(&dav, &request("PUT", "/dav/docs/a%20b.txt", "", "hello")).await;
+        assert_eq!(status(&output), "201");
+        let output = send(&dav, &request("PUT", "/dav/docs/a%20b.txt", "", "hello!")).await;
+        assert_eq!(status(&output), "204");
+        assert_eq!(std::fs::read(root.join("docs/a b.txt")).unwrap(), b"hello!");
+        let output = send(&dav, &request("PUT", "/dav/none/a.txt", "", "x")).await;
+        assert_eq!(status(&output), "409");
+
+        let output = send(&dav, &request("GET", "/dav/docs/a%20b.txt", "", "")).await;
+        assert_eq!(status(&output), "200");
+        assert!(output.ends_with("\r\n\r\nhello!"), "{}", output);
+        let output = send(&dav, &request("GET", "/dav/docs", "", "")).await;
+        assert_eq!(status(&output), "405");
+        let output = send(&dav, &request("GET", "/dav/missing", "", "")).await;
+        assert_eq!(status(&output), "404");
+
+        let output = send(&dav, &request("PROPFIND", "/dav/docs", "Depth: 1\r\n", "")).await;
+        assert_eq!(status(&output), "207");
+        assert!(output.contains("<D:href>/dav/docs/</D:href>"), "{}", output);
+        assert!(
+            output.contains("<D:href>/dav/docs/a%20b.txt</D:href>"),
+            "{}",
+            output
+        );
+        assert!(output.contains("<D:getcontentlength>6</D:getcontentlength>"));
+        let output = send(&dav, &request("PROPFIND", "/dav/docs", "Depth: 0\r\n", "")).await;
+        assert!(!output.contains("a%20b.txt"), "{}", output);
+        let output = send(&dav, &request("PROPFIND", "/dav/docs", "", "")).await;
+        assert_eq!(status(&output), "403");
+
+        let destination = "Destination: http://a/dav/b.txt\r\n";
+        let output = send(
+            &dav,
+            &request("MOVE", "/dav/docs/a%20b.txt", destination, ""),
+        )
+        .await;
+        assert_eq!(status(&output), "201");
+        assert!(root.join("b.txt").is_file());
+        let outside = "Destination: http://a/other/b.txt\r\n";
+        let output = send(&dav, &request("MOVE", "/dav/b.txt", outside, "")).await;
+        assert_eq!(status(&output), "502");
+        send(&dav, &request("PUT", "/dav/c.txt", "", "c")).await;
+        let keep = "Destination: /dav/c.txt\r\nOverwrite: F\r\n";
+        let output = send(&dav, &request("MOVE", "/dav/b.txt", keep, "")).await;
+        assert_eq!(status(&output), "412");
+
+        let output = send(&dav, &request("DELETE", "/dav/docs", "", "")).await;
+        assert_eq!(status(&output), "204");
+        assert!(!root.join("docs").exists());
+        let output = send(&dav, &request("DELETE", "/dav/", "", "")).await;
+        assert_eq!(status(&output), "403");
+        let output = send(&dav, &request("DELETE", "/dav/docs", "", "")).await;
+        assert_eq!(status(&output), "404");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_refused_entries() {
+        let (dav, root) = handler("refused");
+        std::fs::create_dir_all(root.join("site")).unwrap();
+        std::fs::write(root.join("site/.env"), "secret").unwrap();
+        std::fs::write(root.join("site/index.html"), "<p>").unwrap();
+
+        let output = send(&dav, &request("GET", "/dav/site/.env", "", "")).await;
+        assert_eq!(status(&output), "403");
+        // `..` is collapsed before the prefix is matched, climbing out leaves the mount.
+        let climb = "/dav/site/%2e%2e/%2e%2e/etc/passwd";
+        let output = send(&dav, &request("GET", climb, "", "")).await;
+        assert_eq!(status(&output), "404");
+        let output = send(&dav, &request("PROPFIND", "/dav/site", "Depth: 1\r\n", "")).await;
+        assert!(output.contains("index.html"), "{}", output);
+        assert!(!output.contains(".env"), "{}", output);
+        let output = send(&dav, &request("DELETE", "/dav/site", "", "")).await;
+        assert_eq!(status(&output), "403");
+        assert!(root.join("site/.env").is_file());
+        let output = send(
+            &dav,
+            &request("MOVE", "/dav/site", "Destination: /dav/site/in\r\n", ""),
+        )
+        .await;
+        assert_eq!(status(&output), "403");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
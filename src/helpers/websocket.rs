@@ -0,0 +1,75 @@
+use std::error::Error;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::header::{CONNECTION, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE};
+use http::{HeaderMap, Method};
+use sha1::{Digest, Sha1};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::Writer;
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+impl Writer {
+    /// GET with `Upgrade: websocket`, `Connection: upgrade` and a `Sec-WebSocket-Key`.
+    pub fn is_websocket_upgrade(&self) -> bool {
+        self.request_method == Method::GET
+            && has_token(&self.request_headers, UPGRADE, "websocket")
+            && has_token(&self.request_headers, CONNECTION, "upgrade")
+            && self.request_headers.contains_key(SEC_WEBSOCKET_KEY)
+    }
+
+    /// Write the 101 handshake and hand back the socket for WebSocket frames.
+    /// Anything that is not a version 13 upgrade gets 400 or 426 and an error.
+    pub async fn upgrade(mut self, protocol: Option<&str>) -> Result<TcpStream, Box<dyn Error>> {
+        let version = self
+            .request_headers
+            .get(SEC_WEBSOCKET_VERSION)
+            .and_then(|value| value.to_str().ok());
+        if !self.is_websocket_upgrade() || version != Some("13") {
+            let (status, extra) = match self.is_websocket_upgrade() {
+                true => ("426 Upgrade Required", "sec-websocket-version: 13\r\n"),
+                false => ("400 Bad Request", ""),
+            };
+            let head = format!(
+                "HTTP/1.1 {}\r\n{}connection: close\r\ncontent-length: 0\r\n\r\n",
+                status, extra
+            );
+            self.stream.write_all(head.as_bytes()).await?;
+            self.stream.flush().await?;
+            return Err(format!("WebSocket upgrade refused: {}", status).into());
+        }
+
+        let key = self.request_headers[SEC_WEBSOCKET_KEY].as_bytes();
+        let mut head = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\nconnection: Upgrade\r\nsec-websocket-accept: {}\r\n",
+            accept_key(key)
+        );
+        if let Some(protocol) = protocol {
+            head.push_str(&format!("sec-websocket-protocol: {}\r\n", protocol));
+        }
+        head.push_str("\r\n");
+        dev_print!("websocket upgrade: {}", &head);
+        self.stream.write_all(head.as_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(self.stream)
+    }
+}
+
+/// `Sec-WebSocket-Accept` for a client key, RFC 6455 4.2.2.
+fn accept_key(key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key);
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+fn has_token(headers: &HeaderMap, name: http::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
@@ -1,14 +1,56 @@
-use std::{env::current_dir, error::Error, io, net::SocketAddr, path::PathBuf};
+use std::{
+    env::current_dir,
+    error::Error,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 #[cfg(feature = "env")]
 use std::str::FromStr;
 
+pub use helpers::body_sink::{
+    BodySink, BodySinkFactory, BodyStorage, FileSink, MemorySink, SpooledFile,
+};
+pub use helpers::body_stream::{BodyReader, PendingBody};
+#[cfg(feature = "checksum")]
+pub use helpers::checksum::{content_digest, Checksum, ChecksumAlgorithm};
+pub use helpers::chunked::ChunkedStream;
+#[cfg(feature = "compression")]
+pub use helpers::compression::Encoding;
+pub use helpers::conditional::Validators;
+pub use helpers::cookie::{parse_cookies, SameSite, SetCookie};
+#[cfg(feature = "signing")]
+pub use helpers::cookie_key::CookieKey;
+pub use helpers::error::{ParseError, ParseIssue};
+pub use helpers::file_cache::FileCache;
+pub use helpers::form_data::FilePart;
+pub use helpers::lanes::{LaneMatcher, LanePermit, PriorityLanes};
+pub use helpers::memory::MemoryUsage;
+pub use helpers::metrics::{CountingAllocator, ParserMetrics, ParserMetricsSnapshot};
+pub use helpers::multipart::{MultipartBuilder, MultipartStream};
+pub use helpers::multipart_upload::{MultipartUpload, UploadedFile};
+pub use helpers::path::decode_path;
+pub use helpers::query::{from_query, parse_query, QueryError};
+pub use helpers::range::{parse_range, parse_ranges, ByteRange, ByteSource};
+pub use helpers::redirect::safe_redirect_target;
+#[cfg(feature = "s3")]
+pub use helpers::s3_sink::{HttpTransport, S3Config, S3MultipartSink, S3Transport, MIN_PART_SIZE};
+#[cfg(feature = "signing")]
+pub use helpers::signed_url::{SignedUrlError, UrlSigner};
+pub use helpers::sse::{SseEvent, SseStream};
 pub use helpers::traits::http_request::RequestUtils;
 pub use helpers::traits::http_response::ResponseUtil;
 pub use helpers::traits::http_stream::StreamHttp;
+#[cfg(feature = "tus")]
+pub use helpers::tus::{FileUploadStore, MemoryUploadStore, TusHandler, UploadInfo, UploadStore};
+#[cfg(feature = "webdav")]
+pub use helpers::webdav::DavHandler;
 
 pub mod external {
     pub use async_trait;
+    pub use bytes;
     #[cfg(feature = "env")]
     pub use dotenv;
     pub use http;
@@ -17,7 +59,8 @@ pub mod external {
     pub use tokio;
 }
 
-use http::{Request, Response};
+use bytes::Bytes;
+use http::{HeaderMap, Method, Request, Response};
 
 #[macro_export]
 macro_rules! dev_print {
@@ -28,14 +71,21 @@ macro_rules! dev_print {
     };
 }
 
+#[cfg(not(feature = "tokio_rustls"))]
+use std::io;
 #[cfg(not(feature = "tokio_rustls"))]
 use tokio::net::TcpListener;
+#[cfg(not(feature = "tokio_rustls"))]
+use tokio::sync::Notify;
 
 use tokio::net::TcpStream;
 #[cfg(feature = "tokio_rustls")]
 use tokio_rustls::server::TlsStream;
 
 mod helpers;
+use helpers::memory::MemoryLease;
+use helpers::range::SourceBody;
+use helpers::throttle::Throttle;
 pub struct Server {
     #[cfg(not(feature = "tokio_rustls"))]
     pub listener: TcpListener,
@@ -50,7 +100,416 @@ pub struct Options {
     pub read_buffer_size: usize,
     pub read_max_retry: u8,
     pub read_imcomplete_size: usize,
+    pub maintenance: bool,
+    pub maintenance_retry_after: Option<Duration>,
+    pub maintenance_allowlist: Vec<String>,
+    pub maintenance_page: String,
+    pub priority_lanes: Option<PriorityLanes>,
+    pub draining: bool,
+    pub drain_health_paths: Vec<String>,
+    pub require_host: bool,
+    pub body_spool_threshold: usize,
+    pub body_spool_dir: PathBuf,
+    pub body_sink: Option<BodySinkFactory>,
+    /// Return from `parse_request` after the head and leave the body on the socket, read it
+    /// with `Body::reader`.
+    pub stream_request_body: bool,
+    /// Parse Content-Length `multipart/form-data` bodies while reading, parts above this many
+    /// bytes go to `body_spool_dir`, 0 disables. Read with `RequestUtils::take_multipart_upload`.
+    pub multipart_spool_threshold: usize,
+    /// Parts in one multipart body, 0 for no limit.
+    pub max_multipart_parts: usize,
+    /// Body of one multipart part, 0 for no limit.
+    pub max_multipart_part_size: usize,
+    /// Whole multipart body, 0 for no limit.
+    pub max_multipart_size: usize,
+    /// `name` and `filename` of a multipart part, 0 for no limit.
+    pub max_multipart_name_length: usize,
+    pub handle_server_options: bool,
+    pub server_options_allow: String,
+    pub max_uri_length: usize,
+    pub max_query_params: usize,
+    pub header_value_mode: HeaderValueMode,
+    pub normalize_request: bool,
+    pub read_timeout_response: bool,
+    /// Request bodies over this many bytes are answered 413 before they are read, 0 for no
+    /// limit. Chunked bodies stop once their chunk sizes add up to more.
+    pub max_body_size: usize,
+    /// Cap on a decoded chunked request body, 0 for no limit.
+    pub max_chunked_size: usize,
+    /// Request body bytes read per second on one connection, 0 for no limit.
+    pub read_rate_limit: u64,
+    /// `read_rate_limit` for path prefixes, the first match wins.
+    pub read_rate_routes: Vec<(String, u64)>,
+    /// Response bytes written per second on one connection, 0 for no limit.
+    pub write_rate_limit: u64,
+    /// `write_rate_limit` for path prefixes, the first match wins.
+    pub write_rate_routes: Vec<(String, u64)>,
+    /// Bytes after `;` on a chunk size line, 0 for no limit.
+    pub max_chunk_extension_size: usize,
+    /// All trailer lines of a chunked body together, 0 for no limit.
+    pub max_trailer_size: usize,
+    /// Chunks in one chunked body, 0 for no limit.
+    pub max_chunk_count: usize,
+    /// Allow `Server::parse_next_request` on the same connection after a response.
+    pub keep_alive: bool,
+    /// How long a kept-alive connection may sit idle before the next request.
+    pub keep_alive_timeout_miliseconds: u64,
+    /// Decides on `Expect: 100-continue` before the body is read, `None` always continues.
+    pub expect_continue: Option<ExpectContinue>,
+    /// Hosts served by this server, others get 421. `*.example.com` matches subdomains, empty allows all.
+    pub allowed_hosts: Vec<String>,
+    /// Bytes allowed in request and response buffers before new requests get 503, 0 for no limit.
+    pub memory_budget: usize,
+    /// Runs on the peer address right after accept, before anything is read.
+    pub accept_filter: Option<AcceptFilter>,
+    /// Reject bare CR/LF in the head and conflicting Content-Length values, for use behind proxies.
+    pub strict_framing: bool,
+    /// Request rewrites run in order, response rewrites in reverse order.
+    pub transforms: Transforms,
+    /// Send failures, read timeouts and what the application reports, with request context.
+    pub on_internal_error: Option<InternalErrorHook>,
+    /// Symlinks in `response_file` and internal redirect paths.
+    pub symlink_policy: SymlinkPolicy,
+    pub dotfile_policy: DotfilePolicy,
+    /// Names never served from `root_path`, as `.git`, `*.bak` or `backup*`.
+    pub file_denylist: Vec<String>,
+    /// URL prefixes served from their own roots by `Writer::response_static` and internal
+    /// redirects, the longest prefix wins. Other paths are served from `root_path`.
+    pub static_mounts: Vec<StaticMount>,
+    /// Send `file.br`, `file.zst` or `file.gz` next to a `response_file` when `Accept-Encoding` allows it.
+    pub precompressed: bool,
+    /// Keeps stats and bytes of `response_file` files in memory, `None` reads the disk every time.
+    pub file_cache: Option<FileCache>,
+    /// Hosts `ResponseUtil::redirect` may send clients to, empty allows paths on this server only.
+    pub redirect_allowed_hosts: Vec<String>,
+    /// ETag and Last-Modified from file metadata with 304 answers, off for roots whose files are
+    /// generated per request.
+    pub file_validators: bool,
+    /// `application/json` and `multipart/form-data` bodies that do not start like one get 400.
+    pub verify_content_types: Vec<String>,
+    /// Request line plus headers, larger heads get 431 and the connection is closed. 0 for no limit.
+    pub max_header_size: usize,
+    /// One header line, longer lines get 431 and the connection is closed. 0 for no limit.
+    pub max_header_line_size: usize,
+    /// Header lines in the head, more get 431 and the connection is closed. 0 for no limit.
+    pub max_header_count: usize,
+    /// Ranges in one `Range` header answered as `multipart/byteranges`, more get the whole body.
+    /// 0 or 1 answers single ranges only.
+    pub max_ranges: usize,
+    /// Time from the start of a request until file IO in the response gives up with 504, 0 for none.
+    pub request_timeout_miliseconds: u64,
+    /// Answer CORS preflight requests from the `cors_*` options without returning them.
+    pub handle_preflight: bool,
+    /// Origins allowed by a preflight, `*` allows any.
+    pub cors_allow_origins: Vec<String>,
+    pub cors_allow_methods: String,
+    /// `None` allows the headers named in `Access-Control-Request-Headers`.
+    pub cors_allow_headers: Option<String>,
+    pub cors_allow_credentials: bool,
+    pub cors_max_age: Option<Duration>,
+    /// gzip/deflate string, bytes and file bodies when the client accepts it.
+    #[cfg(feature = "compression")]
+    pub compress_responses: bool,
+    #[cfg(feature = "compression")]
+    pub compression_min_size: usize,
+    /// Content type prefixes to compress, empty skips known compressed media only.
+    #[cfg(feature = "compression")]
+    pub compression_types: Vec<String>,
+    /// Keep compressed `response_file` output under this directory, at the file's path and named
+    /// by mtime and size so a changed file is compressed again. Keep it outside the served roots.
+    #[cfg(feature = "compression")]
+    pub compression_cache_dir: Option<PathBuf>,
+    /// `Content-Digest` of string and bytes bodies, a trailer on `ChunkedStream`.
+    #[cfg(feature = "checksum")]
+    pub response_checksum: Option<ChecksumAlgorithm>,
+    /// Requests under `signed_url_paths` need a url signed by this, others get 403.
+    #[cfg(feature = "signing")]
+    pub url_signer: Option<UrlSigner>,
+    /// Path prefixes, as `/downloads/`.
+    #[cfg(feature = "signing")]
+    pub signed_url_paths: Vec<String>,
     current_client_addr: Option<SocketAddr>,
+    /// Bytes read past the previous request on a kept-alive connection.
+    pipelined: Vec<u8>,
+    /// Wakes connections idling in `parse_next_request` so they close, shared by all clones.
+    #[cfg(not(feature = "tokio_rustls"))]
+    idle_reaper: Arc<Notify>,
+    /// Set by `AcceptDecision::Delay` for this connection.
+    #[cfg(not(feature = "tokio_rustls"))]
+    accept_delay: Option<Duration>,
+}
+
+/// How header values with bytes outside visible ASCII(obs-text) are handled.
+/// Control characters other than HTAB are rejected in every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderValueMode {
+    /// Only visible ASCII, SP and HTAB.
+    Strict,
+    /// obs-text is kept as opaque bytes(RFC 9110).
+    Opaque,
+    /// Invalid UTF-8 is replaced with U+FFFD.
+    Lossy,
+    /// obs-text is decoded as ISO-8859-1 and stored as UTF-8.
+    Latin1,
+}
+
+impl std::str::FromStr for HeaderValueMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(HeaderValueMode::Strict),
+            "opaque" => Ok(HeaderValueMode::Opaque),
+            "lossy" => Ok(HeaderValueMode::Lossy),
+            "latin1" => Ok(HeaderValueMode::Latin1),
+            _ => Err(format!("unknown header value mode: {}", s)),
+        }
+    }
+}
+
+/// Symbolic links met while resolving a file to serve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Any symlink in the path is answered with 404.
+    Deny,
+    /// Symlinks are followed as long as the target stays under `root_path`.
+    WithinRoot,
+    /// Symlinks are followed anywhere.
+    Allow,
+}
+
+impl std::str::FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "deny" => Ok(SymlinkPolicy::Deny),
+            "within_root" => Ok(SymlinkPolicy::WithinRoot),
+            "allow" => Ok(SymlinkPolicy::Allow),
+            _ => Err(format!("unknown symlink policy: {}", s)),
+        }
+    }
+}
+
+/// URL prefix served from `root` with its own file policies, see `Options::static_mounts`.
+#[derive(Debug, Clone)]
+pub struct StaticMount {
+    /// `/assets` matches `/assets` and `/assets/...`, not `/assets2`.
+    pub prefix: String,
+    pub root: PathBuf,
+    pub symlink_policy: SymlinkPolicy,
+    pub dotfile_policy: DotfilePolicy,
+    /// Names never served from `root`, as `Options::file_denylist`.
+    pub file_denylist: Vec<String>,
+}
+
+impl StaticMount {
+    /// Policies start as the `Options::new` defaults, not the global ones.
+    pub fn new(prefix: &str, root: impl Into<PathBuf>) -> StaticMount {
+        StaticMount {
+            prefix: prefix.into(),
+            root: root.into(),
+            symlink_policy: SymlinkPolicy::Allow,
+            dotfile_policy: DotfilePolicy::Allow,
+            file_denylist: Vec::new(),
+        }
+    }
+}
+
+/// Files and directories starting with `.` under `root_path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotfilePolicy {
+    Allow,
+    /// Answered with 403, `file_denylist` matches too.
+    Deny,
+    /// Answered with 404 as if missing, `file_denylist` matches too.
+    Ignore,
+}
+
+impl std::str::FromStr for DotfilePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "allow" => Ok(DotfilePolicy::Allow),
+            "deny" => Ok(DotfilePolicy::Deny),
+            "ignore" => Ok(DotfilePolicy::Ignore),
+            _ => Err(format!("unknown dotfile policy: {}", s)),
+        }
+    }
+}
+
+type ExpectFn = dyn Fn(&Request<()>) -> bool + Send + Sync;
+
+/// Check on the request head, returning false answers 417 without reading the body.
+#[derive(Clone)]
+pub struct ExpectContinue(Arc<ExpectFn>);
+
+impl ExpectContinue {
+    pub fn new<F>(check: F) -> ExpectContinue
+    where
+        F: Fn(&Request<()>) -> bool + Send + Sync + 'static,
+    {
+        ExpectContinue(Arc::new(check))
+    }
+
+    pub fn check(&self, request: &Request<()>) -> bool {
+        (self.0)(request)
+    }
+}
+
+impl std::fmt::Debug for ExpectContinue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ExpectContinue")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptDecision {
+    Allow,
+    /// Close the connection without reading from it.
+    Deny,
+    /// Wait before reading the first request, the accept loop is not held up.
+    Delay(Duration),
+}
+
+type AcceptFn = dyn Fn(&SocketAddr) -> AcceptDecision + Send + Sync;
+
+/// Admission check on the peer address, cheaper than parsing the request.
+#[derive(Clone)]
+pub struct AcceptFilter(Arc<AcceptFn>);
+
+impl AcceptFilter {
+    pub fn new<F>(filter: F) -> AcceptFilter
+    where
+        F: Fn(&SocketAddr) -> AcceptDecision + Send + Sync + 'static,
+    {
+        AcceptFilter(Arc::new(filter))
+    }
+
+    pub fn check(&self, addr: &SocketAddr) -> AcceptDecision {
+        (self.0)(addr)
+    }
+}
+
+impl std::fmt::Debug for AcceptFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AcceptFilter")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InternalErrorKind {
+    /// Never reported by the crate, handlers run in the application's tasks. The application
+    /// passes in the `JoinError` of a panicked handler with `Options::report_internal_error`.
+    Panic,
+    /// `responser` could not send the response.
+    Send,
+    /// The request body did not arrive in time, or file IO passed the request deadline.
+    Timeout,
+}
+
+/// Request an internal error happened on.
+#[derive(Debug, Clone)]
+pub struct RequestSummary {
+    pub method: Method,
+    pub path: String,
+    /// `X-Request-Id` header of the request.
+    pub request_id: Option<String>,
+    /// When the request started to be read.
+    pub started: Instant,
+}
+
+impl RequestSummary {
+    pub fn new<T>(request: &Request<T>, started: Instant) -> RequestSummary {
+        RequestSummary {
+            method: request.method().clone(),
+            path: request.uri().path().into(),
+            request_id: request
+                .headers()
+                .get("x-request-id")
+                .and_then(|value| value.to_str().ok())
+                .map(String::from),
+            started,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+type InternalErrorFn = dyn Fn(&RequestSummary, InternalErrorKind, &dyn Error) + Send + Sync;
+
+/// Gets errors the client can not be told about, to wire an error reporting service once.
+#[derive(Clone)]
+pub struct InternalErrorHook(Arc<InternalErrorFn>);
+
+impl InternalErrorHook {
+    pub fn new<F>(hook: F) -> InternalErrorHook
+    where
+        F: Fn(&RequestSummary, InternalErrorKind, &dyn Error) + Send + Sync + 'static,
+    {
+        InternalErrorHook(Arc::new(hook))
+    }
+
+    pub fn report(&self, summary: &RequestSummary, kind: InternalErrorKind, error: &dyn Error) {
+        (self.0)(summary, kind, error)
+    }
+}
+
+impl std::fmt::Debug for InternalErrorHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InternalErrorHook")
+    }
+}
+
+/// Gateway style rewrite of requests and responses, added with `Transforms::push`.
+pub trait Transform: Send + Sync {
+    /// Runs after the built in answers (maintenance, preflight, ...), before the handler.
+    fn request(&self, _request: &mut Request<Body>) {}
+    /// Runs first in `responser`, before compression and checksums see the body.
+    fn response(&self, _response: &mut Response<Writer>) {}
+}
+
+/// Transforms applied to every parsed request and every response sent by `responser`.
+#[derive(Clone, Default)]
+pub struct Transforms(Vec<Arc<dyn Transform>>);
+
+impl Transforms {
+    pub fn push<T: Transform + 'static>(&mut self, transform: T) {
+        self.0.push(Arc::new(transform));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn apply_request(&self, request: &mut Request<Body>) {
+        for transform in self.0.iter() {
+            transform.request(request);
+        }
+    }
+
+    /// Last added first, so the first transform sees the final response like a middleware stack.
+    pub fn apply_response(&self, response: &mut Response<Writer>) {
+        for transform in self.0.iter().rev() {
+            transform.response(response);
+        }
+    }
+}
+
+impl std::fmt::Debug for Transforms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Transforms({})", self.0.len())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Options {
@@ -62,7 +521,88 @@ impl Options {
             read_buffer_size: 4096,
             read_max_retry: 3,
             read_imcomplete_size: 0,
+            maintenance: false,
+            maintenance_retry_after: None,
+            maintenance_allowlist: Vec::new(),
+            maintenance_page: "Service Unavailable".into(),
+            priority_lanes: None,
+            draining: false,
+            drain_health_paths: vec!["/health".into()],
+            require_host: true,
+            body_spool_threshold: 0,
+            body_spool_dir: std::env::temp_dir(),
+            body_sink: None,
+            stream_request_body: false,
+            multipart_spool_threshold: 0,
+            max_multipart_parts: 1024,
+            max_multipart_part_size: 0,
+            max_multipart_size: 0,
+            max_multipart_name_length: 1024,
+            handle_server_options: true,
+            server_options_allow: "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS".into(),
+            max_uri_length: 8192,
+            max_query_params: 1024,
+            header_value_mode: HeaderValueMode::Opaque,
+            normalize_request: false,
+            read_timeout_response: false,
+            max_body_size: 0,
+            max_chunked_size: 0,
+            read_rate_limit: 0,
+            read_rate_routes: Vec::new(),
+            write_rate_limit: 0,
+            write_rate_routes: Vec::new(),
+            max_chunk_extension_size: 1024,
+            max_trailer_size: 8192,
+            max_chunk_count: 1048576,
+            keep_alive: false,
+            keep_alive_timeout_miliseconds: 5000,
+            expect_continue: None,
+            allowed_hosts: vec![],
+            memory_budget: 0,
+            accept_filter: None,
+            transforms: Transforms::default(),
+            on_internal_error: None,
+            strict_framing: false,
+            symlink_policy: SymlinkPolicy::Allow,
+            dotfile_policy: DotfilePolicy::Allow,
+            file_denylist: Vec::new(),
+            static_mounts: Vec::new(),
+            precompressed: false,
+            file_cache: None,
+            redirect_allowed_hosts: Vec::new(),
+            file_validators: true,
+            verify_content_types: Vec::new(),
+            max_header_size: 65536,
+            max_header_line_size: 16384,
+            max_header_count: 128,
+            max_ranges: 16,
+            request_timeout_miliseconds: 0,
+            handle_preflight: false,
+            cors_allow_origins: vec!["*".into()],
+            cors_allow_methods: "GET, HEAD, POST, PUT, PATCH, DELETE".into(),
+            cors_allow_headers: None,
+            cors_allow_credentials: false,
+            cors_max_age: None,
+            #[cfg(feature = "compression")]
+            compress_responses: false,
+            #[cfg(feature = "compression")]
+            compression_min_size: 1024,
+            #[cfg(feature = "compression")]
+            compression_types: Vec::new(),
+            #[cfg(feature = "compression")]
+            compression_cache_dir: None,
+            #[cfg(feature = "checksum")]
+            response_checksum: None,
+            #[cfg(feature = "signing")]
+            url_signer: None,
+            #[cfg(feature = "signing")]
+            signed_url_paths: Vec::new(),
             current_client_addr: None,
+            pipelined: vec![],
+            #[cfg(not(feature = "tokio_rustls"))]
+            idle_reaper: Arc::new(Notify::new()),
+            #[cfg(not(feature = "tokio_rustls"))]
+            accept_delay: None,
         };
 
         #[cfg(feature = "env")]
@@ -101,6 +641,389 @@ impl Options {
                     _options.read_imcomplete_size = data;
                 }
             }
+
+            if let Ok(data) = env::var("MAINTENANCE") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.maintenance = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAINTENANCE_RETRY_AFTER") {
+                if let Ok(data) = data.parse::<u64>() {
+                    _options.maintenance_retry_after = Some(Duration::from_secs(data));
+                }
+            }
+
+            if let Ok(data) = env::var("REQUIRE_HOST") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.require_host = data;
+                }
+            }
+
+            if let Ok(data) = env::var("BODY_SPOOL_THRESHOLD") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.body_spool_threshold = data;
+                }
+            }
+
+            if let Ok(data) = env::var("BODY_SPOOL_DIR") {
+                _options.body_spool_dir = PathBuf::from_str(&data).unwrap();
+            }
+
+            if let Ok(data) = env::var("STREAM_REQUEST_BODY") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.stream_request_body = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MULTIPART_SPOOL_THRESHOLD") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.multipart_spool_threshold = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_MULTIPART_PARTS") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_multipart_parts = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_MULTIPART_PART_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_multipart_part_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_MULTIPART_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_multipart_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_MULTIPART_NAME_LENGTH") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_multipart_name_length = data;
+                }
+            }
+
+            if let Ok(data) = env::var("HANDLE_SERVER_OPTIONS") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.handle_server_options = data;
+                }
+            }
+
+            if let Ok(data) = env::var("SERVER_OPTIONS_ALLOW") {
+                _options.server_options_allow = data;
+            }
+
+            if let Ok(data) = env::var("MAX_URI_LENGTH") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_uri_length = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_QUERY_PARAMS") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_query_params = data;
+                }
+            }
+
+            if let Ok(data) = env::var("HEADER_VALUE_MODE") {
+                if let Ok(data) = data.parse::<HeaderValueMode>() {
+                    _options.header_value_mode = data;
+                }
+            }
+
+            if let Ok(data) = env::var("NORMALIZE_REQUEST") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.normalize_request = data;
+                }
+            }
+
+            if let Ok(data) = env::var("READ_TIMEOUT_RESPONSE") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.read_timeout_response = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_BODY_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_body_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_CHUNKED_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_chunked_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("READ_RATE_LIMIT") {
+                if let Ok(data) = data.parse::<u64>() {
+                    _options.read_rate_limit = data;
+                }
+            }
+
+            // `/upload/=1048576,/api/=65536`
+            if let Ok(data) = env::var("READ_RATE_ROUTES") {
+                _options.read_rate_routes = data
+                    .split(',')
+                    .filter_map(|route| route.split_once('='))
+                    .filter_map(|(prefix, rate)| {
+                        Some((prefix.trim().to_string(), rate.trim().parse::<u64>().ok()?))
+                    })
+                    .collect();
+            }
+
+            if let Ok(data) = env::var("WRITE_RATE_LIMIT") {
+                if let Ok(data) = data.parse::<u64>() {
+                    _options.write_rate_limit = data;
+                }
+            }
+
+            // `/video/=1048576`
+            if let Ok(data) = env::var("WRITE_RATE_ROUTES") {
+                _options.write_rate_routes = data
+                    .split(',')
+                    .filter_map(|route| route.split_once('='))
+                    .filter_map(|(prefix, rate)| {
+                        Some((prefix.trim().to_string(), rate.trim().parse::<u64>().ok()?))
+                    })
+                    .collect();
+            }
+
+            if let Ok(data) = env::var("MAX_CHUNK_EXTENSION_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_chunk_extension_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_TRAILER_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_trailer_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_CHUNK_COUNT") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_chunk_count = data;
+                }
+            }
+
+            if let Ok(data) = env::var("KEEP_ALIVE") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.keep_alive = data;
+                }
+            }
+
+            if let Ok(data) = env::var("KEEP_ALIVE_TIMEOUT_MILISECONDS") {
+                if let Ok(data) = data.parse::<u64>() {
+                    _options.keep_alive_timeout_miliseconds = data;
+                }
+            }
+
+            if let Ok(data) = env::var("ALLOWED_HOSTS") {
+                _options.allowed_hosts = data
+                    .split(',')
+                    .map(|host| host.trim().to_string())
+                    .filter(|host| !host.is_empty())
+                    .collect();
+            }
+
+            if let Ok(data) = env::var("MEMORY_BUDGET") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.memory_budget = data;
+                }
+            }
+
+            if let Ok(data) = env::var("STRICT_FRAMING") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.strict_framing = data;
+                }
+            }
+
+            if let Ok(data) = env::var("SYMLINK_POLICY") {
+                if let Ok(data) = data.parse::<SymlinkPolicy>() {
+                    _options.symlink_policy = data;
+                }
+            }
+
+            if let Ok(data) = env::var("DOTFILE_POLICY") {
+                if let Ok(data) = data.parse::<DotfilePolicy>() {
+                    _options.dotfile_policy = data;
+                }
+            }
+
+            if let Ok(data) = env::var("FILE_DENYLIST") {
+                _options.file_denylist = data
+                    .split(',')
+                    .map(|pattern| pattern.trim().to_string())
+                    .filter(|pattern| !pattern.is_empty())
+                    .collect();
+            }
+
+            // `/assets=/srv/cdn,/downloads=/data`, with the default policies.
+            if let Ok(data) = env::var("STATIC_MOUNTS") {
+                _options.static_mounts = data
+                    .split(',')
+                    .filter_map(|mount| mount.split_once('='))
+                    .map(|(prefix, root)| StaticMount::new(prefix.trim(), root.trim()))
+                    .collect();
+            }
+
+            if let Ok(data) = env::var("PRECOMPRESSED") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.precompressed = data;
+                }
+            }
+
+            if let Ok(data) = env::var("FILE_CACHE_MAX_BYTES") {
+                if let Ok(max_bytes) = data.parse::<usize>() {
+                    let ttl = env::var("FILE_CACHE_TTL_SECONDS")
+                        .ok()
+                        .and_then(|data| data.parse::<u64>().ok())
+                        .unwrap_or(60);
+                    _options.file_cache = (max_bytes > 0)
+                        .then(|| FileCache::new(max_bytes, Duration::from_secs(ttl)));
+                }
+            }
+
+            if let Ok(data) = env::var("REDIRECT_ALLOWED_HOSTS") {
+                _options.redirect_allowed_hosts = data
+                    .split(',')
+                    .map(|host| host.trim().to_string())
+                    .filter(|host| !host.is_empty())
+                    .collect();
+            }
+
+            if let Ok(data) = env::var("FILE_VALIDATORS") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.file_validators = data;
+                }
+            }
+
+            if let Ok(data) = env::var("VERIFY_CONTENT_TYPES") {
+                _options.verify_content_types = data
+                    .split(',')
+                    .map(|content_type| content_type.trim().to_string())
+                    .filter(|content_type| !content_type.is_empty())
+                    .collect();
+            }
+
+            if let Ok(data) = env::var("MAX_HEADER_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_header_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_HEADER_LINE_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_header_line_size = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_HEADER_COUNT") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_header_count = data;
+                }
+            }
+
+            if let Ok(data) = env::var("MAX_RANGES") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.max_ranges = data;
+                }
+            }
+
+            if let Ok(data) = env::var("REQUEST_TIMEOUT_MILISECONDS") {
+                if let Ok(data) = data.parse::<u64>() {
+                    _options.request_timeout_miliseconds = data;
+                }
+            }
+
+            if let Ok(data) = env::var("HANDLE_PREFLIGHT") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.handle_preflight = data;
+                }
+            }
+
+            if let Ok(data) = env::var("CORS_ALLOW_ORIGINS") {
+                _options.cors_allow_origins = data
+                    .split(',')
+                    .map(|origin| origin.trim().to_string())
+                    .filter(|origin| !origin.is_empty())
+                    .collect();
+            }
+
+            if let Ok(data) = env::var("CORS_ALLOW_METHODS") {
+                _options.cors_allow_methods = data;
+            }
+
+            if let Ok(data) = env::var("CORS_ALLOW_HEADERS") {
+                _options.cors_allow_headers = Some(data);
+            }
+
+            if let Ok(data) = env::var("CORS_ALLOW_CREDENTIALS") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.cors_allow_credentials = data;
+                }
+            }
+
+            if let Ok(data) = env::var("CORS_MAX_AGE") {
+                if let Ok(data) = data.parse::<u64>() {
+                    _options.cors_max_age = Some(Duration::from_secs(data));
+                }
+            }
+
+            #[cfg(feature = "compression")]
+            if let Ok(data) = env::var("COMPRESS_RESPONSES") {
+                if let Ok(data) = data.parse::<bool>() {
+                    _options.compress_responses = data;
+                }
+            }
+
+            #[cfg(feature = "compression")]
+            if let Ok(data) = env::var("COMPRESSION_MIN_SIZE") {
+                if let Ok(data) = data.parse::<usize>() {
+                    _options.compression_min_size = data;
+                }
+            }
+
+            #[cfg(feature = "compression")]
+            if let Ok(data) = env::var("COMPRESSION_TYPES") {
+                _options.compression_types = data
+                    .split(',')
+                    .map(|content_type| content_type.trim().to_string())
+                    .filter(|content_type| !content_type.is_empty())
+                    .collect();
+            }
+
+            #[cfg(feature = "compression")]
+            if let Ok(data) = env::var("COMPRESSION_CACHE_DIR") {
+                _options.compression_cache_dir = Some(PathBuf::from_str(&data).unwrap());
+            }
+
+            #[cfg(feature = "checksum")]
+            if let Ok(data) = env::var("RESPONSE_CHECKSUM") {
+                if let Ok(data) = data.parse::<ChecksumAlgorithm>() {
+                    _options.response_checksum = Some(data);
+                }
+            }
+
+            #[cfg(feature = "signing")]
+            if let Ok(data) = env::var("SIGNED_URL_KEY") {
+                if !data.is_empty() {
+                    _options.url_signer = Some(UrlSigner::new(data));
+                }
+            }
+
+            #[cfg(feature = "signing")]
+            if let Ok(data) = env::var("SIGNED_URL_PATHS") {
+                _options.signed_url_paths = data
+                    .split(',')
+                    .map(|path| path.trim().to_string())
+                    .filter(|path| !path.is_empty())
+                    .collect();
+            }
         }
 
         _options
@@ -112,6 +1035,57 @@ impl Options {
             None => "".into(),
         }
     }
+
+    pub fn is_host_allowed(&self, host: &str) -> bool {
+        // Drop the port, but not the last group of a bracketed IPv6 literal.
+        let name = match host.rfind(':') {
+            Some(colon) if !host[colon..].contains(']') => &host[..colon],
+            _ => host,
+        };
+        self.allowed_hosts.iter().any(|allowed| {
+            if allowed.eq_ignore_ascii_case(host) || allowed.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            match allowed.strip_prefix("*.") {
+                Some(domain) => name
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", domain.to_ascii_lowercase())),
+                None => false,
+            }
+        })
+    }
+
+    pub fn is_cors_origin_allowed(&self, origin: &str) -> bool {
+        self.cors_allow_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Pass an error to `on_internal_error`, e.g. the `JoinError` of a panicked handler task
+    /// with the summary taken from `Writer::request_summary` before spawning it.
+    pub fn report_internal_error(
+        &self,
+        summary: &RequestSummary,
+        kind: InternalErrorKind,
+        error: &dyn Error,
+    ) {
+        dev_print!(
+            "Internal error {:?} on {} {}: {}",
+            kind,
+            summary.method,
+            summary.path,
+            error
+        );
+        if let Some(hook) = &self.on_internal_error {
+            hook.report(summary, kind, error);
+        }
+    }
+
+    pub fn is_maintenance_allowed(&self, path: &str) -> bool {
+        self.maintenance_allowlist
+            .iter()
+            .any(|allowed| path.starts_with(allowed.as_str()))
+    }
 }
 
 impl Server {
@@ -129,23 +1103,46 @@ impl Server {
             options: Options::new(),
         })
     }
+    /// Out of file descriptors, idle keep-alive connections are closed and accepting pauses
+    /// with a growing backoff until a descriptor is free again.
     #[cfg(not(feature = "tokio_rustls"))]
     pub async fn accept(&mut self) -> Result<(TcpStream, Options), Box<dyn Error>> {
-        use std::time::Duration;
+        let mut backoff = Duration::from_millis(10);
+        let (stream, addr, decision) = loop {
+            match self.listener.accept().await {
+                Ok((stream, addr)) => {
+                    let decision = match &self.options.accept_filter {
+                        Some(filter) => filter.check(&addr),
+                        None => AcceptDecision::Allow,
+                    };
+                    if decision == AcceptDecision::Deny {
+                        dev_print!("Denied connection from {}", addr);
+                        continue;
+                    }
+                    break (stream, addr, decision);
+                }
+                Err(e) if is_fd_exhausted(&e) => {
+                    dev_print!("Accept paused for {:?}: {:?}", backoff, e);
+                    self.options.idle_reaper.notify_waiters();
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_secs(1));
+                }
+                Err(e) => {
+                    if is_connection_error(&e) {
+                        return Err(e.into());
+                    }
+                    dev_print!("Accept Error: {:?}", e);
 
-        let (stream, addr) = match self.listener.accept().await {
-            Ok(data) => data,
-            Err(e) => {
-                if is_connection_error(&e) {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
                     return Err(e.into());
                 }
-                dev_print!("Accept Error: {:?}", e);
-
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                return Err(e.into());
             }
         };
         self.options.current_client_addr = Some(addr);
+        self.options.accept_delay = match decision {
+            AcceptDecision::Delay(delay) => Some(delay),
+            _ => None,
+        };
         Ok((stream, self.options.clone()))
     }
     #[cfg(not(feature = "tokio_rustls"))]
@@ -153,7 +1150,32 @@ impl Server {
         stream: TcpStream,
         options: Options,
     ) -> Result<(Request<Body>, Response<Writer>), Box<dyn Error>> {
-        Ok(stream.parse_request(&options).await?)
+        if let Some(delay) = options.accept_delay {
+            tokio::time::sleep(delay).await;
+        }
+        stream.parse_request(&options).await
+    }
+    /// Wait up to `keep_alive_timeout_miliseconds` for the next request on a kept-alive stream,
+    /// `None` when the client closed or stayed idle.
+    #[cfg(not(feature = "tokio_rustls"))]
+    pub async fn parse_next_request(
+        stream: TcpStream,
+        options: Options,
+    ) -> Result<Option<(Request<Body>, Response<Writer>)>, Box<dyn Error>> {
+        if options.pipelined.is_empty() {
+            let idle = Duration::from_millis(options.keep_alive_timeout_miliseconds);
+            let mut probe = [0; 1];
+            let reaper = options.idle_reaper.clone();
+            tokio::select! {
+                peeked = tokio::time::timeout(idle, stream.peek(&mut probe)) => match peeked {
+                    Ok(Ok(0)) | Err(_) => return Ok(None),
+                    Ok(Err(e)) => return Err(e.into()),
+                    Ok(Ok(_)) => {}
+                },
+                _ = reaper.notified() => return Ok(None),
+            }
+        }
+        Ok(Some(stream.parse_request(&options).await?))
     }
     #[cfg(feature = "tokio_rustls")]
     pub async fn parse_request(
@@ -161,18 +1183,55 @@ impl Server {
         options: Options,
     ) -> Result<(Request<Body>, Response<Writer>), Box<dyn Error>> {
         let (stream, _connect) = stream.into_inner();
-        Ok(stream.parse_request(&options).await?)
+        stream.parse_request(&options).await
     }
     pub fn set_no_delay(&mut self, no_delay: bool) {
         self.options.no_delay = no_delay;
     }
+    pub fn set_maintenance(&mut self, maintenance: bool, retry_after: Option<Duration>) {
+        self.options.maintenance = maintenance;
+        self.options.maintenance_retry_after = retry_after;
+    }
+    pub fn start_draining(&mut self) {
+        self.options.draining = true;
+    }
+    pub fn set_priority_lanes(&mut self, priority_lanes: PriorityLanes) {
+        self.options.priority_lanes = Some(priority_lanes);
+    }
 }
 
 pub struct Body {
-    pub bytes: Vec<u8>,
-    pub body: String,
+    /// Shares the buffer the request was read into.
+    pub bytes: Bytes,
     pub len: usize,
     pub ip: Option<SocketAddr>,
+    pub storage: Option<BodyStorage>,
+    /// Trailer fields of a chunked request body.
+    pub trailers: HeaderMap,
+    /// Body not read yet with `stream_request_body`.
+    pub pending: Option<PendingBody>,
+}
+
+impl Body {
+    /// `bytes` as text without a copy, an error for a body that is not UTF-8.
+    pub fn body_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.bytes)
+    }
+
+    fn set_storage(&mut self, storage: Option<BodyStorage>) {
+        match storage {
+            Some(BodyStorage::Memory(bytes)) => {
+                self.len = bytes.len();
+                self.bytes = bytes.into();
+            }
+            Some(BodyStorage::File(file)) => {
+                self.len = file.len();
+                self.storage = Some(BodyStorage::File(file));
+            }
+            Some(storage) => self.storage = Some(storage),
+            None => {}
+        }
+    }
 }
 
 pub struct Writer {
@@ -181,8 +1240,34 @@ pub struct Writer {
     pub bytes: Vec<u8>,
     pub use_file: bool,
     pub options: Options,
+    pub lane_permit: Option<LanePermit>,
+    pub request_method: Method,
+    pub request_headers: HeaderMap,
+    request_path: String,
+    started: Instant,
+    /// The request body is still on the socket, so the connection can not be reused.
+    unread_body: bool,
+    /// `responser` failed, part of a response may be on the stream.
+    send_failed: bool,
+    /// `write_rate_limit` of the request, or `Writer::set_write_rate`.
+    write_throttle: Option<Throttle>,
+    /// Mount of `Writer::response_static`, `root_path` when `None`.
+    mount: Option<StaticMount>,
+    source: Option<SourceBody>,
+    memory_lease: MemoryLease,
+    deadline: Option<Instant>,
 }
 
+/// EMFILE or ENFILE, the process or the system ran out of file descriptors.
+#[cfg(not(feature = "tokio_rustls"))]
+fn is_fd_exhausted(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    return matches!(e.raw_os_error(), Some(23) | Some(24));
+    #[cfg(not(unix))]
+    return false;
+}
+
+#[cfg(not(feature = "tokio_rustls"))]
 fn is_connection_error(e: &io::Error) -> bool {
     matches!(
         e.kind(),
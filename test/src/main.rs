@@ -42,29 +42,24 @@ async fn www_service(
     println!("ip: {:?}", request.body().ip);
     println!(
         "request: {:?}\n",
-        String::from_utf8_lossy(request.body().bytes.as_slice())
+        String::from_utf8_lossy(&request.body().bytes)
     );
-    if request.headers().get("host") != None && request.uri().path() != "/" {
+    if request.headers().get("host").is_some() && request.uri().path() != "/" {
         let path = request.uri().path()[1..].to_owned();
 
         if path.contains(".") {
             let path: String = urlencoding::decode(&path)?.into();
             let dir = std::env::current_dir()?;
-            let path = dir.join(&path);
 
-            let exist = try_exists(&path).await?;
+            let exist = try_exists(dir.join(&path)).await?;
 
             if exist {
                 response.body_mut().response_file(path)?;
             } else {
-                let dir = std::env::current_dir()?;
-                let path = dir.join("app/index.html");
-                response.body_mut().response_file(path)?;
+                response.body_mut().response_file("app/index.html")?;
             }
         } else {
-            let dir = std::env::current_dir()?;
-            let path = dir.join("app/index.html");
-            response.body_mut().response_file(path)?;
+            response.body_mut().response_file("app/index.html")?;
         }
     }
 